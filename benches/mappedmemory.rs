@@ -0,0 +1,41 @@
+//! Lookup cost of `MappedMemory` depending on the number of mounts.
+//!
+//! Run with `cargo bench`. For every mount count, the benchmark mounts a `NullMemory` for the whole address space and that many small memories spread over it, then reads from addresses in all mounts.
+extern crate osciemu;
+
+use osciemu::memory::{MappedMemory, Memory, NullMemory, SliceMemory};
+use std::time::Instant;
+
+const READS: usize = 10_000_000;
+const MOUNT_DISTANCE: usize = 0x10_0000;
+
+fn mapped_memory(mounts: usize) -> MappedMemory {
+    let mut memory = MappedMemory::new();
+    memory.mount(0, Box::new(NullMemory::new())).unwrap();
+    for i in 0..mounts {
+        memory.mount(i * MOUNT_DISTANCE, Box::new(SliceMemory::new(16))).unwrap();
+    }
+    memory
+}
+
+fn main() {
+    println!("{:>8} {:>12}", "mounts", "ns/read");
+    for &mounts in &[1, 4, 16, 64, 256, 1024] {
+        let memory = mapped_memory(mounts);
+        let addresses: Vec<usize> = (0..1024)
+            .map(|i| (i % mounts) * MOUNT_DISTANCE + i % 32)
+            .collect();
+        let start = Instant::now();
+        let mut sum = 0i32;
+        for i in 0..READS {
+            sum = sum.wrapping_add(memory.get(addresses[i % addresses.len()]));
+        }
+        let elapsed = start.elapsed();
+        assert_eq!(sum, 0);
+        println!(
+            "{:>8} {:>12.2}",
+            mounts,
+            elapsed.as_secs_f64() * 1e9 / READS as f64
+        );
+    }
+}
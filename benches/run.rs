@@ -0,0 +1,85 @@
+//! Speed of `Emulator::run` compared to calling `Emulator::step` for every cycle.
+//!
+//! Run with `cargo bench`. The BIOS counts register 0 down in a loop of three instructions, one of which writes to RAM, so the cached runs also pay for the invalidation checks of guest writes. Stepping checks the flags and ticks the devices after every cycle, while `run()` only does so around writes to devices. Every mode is measured five times and the fastest run is reported.
+extern crate osciemu;
+
+use osciemu::assembler;
+use osciemu::emulator::{Emulator, Ram, RunLimits, StopReason};
+use osciemu::memory::{address, Memory, SliceMemory};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: i32 = 2_000_000;
+const REPETITIONS: usize = 5;
+
+const BIOS: &str = "
+    .addr 0x40000000
+    loop: register0 one register0 done
+    counter minus_one counter $+instruction_size
+    0 0 register1 loop
+    done: one 0 flags0 0
+    one: .dw 1
+    minus_one: .dw (-1)
+    .addr 0x100
+    counter:
+";
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Step,
+    RunUncached,
+    Run,
+}
+
+fn emulator(mode: Mode) -> Emulator {
+    let bios = assembler::load(&mut BIOS.as_bytes()).unwrap();
+    let mut emulator = Emulator::with_ram(bios, Box::new(SliceMemory::new(0)), Ram::Fixed(0x200));
+    if let Mode::RunUncached = mode {
+        emulator.disable_instruction_cache();
+    }
+    emulator
+        .memory
+        .set(address::REGISTERS_START_ADDRESS, ITERATIONS);
+    emulator
+}
+
+/// Runs the BIOS to completion and returns the time per cycle.
+fn measure(mode: Mode) -> (usize, Duration) {
+    let mut emulator = emulator(mode);
+    let start = Instant::now();
+    match mode {
+        Mode::Step => while !emulator.is_halted() {
+            emulator.step();
+        },
+        Mode::RunUncached | Mode::Run => {
+            let outcome = emulator.run(RunLimits::default());
+            assert_eq!(outcome.reason, StopReason::Halted);
+        }
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(emulator.memory.get(0x100), ITERATIONS - 1);
+    (emulator.cycles, elapsed / emulator.cycles as u32)
+}
+
+fn main() {
+    println!("{:>16} {:>12} {:>10} {:>8}", "mode", "cycles", "ns/cycle", "speedup");
+    let mut baseline = None;
+    for &(mode, name) in &[
+        (Mode::Step, "step"),
+        (Mode::RunUncached, "run (no cache)"),
+        (Mode::Run, "run"),
+    ] {
+        let (cycles, per_cycle) = (0..REPETITIONS)
+            .map(|_| measure(mode))
+            .min_by_key(|&(_, per_cycle)| per_cycle)
+            .unwrap();
+        let ns = per_cycle.as_secs_f64() * 1e9;
+        let baseline = *baseline.get_or_insert(ns);
+        println!(
+            "{:>16} {:>12} {:>10.2} {:>7.2}x",
+            name,
+            cycles,
+            ns,
+            baseline / ns
+        );
+    }
+}
@@ -0,0 +1,40 @@
+; Raises a software interrupt and halts after the handler has returned.
+.addr 0x40000000
+
+start:
+; Put the stack at the end of the memory image
+stack_top zero stack_pointer $+instruction_size
+; Install the interrupt handler
+handler_address zero ivt0 $+instruction_size
+; Enable interrupts and raise line 0
+enable zero flags0 $+instruction_size
+
+; The interrupt is dispatched before this instruction is executed
+resume:
+; register1 = register0
+register0 zero register1 $+instruction_size
+; Set halt bit
+one zero flags0 0
+
+handler:
+; register0 = 42
+answer zero register0 $+instruction_size
+; Clear the pending bit and return from the interrupt
+return zero flags0 $+instruction_size
+
+zero:
+.dw 0
+one:
+.dw 1
+answer:
+.dw 42
+stack_top:
+.dw 16
+handler_address:
+.dw handler
+; IE | P0
+enable:
+.dw 4 + 256
+; IR
+return:
+.dw 8
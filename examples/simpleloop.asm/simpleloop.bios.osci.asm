@@ -0,0 +1,22 @@
+; Counts register 0 up to 10 and halts.
+; This is the assembly version of simpleloop.bios.hex.
+.addr 0x40000000
+
+; set register0 to 0, jump to loop
+start:
+0 0 register0 loop
+
+data:
+.dw 0 (-1) 10 1
+
+; increase register0 by 1
+loop:
+register0 data+1 register0 0
+; check if the counter is 10, and if so jump to end
+data+2 register0 register1 end
+; unconditional jump to loop
+0 0 register1 loop
+
+; set halt bit
+end:
+data+3 data flags0 0
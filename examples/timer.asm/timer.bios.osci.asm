@@ -0,0 +1,53 @@
+; Counts in register1 until the periodic timer has fired three times.
+; Since the timer counts emulated cycles, the final value of register1 is exact.
+.addr 0x40000000
+
+start:
+stack_top zero stack_pointer $+instruction_size
+handler_address zero ivt0 $+instruction_size
+; Fire every 10 cycles
+ten zero timer_counter $+instruction_size
+ten zero timer_reload $+instruction_size
+timer_mode zero timer_control $+instruction_size
+; Enable interrupts
+enable zero flags0 $+instruction_size
+
+loop:
+; register1 += 1
+register1 minus_one register1 $+instruction_size
+; Stop once register0 reached 3
+three register0 register2 done
+zero zero register2 loop
+
+done:
+one zero flags0 0
+
+handler:
+; register0 += 1
+register0 minus_one register0 $+instruction_size
+; Clear the pending bit and return from the interrupt
+return zero flags0 $+instruction_size
+
+zero:
+.dw 0
+one:
+.dw 1
+minus_one:
+.dw -1
+three:
+.dw 3
+ten:
+.dw 10
+stack_top:
+.dw 16
+handler_address:
+.dw handler
+; enable | periodic | interrupt
+timer_mode:
+.dw 1 + 2 + 4
+; IE
+enable:
+.dw 4
+; IR
+return:
+.dw 8
@@ -0,0 +1,467 @@
+//! Assembler for osci assembly.
+//!
+//! This is a native port of the JavaScript assembler in `tools/osciasm` and accepts the same syntax. Since osci’s memory is word-addressed, all addresses and sizes are counted in words rather than bytes.
+//!
+//! # Syntax
+//! Every line contains a label, an assembler directive or an instruction. Comments start with `;` and extend until the end of the line.
+//!
+//! - `name:` defines a label with the address of the next instruction or directive.
+//! - `op_a op_b target jmp` assembles to one instruction. Each operand is an expression. Like in the JavaScript assembler, a direct `jmp` is rounded up to a multiple of `instruction_size`. An indirect (negative) `jmp` is the address of a pointer and is emitted as is.
+//! - `.addr expr` sets the address that is used for following labels and `$`. It does not emit any words.
+//! - `.dw expr ...` emits one word per expression.
+//! - `.db expr ...` emits one word per byte. Each value is truncated to 8 bits and strings are expanded to their bytes.
+//!
+//! `.dw` and `.db` are padded with zeros to a multiple of `instruction_size`.
+//!
+//! Expressions can use `+`, `-`, `*`, `/`, parentheses and unary minus. Numbers are decimal, hexadecimal (`0x`), binary (`0b`) or octal (leading `0`). `$` is the address of the current instruction. The following symbols are predefined:
+//!
+//! - `instruction_size`: Size of an instruction in words
+//! - `register0` to `register3`: The registers
+//! - `stack_pointer`: The stack pointer register
+//! - `ivt0`: IVT entry 0
+//! - `ivt_return`: Where the return address of an interrupt can be found. Interrupt dispatch pushes the return address on the stack rather than into a fixed word, so this is an alias of `stack_pointer` and `-ivt_return` reads the return address.
+//! - `flags0`: Flags word 0
+//! - `uart_data`, `uart_status`: The UART’s data and status words
+//! - `timer_counter`, `timer_reload`, `timer_control`: The timer’s words
+//!
+//! Just like the JavaScript assembler, a `-` after a complete operand is parsed as a subtraction, even when separated by whitespace. Wrap negative (indirect) operands in parentheses when they don’t come first, e.g. `a (-ptr) register0 $+instruction_size`.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::assembler;
+//! let mut code = std::io::Cursor::new("
+//!     .addr 0x40000000
+//!     ; Calculate 0x10 - 0x3 and store it in register 0
+//!     a b register0 0
+//!     a: .dw 0x10
+//!     b: .dw 0x3
+//! ");
+//! let mem = assembler::load(&mut code).unwrap();
+//! assert_eq!(mem.get(0), 0x40000004);
+//! assert_eq!(mem.get(1), 0x40000008);
+//! assert_eq!(mem.get(4), 0x10);
+//! ```
+mod parser;
+
+use self::parser::{Expr, StatementKind};
+use loader::{LoadError, Result};
+use memory::{address, Memory, SliceMemory};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+pub use instruction::INSTRUCTION_SIZE;
+
+/// Maps a source line to the words it emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine {
+    /// 1-based line number.
+    pub line: usize,
+    /// Index of the first emitted word in `Program::words`.
+    pub offset: usize,
+    /// Number of emitted words (including padding).
+    pub size: usize,
+    /// Address of the first emitted word as set by `.addr`.
+    pub address: usize,
+    /// Whether the line is an instruction rather than a data directive.
+    pub instruction: bool,
+}
+
+/// Result of assembling a source file.
+pub struct Program {
+    /// The assembled words.
+    pub words: Vec<i32>,
+    /// All labels and their addresses.
+    pub symbols: BTreeMap<String, i32>,
+    /// All instructions and data directives in source order.
+    ///
+    /// `offset` is the index into `words` and therefore relative to wherever the program is mounted, while `address` is the location the source claims with `.addr`.
+    pub lines: Vec<SourceLine>,
+}
+
+impl Program {
+    /// Returns the line that emitted the word at `offset`.
+    pub fn line_at(&self, offset: usize) -> Option<&SourceLine> {
+        self.lines
+            .iter()
+            .find(|line| line.offset <= offset && offset < line.offset + line.size)
+    }
+
+    /// Returns the first line at or after `line` that emitted words.
+    pub fn line_from(&self, line: usize) -> Option<&SourceLine> {
+        self.lines.iter().find(|l| l.line >= line)
+    }
+
+    /// Returns the labels defined by the source by their address.
+    ///
+    /// Predefined symbols are not included. If several labels share an address, the alphabetically first one is used.
+    pub fn labels(&self) -> BTreeMap<usize, String> {
+        let predefined = predefined_symbols();
+        let mut labels = BTreeMap::new();
+        for (name, &value) in &self.symbols {
+            if value >= 0 && !predefined.contains_key(name) {
+                labels.entry(value as usize).or_insert_with(|| name.clone());
+            }
+        }
+        labels
+    }
+}
+
+/// Returns the symbols that are defined before assembly starts.
+pub fn predefined_symbols() -> BTreeMap<String, i32> {
+    let mut symbols = BTreeMap::new();
+    symbols.insert(String::from("instruction_size"), INSTRUCTION_SIZE as i32);
+    for i in 0..address::NUM_REGISTERS {
+        symbols.insert(
+            format!("register{}", i),
+            (address::REGISTERS_START_ADDRESS + i) as i32,
+        );
+    }
+    symbols.insert(
+        String::from("stack_pointer"),
+        address::STACK_POINTER_ADDRESS as i32,
+    );
+    symbols.insert(
+        String::from("ivt_return"),
+        address::STACK_POINTER_ADDRESS as i32,
+    );
+    for i in 0..address::NUM_IVT_ENTRIES {
+        symbols.insert(format!("ivt{}", i), (address::IVT_START_ADDRESS + i) as i32);
+    }
+    for i in 0..address::NUM_FLAGS {
+        symbols.insert(
+            format!("flags{}", i),
+            (address::FLAGS_START_ADDRESS + i) as i32,
+        );
+    }
+    symbols.insert(String::from("uart_data"), address::UART_DATA_ADDRESS as i32);
+    symbols.insert(
+        String::from("uart_status"),
+        address::UART_STATUS_ADDRESS as i32,
+    );
+    symbols.insert(
+        String::from("timer_counter"),
+        address::TIMER_COUNTER_ADDRESS as i32,
+    );
+    symbols.insert(
+        String::from("timer_reload"),
+        address::TIMER_RELOAD_ADDRESS as i32,
+    );
+    symbols.insert(
+        String::from("timer_control"),
+        address::TIMER_CONTROL_ADDRESS as i32,
+    );
+    symbols
+}
+
+/// Assembles osci assembly source code.
+pub fn assemble(source: &str) -> Result<Program> {
+    let tokens = parser::tokenize(source)?;
+    let statements = parser::parse(&tokens)?;
+    let mut symbols = predefined_symbols();
+
+    // First pass: Assign addresses to all labels.
+    let mut location: i64 = 0;
+    for statement in &statements {
+        match statement.kind {
+            StatementKind::Label(ref name) => {
+                if symbols.contains_key(name) {
+                    return error_at(statement.line, format!("Duplicate symbol {}", name));
+                }
+                symbols.insert(name.clone(), location as i32);
+            }
+            StatementKind::Directive(ref name, ref ops) if name == "addr" => {
+                location = evaluate_addr(ops, location, &symbols, statement.line)?;
+            }
+            ref kind => location += size_of(kind, statement.line)? as i64,
+        }
+    }
+
+    // Second pass: Emit words.
+    let mut words = Vec::new();
+    let mut lines = Vec::new();
+    let mut location: i64 = 0;
+    for statement in &statements {
+        let line = statement.line;
+        let start = words.len();
+        match statement.kind {
+            StatementKind::Label(_) => continue,
+            StatementKind::Directive(ref name, ref ops) => match name.as_str() {
+                "addr" => {
+                    location = evaluate_addr(ops, location, &symbols, line)?;
+                    continue;
+                }
+                "dw" => {
+                    for op in ops {
+                        for value in evaluate_values(op, location, &symbols, line)? {
+                            words.push(value as i32);
+                        }
+                    }
+                }
+                "db" => {
+                    for op in ops {
+                        for value in evaluate_values(op, location, &symbols, line)? {
+                            words.push((value & 0xFF) as i32);
+                        }
+                    }
+                }
+                _ => return error_at(line, format!("Unknown assembler directive .{}", name)),
+            },
+            StatementKind::Instruction(ref ops) => {
+                for (i, op) in ops.iter().enumerate() {
+                    let mut value = evaluate(op, location, &symbols, line)?;
+                    if i == 3 && value >= 0 {
+                        value = round_up(value as usize) as i64;
+                    }
+                    words.push(value as i32);
+                }
+            }
+        }
+        let size = round_up(words.len() - start);
+        words.resize(start + size, 0);
+        lines.push(SourceLine {
+            line,
+            offset: start,
+            size,
+            address: location as usize,
+            instruction: matches!(statement.kind, StatementKind::Instruction(_)),
+        });
+        location += size as i64;
+    }
+
+    Ok(Program {
+        words,
+        symbols,
+        lines,
+    })
+}
+
+/// Assembles osci assembly source code read from a stream into a memory.
+pub fn load<U: Read>(f: &mut U) -> Result<Box<dyn Memory>> {
+    let mut source = String::new();
+    f.read_to_string(&mut source)?;
+    let program = assemble(&source)?;
+    Ok(Box::new(SliceMemory::from_slice(
+        program.words.into_boxed_slice(),
+    )))
+}
+
+fn error_at<T>(line: usize, msg: String) -> Result<T> {
+    Err(LoadError::from_message(format!("line {}: {}", line, msg)))
+}
+
+fn round_up(size: usize) -> usize {
+    size.div_ceil(INSTRUCTION_SIZE) * INSTRUCTION_SIZE
+}
+
+fn size_of(kind: &StatementKind, line: usize) -> Result<usize> {
+    match *kind {
+        StatementKind::Label(_) => Ok(0),
+        StatementKind::Instruction(_) => Ok(INSTRUCTION_SIZE),
+        StatementKind::Directive(ref name, ref ops) => match name.as_str() {
+            "addr" => Ok(0),
+            "dw" | "db" => Ok(round_up(ops.iter().map(num_values).sum())),
+            _ => error_at(line, format!("Unknown assembler directive .{}", name)),
+        },
+    }
+}
+
+fn num_values(expr: &Expr) -> usize {
+    match *expr {
+        Expr::Str(ref s) => s.len(),
+        _ => 1,
+    }
+}
+
+fn evaluate_addr(
+    ops: &[Expr],
+    location: i64,
+    symbols: &BTreeMap<String, i32>,
+    line: usize,
+) -> Result<i64> {
+    if ops.len() != 1 {
+        return error_at(line, String::from(".addr takes exactly one argument"));
+    }
+    evaluate(&ops[0], location, symbols, line)
+}
+
+/// Evaluates an expression that may be a string literal, which yields one value per byte.
+fn evaluate_values(
+    expr: &Expr,
+    location: i64,
+    symbols: &BTreeMap<String, i32>,
+    line: usize,
+) -> Result<Vec<i64>> {
+    match *expr {
+        Expr::Str(ref s) => Ok(s.bytes().map(|b| b as i64).collect()),
+        _ => Ok(vec![evaluate(expr, location, symbols, line)?]),
+    }
+}
+
+fn evaluate(
+    expr: &Expr,
+    location: i64,
+    symbols: &BTreeMap<String, i32>,
+    line: usize,
+) -> Result<i64> {
+    let eval = |expr: &Expr| evaluate(expr, location, symbols, line);
+    match *expr {
+        Expr::Number(n) => Ok(n),
+        Expr::Location => Ok(location),
+        Expr::Symbol(ref name) => match symbols.get(name) {
+            // Addresses are stored as `i32`, but the upper half of the address space
+            // must not turn negative in arithmetic.
+            Some(&value) => Ok(value as u32 as i64),
+            None => error_at(line, format!("Unknown symbol {}", name)),
+        },
+        Expr::Str(_) => error_at(
+            line,
+            String::from("String literals are only allowed in .db and .dw"),
+        ),
+        Expr::Neg(ref a) => Ok(eval(a)?.wrapping_neg()),
+        Expr::Add(ref a, ref b) => Ok(eval(a)?.wrapping_add(eval(b)?)),
+        Expr::Sub(ref a, ref b) => Ok(eval(a)?.wrapping_sub(eval(b)?)),
+        Expr::Mul(ref a, ref b) => Ok(eval(a)?.wrapping_mul(eval(b)?)),
+        Expr::Div(ref a, ref b) => {
+            let (a, b) = (eval(a)?, eval(b)?);
+            if b == 0 {
+                return error_at(line, String::from("Division by zero"));
+            }
+            // Round towards negative infinity like `Math.floor()` in the JavaScript assembler.
+            let q = a / b;
+            Ok(if a % b != 0 && (a < 0) != (b < 0) {
+                q - 1
+            } else {
+                q
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::address;
+
+    #[test]
+    fn instructions() {
+        let program = super::assemble("1 2 3 4\n4 3 2 1\n0 0 0 (-1)").unwrap();
+        assert_eq!(program.words, vec![1, 2, 3, 4, 4, 3, 2, 4, 0, 0, 0, -1]);
+    }
+
+    #[test]
+    fn labels() {
+        let program = super::assemble(
+            "
+            .addr 0x100
+            start: 0 0 0 end
+            end:
+            start end $ $+instruction_size
+            ",
+        )
+        .unwrap();
+        assert_eq!(program.symbols["start"], 0x100);
+        assert_eq!(program.symbols["end"], 0x104);
+        assert_eq!(
+            &program.words[..],
+            &[0, 0, 0, 0x104, 0x100, 0x104, 0x104, 0x108]
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let program = super::assemble(
+            "
+            .addr 0x100
+            start: 0 0 0 end
+            ; comment
+            end: .dw 1 2 3 4 5
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            program.lines,
+            vec![
+                super::SourceLine {
+                    line: 3,
+                    offset: 0,
+                    size: 4,
+                    address: 0x100,
+                    instruction: true,
+                },
+                super::SourceLine {
+                    line: 5,
+                    offset: 4,
+                    size: 8,
+                    address: 0x104,
+                    instruction: false,
+                },
+            ]
+        );
+        assert_eq!(program.line_at(9).map(|l| l.line), Some(5));
+        assert_eq!(program.line_at(12), None);
+        assert_eq!(program.line_from(4).map(|l| l.offset), Some(4));
+        assert_eq!(program.line_from(6), None);
+
+        let labels: Vec<(usize, String)> = program.labels().into_iter().collect();
+        assert_eq!(
+            labels,
+            vec![(0x100, String::from("start")), (0x104, String::from("end"))]
+        );
+    }
+
+    #[test]
+    fn predefined_symbols() {
+        let program = super::assemble(".dw register0 register3 flags0 ivt0 ivt_return").unwrap();
+        assert_eq!(
+            program.words,
+            vec![
+                address::REGISTERS_START_ADDRESS as i32,
+                address::REGISTERS_START_ADDRESS as i32 + 3,
+                address::FLAGS_START_ADDRESS as i32,
+                address::IVT_START_ADDRESS as i32,
+                address::STACK_POINTER_ADDRESS as i32,
+                0,
+                0,
+                0,
+            ]
+        );
+    }
+
+    #[test]
+    fn data_directives() {
+        let program = super::assemble(".dw 1 (-1) 0xFFFFFFFF\n.db \"hi\" 0x1FF").unwrap();
+        assert_eq!(program.words, vec![1, -1, -1, 0, 0x68, 0x69, 0xFF, 0]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let program = super::assemble(".dw 2+3*4 (2+3)*4 (-7/2) (7/-2) (-5) 1 - 2").unwrap();
+        assert_eq!(program.words, vec![14, 20, -4, -4, -5, -1, 0, 0]);
+        // Overflows wrap around instead of panicking.
+        let program = super::assemble(".dw (-(0x100000000*0x80000000))").unwrap();
+        assert_eq!(program.words, vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn indirect_operands() {
+        let program = super::assemble("-register0 (-register1) register2 0").unwrap();
+        assert_eq!(
+            program.words,
+            vec![
+                -(address::REGISTERS_START_ADDRESS as i32),
+                -(address::REGISTERS_START_ADDRESS as i32 + 1),
+                address::REGISTERS_START_ADDRESS as i32 + 2,
+                0,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(super::assemble("unknown 0 0 0").is_err());
+        assert!(super::assemble("a: a: 0 0 0 0").is_err());
+        assert!(super::assemble(".foo 1").is_err());
+        assert!(super::assemble(".addr 1 2").is_err());
+        assert!(super::assemble(".dw 1/0").is_err());
+    }
+}
@@ -0,0 +1,368 @@
+//! Tokenizer and parser for osci assembly.
+use loader::{LoadError, Result};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A single lexical token of the assembly source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Label(String),
+    Directive(String),
+    Symbol(String),
+    Number(i64),
+    Str(String),
+    Dollar,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LParen,
+    RParen,
+    Newline,
+}
+
+/// An arithmetic expression as it appears in an operand.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    Str(String),
+    Location,
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+}
+
+/// Kind of a parsed statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    /// `name:`
+    Label(String),
+    /// `.name op op ...`
+    Directive(String, Vec<Expr>),
+    /// `op_a op_b target jmp`
+    Instruction([Expr; 4]),
+}
+
+/// A parsed statement together with the source line it started on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    /// 1-based line number.
+    pub line: usize,
+    pub kind: StatementKind,
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn error_at<T>(line: usize, msg: String) -> Result<T> {
+    Err(LoadError::from_message(format!("line {}: {}", line, msg)))
+}
+
+/// Splits the source into tokens, each paired with its line number.
+pub fn tokenize(source: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' | '\r' => {
+                chars.next();
+            }
+            '\n' => {
+                chars.next();
+                tokens.push((line, Token::Newline));
+                line += 1;
+            }
+            ';' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '$' | '+' | '-' | '*' | '/' | '(' | ')' => {
+                chars.next();
+                tokens.push((
+                    line,
+                    match c {
+                        '$' => Token::Dollar,
+                        '+' => Token::Plus,
+                        '-' => Token::Minus,
+                        '*' => Token::Star,
+                        '/' => Token::Slash,
+                        '(' => Token::LParen,
+                        _ => Token::RParen,
+                    },
+                ));
+            }
+            '"' => {
+                chars.next();
+                tokens.push((line, Token::Str(read_string(&mut chars, line)?)));
+            }
+            '.' => {
+                chars.next();
+                let name = read_while(&mut chars, is_symbol_char);
+                if name.is_empty() {
+                    return error_at(line, String::from("Expected directive name after '.'"));
+                }
+                tokens.push((line, Token::Directive(name)));
+            }
+            c if c.is_ascii_digit() => {
+                let literal = read_while(&mut chars, |c| c.is_ascii_hexdigit() || c == 'x');
+                tokens.push((line, Token::Number(parse_number(&literal, line)?)));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let name = read_while(&mut chars, is_symbol_char);
+                if chars.peek() == Some(&':') {
+                    chars.next();
+                    tokens.push((line, Token::Label(name)));
+                } else {
+                    tokens.push((line, Token::Symbol(name)));
+                }
+            }
+            c => return error_at(line, format!("Unexpected character '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn read_while<F: Fn(char) -> bool>(chars: &mut Peekable<Chars>, f: F) -> String {
+    let mut s = String::new();
+    while let Some(&c) = chars.peek() {
+        if !f(c) {
+            break;
+        }
+        s.push(c);
+        chars.next();
+    }
+    s
+}
+
+fn read_string(chars: &mut Peekable<Chars>, line: usize) -> Result<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            // A backslash takes the following character verbatim.
+            Some('\\') => match chars.next() {
+                Some(c) => s.push(c),
+                None => break,
+            },
+            Some('\n') | None => break,
+            Some(c) => s.push(c),
+        }
+    }
+    error_at(line, String::from("Unterminated string literal"))
+}
+
+/// Parses a number literal.
+///
+/// Like in the JavaScript assembler, a `0x` prefix denotes hexadecimal, `0b` binary and a leading `0` octal numbers. Everything else is decimal.
+fn parse_number(literal: &str, line: usize) -> Result<i64> {
+    let (digits, radix) = if let Some(digits) = literal.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = literal.strip_prefix("0b") {
+        (digits, 2)
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        (&literal[1..], 8)
+    } else {
+        (literal, 10)
+    };
+    i64::from_str_radix(digits, radix)
+        .or_else(|_| error_at(line, format!("Invalid number literal '{}'", literal)))
+}
+
+/// Parses a token stream into a list of statements.
+pub fn parse(tokens: &[(usize, Token)]) -> Result<Vec<Statement>> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let mut statements = Vec::new();
+    while let Some(line) = parser.line() {
+        let kind = match *parser.peek().unwrap() {
+            Token::Newline => {
+                parser.pos += 1;
+                continue;
+            }
+            Token::Label(ref name) => {
+                parser.pos += 1;
+                StatementKind::Label(name.clone())
+            }
+            Token::Directive(ref name) => {
+                parser.pos += 1;
+                let mut ops = Vec::new();
+                while parser.peek().is_some_and(|t| *t != Token::Newline) {
+                    ops.push(parser.expression()?);
+                }
+                StatementKind::Directive(name.clone(), ops)
+            }
+            _ => StatementKind::Instruction([
+                parser.expression()?,
+                parser.expression()?,
+                parser.expression()?,
+                parser.expression()?,
+            ]),
+        };
+        statements.push(Statement { line, kind });
+    }
+    Ok(statements)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn line(&self) -> Option<usize> {
+        self.tokens.get(self.pos).map(|&(line, _)| line)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn last_line(&self) -> usize {
+        self.tokens.last().map_or(1, |&(line, _)| line)
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<Expr> {
+        let mut expr = self.term()?;
+        loop {
+            match self.peek() {
+                Some(&Token::Plus) => {
+                    self.pos += 1;
+                    expr = Expr::Add(Box::new(expr), Box::new(self.term()?));
+                }
+                Some(&Token::Minus) => {
+                    self.pos += 1;
+                    expr = Expr::Sub(Box::new(expr), Box::new(self.term()?));
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    // term := factor (('*' | '/') factor)*
+    fn term(&mut self) -> Result<Expr> {
+        let mut expr = self.factor()?;
+        loop {
+            match self.peek() {
+                Some(&Token::Star) => {
+                    self.pos += 1;
+                    expr = Expr::Mul(Box::new(expr), Box::new(self.factor()?));
+                }
+                Some(&Token::Slash) => {
+                    self.pos += 1;
+                    expr = Expr::Div(Box::new(expr), Box::new(self.factor()?));
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    // factor := '(' expression ')' | '-' factor | '$' | number | symbol | string
+    fn factor(&mut self) -> Result<Expr> {
+        let line = self.line().unwrap_or_else(|| self.last_line());
+        match self.next() {
+            Some(&Token::LParen) => {
+                let expr = self.expression()?;
+                match self.next() {
+                    Some(&Token::RParen) => Ok(expr),
+                    _ => error_at(line, String::from("Missing closing parenthesis")),
+                }
+            }
+            Some(&Token::Minus) => Ok(Expr::Neg(Box::new(self.factor()?))),
+            Some(&Token::Dollar) => Ok(Expr::Location),
+            Some(&Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Symbol(name)) => Ok(Expr::Symbol(name.clone())),
+            Some(Token::Str(s)) => Ok(Expr::Str(s.clone())),
+            Some(&Token::Newline) | None => {
+                error_at(line, String::from("Unexpected end of expression"))
+            }
+            Some(token) => error_at(line, format!("Unexpected token {:?}", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expr, StatementKind, Token};
+
+    fn parse(source: &str) -> Vec<StatementKind> {
+        let tokens = super::tokenize(source).unwrap();
+        super::parse(&tokens)
+            .unwrap()
+            .into_iter()
+            .map(|statement| statement.kind)
+            .collect()
+    }
+
+    #[test]
+    fn numbers() {
+        let tokens = super::tokenize("10 0x10 0b10 010").unwrap();
+        let numbers: Vec<Token> = tokens.into_iter().map(|(_, token)| token).collect();
+        assert_eq!(
+            numbers,
+            vec![
+                Token::Number(10),
+                Token::Number(16),
+                Token::Number(2),
+                Token::Number(8),
+            ]
+        );
+        assert!(super::tokenize("0xZ").is_err());
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(parse("; This is a comment\n  ; another one"), vec![]);
+    }
+
+    #[test]
+    fn labels_and_instructions() {
+        let statements = parse("label: 1 2 3 4\n4 3 2 1");
+        assert_eq!(statements.len(), 3);
+        assert_eq!(statements[0], StatementKind::Label(String::from("label")));
+        assert_eq!(
+            statements[1],
+            StatementKind::Instruction([
+                Expr::Number(1),
+                Expr::Number(2),
+                Expr::Number(3),
+                Expr::Number(4),
+            ])
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        let statements = parse(".dw 1 + 2 * 3");
+        assert_eq!(
+            statements[0],
+            StatementKind::Directive(
+                String::from("dw"),
+                vec![Expr::Add(
+                    Box::new(Expr::Number(1)),
+                    Box::new(Expr::Mul(
+                        Box::new(Expr::Number(2)),
+                        Box::new(Expr::Number(3)),
+                    )),
+                ),]
+            )
+        );
+    }
+
+    #[test]
+    fn unterminated_instruction() {
+        let tokens = super::tokenize("1 2 3\n4").unwrap();
+        assert!(super::parse(&tokens).is_err());
+    }
+}
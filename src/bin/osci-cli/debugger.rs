@@ -0,0 +1,540 @@
+//! Command-line debugger.
+//!
+//! The debugger reads one command per line. Addresses can be given in hex or by their symbolic name (`r0`, `sp`, `flags0`, ...) and values in hex. Lines starting with `#` are ignored, an empty line repeats the previous command.
+//!
+//! The emulator journals the last `JOURNAL_CAPACITY` changes so `back` and `reverse` can undo cycles.
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+
+use osciemu::disassembler;
+use osciemu::emulator::snapshot::SnapshotError;
+use osciemu::emulator::watchpoint::{WatchKind, WatchpointId};
+use osciemu::emulator::{Emulator, RunLimits, RunOutcome, StopReason};
+use osciemu::memory::{address, Memory};
+
+/// Number of journal entries kept for `back` and `reverse`.
+const JOURNAL_CAPACITY: usize = 100_000;
+
+const HELP: &str = "\
+step [N]                 Execute N cycles (default 1)
+continue                 Run until a stop condition occurs
+back [N]                 Undo N cycles (default 1)
+reverse ADDR[-END]       Undo cycles up to the last write to ADDR (END is exclusive)
+break ADDR               Add a breakpoint
+delete ADDR              Remove a breakpoint
+watch [read|write|change] ADDR[-END]
+                         Add a watchpoint (default write, END is exclusive)
+unwatch ID               Remove a watchpoint
+info                     List breakpoints and watchpoints
+print ADDR[-END]         Print memory (END is exclusive)
+regs                     Print ip, registers, stack pointer and flags
+set ADDR VALUE           Write VALUE to memory
+disasm [N]               Disassemble N instructions around ip (default 3)
+mounts                   Show the memory layout
+save FILE                Save a snapshot (resume with --resume FILE)
+rerun                    Restart the program, keeping breakpoints and watchpoints
+help                     Show this help
+quit                     Exit the debugger";
+
+/// Whether the debugger should keep reading commands.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+pub struct Debugger {
+    factory: Box<dyn Fn() -> Emulator>,
+    emulator: Emulator,
+    limits: RunLimits,
+    last_command: String,
+}
+
+impl Debugger {
+    /// Creates a debugger. `factory` is used to create the emulator initially and on `rerun`.
+    pub fn new(factory: Box<dyn Fn() -> Emulator>, limits: RunLimits) -> Debugger {
+        let mut emulator = factory();
+        emulator.enable_journal(JOURNAL_CAPACITY);
+        Debugger {
+            factory,
+            emulator,
+            limits,
+            last_command: String::new(),
+        }
+    }
+
+    /// Reads and executes commands until `quit` or the end of `input`.
+    ///
+    /// With `echo`, every command is written to `out` before its output, which makes the output of a script readable. Otherwise a prompt is shown.
+    pub fn repl<R: BufRead>(
+        &mut self,
+        input: R,
+        out: &mut dyn Write,
+        echo: bool,
+    ) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            if !echo {
+                write!(out, "(osci) ")?;
+                out.flush()?;
+            }
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            if echo {
+                writeln!(out, "(osci) {}", line)?;
+            }
+            if self.execute(&line, out)? == Flow::Quit {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes a single command.
+    pub fn execute(&mut self, line: &str, out: &mut dyn Write) -> io::Result<Flow> {
+        let line = line.trim();
+        if line.starts_with('#') {
+            return Ok(Flow::Continue);
+        }
+        let line = if line.is_empty() {
+            self.last_command.clone()
+        } else {
+            self.last_command = String::from(line);
+            String::from(line)
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        match self.command(&args, out) {
+            Ok(flow) => Ok(flow),
+            Err(CommandError::Io(err)) => Err(err),
+            Err(CommandError::Usage(msg)) => {
+                writeln!(out, "Error: {}", msg)?;
+                Ok(Flow::Continue)
+            }
+        }
+    }
+
+    fn command(&mut self, args: &[&str], out: &mut dyn Write) -> Result<Flow, CommandError> {
+        match args[0] {
+            "step" | "s" => {
+                let cycles = match args.get(1) {
+                    Some(n) => n
+                        .parse::<usize>()
+                        .map_err(|_| usage(format!("Invalid number of cycles '{}'", n)))?,
+                    None => 1,
+                };
+                let outcome = self.emulator.run(RunLimits {
+                    max_cycles: Some(cycles),
+                    ..self.limits
+                });
+                self.report(&outcome, out)?;
+            }
+            "continue" | "c" => {
+                let outcome = self.emulator.run(self.limits);
+                self.report(&outcome, out)?;
+            }
+            "back" => {
+                let cycles = match args.get(1) {
+                    Some(n) => n
+                        .parse::<usize>()
+                        .map_err(|_| usage(format!("Invalid number of cycles '{}'", n)))?,
+                    None => 1,
+                };
+                let undone = self.emulator.step_back(cycles);
+                self.report_undone(undone, out)?;
+            }
+            "reverse" => {
+                let (start, end) = parse_range(arg(args, 1)?)?;
+                let cycles = self.emulator.cycles;
+                if self.emulator.step_back_to_write(start..end).is_none() {
+                    writeln!(
+                        out,
+                        "No write to 0x{:08X}-0x{:08X} in the journal",
+                        start, end
+                    )?;
+                }
+                self.report_undone(cycles - self.emulator.cycles, out)?;
+            }
+            "break" | "b" => {
+                let addr = parse_address(arg(args, 1)?)?;
+                self.emulator.add_breakpoint(addr);
+                writeln!(out, "Breakpoint at 0x{:08X}", addr)?;
+            }
+            "delete" | "d" => {
+                let addr = parse_address(arg(args, 1)?)?;
+                if !self.emulator.remove_breakpoint(addr) {
+                    return Err(usage(format!("No breakpoint at 0x{:08X}", addr)));
+                }
+            }
+            "watch" | "w" => {
+                let (kind, range) = match args.get(2) {
+                    Some(range) => (parse_watch_kind(args[1])?, *range),
+                    None => (WatchKind::Write, arg(args, 1)?),
+                };
+                let (start, end) = parse_range(range)?;
+                let id = self.emulator.add_watchpoint(start..end, kind);
+                writeln!(
+                    out,
+                    "Watchpoint {}: {:?} 0x{:08X}-0x{:08X}",
+                    id.0, kind, start, end
+                )?;
+            }
+            "unwatch" => {
+                let id = arg(args, 1)?;
+                let id = id
+                    .parse::<usize>()
+                    .map_err(|_| usage(format!("Invalid watchpoint '{}'", id)))?;
+                if !self.emulator.remove_watchpoint(WatchpointId(id)) {
+                    return Err(usage(format!("No watchpoint {}", id)));
+                }
+            }
+            "info" | "i" => {
+                for addr in self.emulator.breakpoints() {
+                    writeln!(out, "Breakpoint at {}", format_address(*addr))?;
+                }
+                for wp in self.emulator.watchpoints() {
+                    writeln!(
+                        out,
+                        "Watchpoint {}: {:?} 0x{:08X}-0x{:08X}",
+                        wp.id.0, wp.kind, wp.range.start, wp.range.end
+                    )?;
+                }
+            }
+            "print" | "p" => {
+                let (start, end) = parse_range(arg(args, 1)?)?;
+                for addr in start..end {
+                    // Peek to not consume UART input.
+                    match self.emulator.memory.peek(addr) {
+                        Some(value) => writeln!(out, "{}: 0x{:08X}", format_address(addr), value)?,
+                        None => match self.emulator.memory.try_get(addr) {
+                            Ok(_) => writeln!(out, "{}: (not readable)", format_address(addr))?,
+                            Err(err) => writeln!(out, "{}: {}", format_address(addr), err)?,
+                        },
+                    }
+                }
+            }
+            "regs" | "r" => self.print_registers(out)?,
+            "set" => {
+                let addr = parse_address(arg(args, 1)?)?;
+                let value = parse_value(arg(args, 2)?)?;
+                if let Err(err) = self.emulator.memory.try_set(addr, value) {
+                    return Err(usage(err.to_string()));
+                }
+            }
+            "disasm" | "x" => {
+                let count = match args.get(1) {
+                    Some(n) => n
+                        .parse::<usize>()
+                        .map_err(|_| usage(format!("Invalid number of instructions '{}'", n)))?,
+                    None => 3,
+                };
+                self.disassemble_around_ip(count, out)?;
+            }
+            "mounts" | "m" => {
+                for mount in self.emulator.memory.mounts() {
+                    writeln!(
+                        out,
+                        "0x{:08X}-0x{:08X} {:10} {}",
+                        mount.start_address,
+                        mount.start_address + mount.size,
+                        mount.name,
+                        if mount.enabled { "enabled" } else { "disabled" }
+                    )?;
+                }
+            }
+            "save" => {
+                let path = arg(args, 1)?;
+                let result = File::create(path)
+                    .map_err(SnapshotError::from)
+                    .and_then(|file| {
+                        let mut file = BufWriter::new(file);
+                        self.emulator.save_snapshot(&mut file)?;
+                        file.flush().map_err(SnapshotError::from)
+                    });
+                if let Err(err) = result {
+                    return Err(usage(format!("Could not save snapshot: {}", err)));
+                }
+                writeln!(out, "Snapshot saved to {}", path)?;
+            }
+            "rerun" => {
+                let mut emulator = (self.factory)();
+                emulator.enable_journal(JOURNAL_CAPACITY);
+                for addr in self.emulator.breakpoints() {
+                    emulator.add_breakpoint(*addr);
+                }
+                // Re-adding the watchpoints assigns new ids, so they are listed.
+                for wp in self.emulator.watchpoints() {
+                    let id = emulator.add_watchpoint(wp.range.clone(), wp.kind);
+                    writeln!(out, "Watchpoint {} is now {}", wp.id.0, id.0)?;
+                }
+                self.emulator = emulator;
+                self.print_current(out)?;
+            }
+            "help" | "h" => writeln!(out, "{}", HELP)?,
+            "quit" | "q" => return Ok(Flow::Quit),
+            command => return Err(usage(format!("Unknown command '{}'", command))),
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn report(&self, outcome: &RunOutcome, out: &mut dyn Write) -> io::Result<()> {
+        if outcome.reason != StopReason::CycleLimit {
+            writeln!(out, "{}", outcome.reason)?;
+        }
+        writeln!(
+            out,
+            "{} cycles executed, {} in total",
+            outcome.cycles, self.emulator.cycles
+        )?;
+        self.print_current(out)
+    }
+
+    fn report_undone(&self, undone: usize, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{} cycles undone, {} in total",
+            undone, self.emulator.cycles
+        )?;
+        self.print_current(out)
+    }
+
+    fn print_current(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.disassemble_around_ip(0, out)
+    }
+
+    fn print_registers(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "ip: 0x{:08X}", self.emulator.ip)?;
+        let registers = (0..address::NUM_REGISTERS)
+            .map(|idx| (format!("r{}", idx), address::REGISTERS_START_ADDRESS + idx));
+        let others = [address::STACK_POINTER_ADDRESS, address::FLAGS_START_ADDRESS]
+            .iter()
+            .map(|addr| (format_address(*addr), *addr));
+        for (name, addr) in registers.chain(others) {
+            match self.emulator.memory.try_get(addr) {
+                Ok(value) => writeln!(out, "{}: 0x{:08X}", name, value)?,
+                Err(err) => writeln!(out, "{}: {}", name, err)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn disassemble_around_ip(&self, count: usize, out: &mut dyn Write) -> io::Result<()> {
+        let ip = self.emulator.ip;
+        let start = ip.saturating_sub(4 * count);
+        let end = ip.saturating_add(4 * count + 4);
+        for addr in (start..end).step_by(4) {
+            let marker = if addr == ip { "=>" } else { "  " };
+            match disassembler::disassemble(addr, &self.emulator.memory) {
+                Ok(disassembly) => writeln!(out, "{} {}", marker, disassembly)?,
+                Err(fault) if addr == ip => writeln!(out, "{} {}", marker, fault)?,
+                Err(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+enum CommandError {
+    Io(io::Error),
+    Usage(String),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::Io(err)
+    }
+}
+
+fn usage(msg: String) -> CommandError {
+    CommandError::Usage(msg)
+}
+
+fn arg<'a>(args: &[&'a str], idx: usize) -> Result<&'a str, CommandError> {
+    args.get(idx)
+        .cloned()
+        .ok_or_else(|| usage(format!("Missing argument for '{}'", args[0])))
+}
+
+fn format_address(addr: usize) -> String {
+    match disassembler::address_name(addr) {
+        Some(name) => format!("{} (0x{:08X})", name, addr),
+        None => format!("0x{:08X}", addr),
+    }
+}
+
+fn parse_address(s: &str) -> Result<usize, CommandError> {
+    disassembler::address_by_name(s)
+        .or_else(|| usize::from_str_radix(s.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| usage(format!("Invalid address '{}'", s)))
+}
+
+/// Parses `ADDR` or `START-END` into an exclusive range.
+fn parse_range(s: &str) -> Result<(usize, usize), CommandError> {
+    match s.find('-') {
+        Some(idx) => {
+            let start = parse_address(&s[..idx])?;
+            let end = parse_address(&s[idx + 1..])?;
+            if end <= start {
+                return Err(usage(format!("Empty range '{}'", s)));
+            }
+            Ok((start, end))
+        }
+        None => parse_address(s).map(|addr| (addr, addr + 1)),
+    }
+}
+
+fn parse_value(s: &str) -> Result<i32, CommandError> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    u32::from_str_radix(digits.trim_start_matches("0x"), 16)
+        .map(|value| {
+            if negative {
+                (value as i32).wrapping_neg()
+            } else {
+                value as i32
+            }
+        })
+        .map_err(|_| usage(format!("Invalid value '{}'", s)))
+}
+
+fn parse_watch_kind(s: &str) -> Result<WatchKind, CommandError> {
+    match s {
+        "read" => Ok(WatchKind::Read),
+        "write" => Ok(WatchKind::Write),
+        "change" => Ok(WatchKind::Change),
+        _ => Err(usage(format!("Invalid watchpoint kind '{}'", s))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use osciemu::emulator::{Emulator, RunLimits};
+    use osciemu::memory::{address, SliceMemory};
+
+    fn debugger() -> super::Debugger {
+        super::Debugger::new(
+            Box::new(|| {
+                let bios = SliceMemory::from_slice(Box::new([
+                    // 0x00: Decrement r0 and exit the loop once it is not positive
+                    address::REGISTERS_START_ADDRESS as i32,
+                    address::BIOS_START_ADDRESS as i32 + 12,
+                    address::REGISTERS_START_ADDRESS as i32,
+                    address::BIOS_START_ADDRESS as i32 + 8,
+                    // 0x04: Jump to 0x00
+                    address::BIOS_START_ADDRESS as i32 + 13,
+                    address::BIOS_START_ADDRESS as i32 + 13,
+                    address::REGISTERS_START_ADDRESS as i32 + 1,
+                    address::BIOS_START_ADDRESS as i32,
+                    // 0x08: Halt
+                    address::BIOS_START_ADDRESS as i32 + 12,
+                    address::BIOS_START_ADDRESS as i32 + 13,
+                    address::FLAGS_START_ADDRESS as i32,
+                    0,
+                    // 0x0C: Constants
+                    1,
+                    0,
+                    0,
+                    0,
+                ]));
+                Emulator::from_bios_only(Box::new(bios))
+            }),
+            RunLimits::default(),
+        )
+    }
+
+    fn run_script(debugger: &mut super::Debugger, script: &str) -> String {
+        let mut out = Vec::new();
+        debugger.repl(script.as_bytes(), &mut out, true).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn script() {
+        let mut debugger = debugger();
+        let out = run_script(
+            &mut debugger,
+            "set r0 2\n\
+             watch change r0\n\
+             continue\n\
+             print r0\n\
+             unwatch 0\n\
+             continue\n\
+             quit\n\
+             print r0\n",
+        );
+        assert!(out.contains("Watchpoint 0 (Change) at 0x7FFFFFF9: 0x00000001"));
+        assert!(out.contains("r0 (0x7FFFFFF9): 0x00000001"));
+        assert!(out.contains("Halted"));
+        assert_eq!(out.matches("(osci) print r0").count(), 1);
+    }
+
+    #[test]
+    fn breakpoints_and_rerun() {
+        let mut debugger = debugger();
+        let out = run_script(
+            &mut debugger,
+            "break 40000008\n\
+             continue\n\
+             rerun\n\
+             step\n\
+             \n\
+             info\n\
+             foo\n",
+        );
+        assert!(out.contains("Breakpoint at 0x40000008"));
+        assert!(out.contains("=> 0x40000008:"));
+        assert!(out.contains("1 cycles executed, 2 in total"));
+        assert!(out.contains("Halted"));
+        assert!(out.contains("Error: Unknown command 'foo'"));
+    }
+
+    #[test]
+    fn back_and_reverse() {
+        let mut debugger = debugger();
+        let out = run_script(
+            &mut debugger,
+            "set r0 3\n\
+             continue\n\
+             back\n\
+             reverse r0\n\
+             print r0\n\
+             back 100\n\
+             reverse r2\n",
+        );
+        assert!(out.contains("1 cycles undone, 5 in total\n=> 0x40000008:"));
+        assert!(out.contains("1 cycles undone, 4 in total\n=> 0x40000000:"));
+        assert!(out.contains("r0 (0x7FFFFFF9): 0x00000001"));
+        assert!(out.contains("4 cycles undone, 0 in total"));
+        assert!(out.contains("No write to 0x7FFFFFFB-0x7FFFFFFC in the journal"));
+    }
+
+    #[test]
+    fn mounts() {
+        let mut debugger = debugger();
+        let out = run_script(&mut debugger, "mounts\n");
+        assert!(out.contains("0x40000000-0x40000010 bios       enabled"));
+        assert!(out.contains("flags"));
+    }
+
+    #[test]
+    fn regs() {
+        let mut debugger = debugger();
+        let out = run_script(&mut debugger, "set r1 5\nregs\n");
+        assert!(out.contains("ip: 0x40000000"));
+        assert!(out.contains("r1: 0x00000005"));
+        assert!(out.contains("flags0 (0x7FFFFFFE): 0x00000000"));
+    }
+
+    #[test]
+    fn parse_value() {
+        assert_eq!(super::parse_value("10").ok(), Some(16));
+        assert_eq!(super::parse_value("-1").ok(), Some(-1));
+        assert_eq!(super::parse_value("FFFFFFFF").ok(), Some(-1));
+        assert!(super::parse_value("x").is_err());
+    }
+}
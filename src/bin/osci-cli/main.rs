@@ -2,111 +2,576 @@
 extern crate clap;
 extern crate osciemu;
 
-use std::io;
+mod debugger;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpListener;
 use std::path::Path;
-use osciemu::utils::load_file;
+use clap::ArgMatches;
+use osciemu::utils::{load_file_with_options, load_symbols};
+use osciemu::symbols::{self, Symbols};
+use osciemu::assembler;
 use osciemu::loader;
-use osciemu::memory::{Memory, SliceMemory};
-use osciemu::emulator::Emulator;
+use osciemu::loader::rawloader::{self, Endianness, PartialWord};
+use osciemu::memory::{address, FileMemory, Memory, SliceMemory};
+use osciemu::emulator::{Emulator, Ram, RunLimits, StopReason};
+use osciemu::disassembler;
+use osciemu::device::Uart;
+use osciemu::gdbstub::GdbStub;
+use osciemu::profile::Profiler;
+use osciemu::trace::{TraceFilter, TraceFormat, Tracer};
 
-fn main() {
-    let matches = clap_app!(myapp =>
+/// Number of entries in each section of the `--profile` report.
+const PROFILE_REPORT_LIMIT: usize = 20;
+
+/// Builds the command line interface.
+fn app() -> clap::App<'static, 'static> {
+    clap_app!(myapp =>
             (version: "0.1.0")
             (author: "Surma <surma@surma.link>")
             (about: "Emulates an osci CPU")
             (@arg MEMORY: -m --memory +takes_value "Memory image to load")
-            (@arg BIOS: -b --bios +required +takes_value "BIOS image to load")
-            (@arg STEP: --step "Walk through in stepping mode")
+            (@arg BIOS: -b --bios required_unless[RESUME] +takes_value "BIOS image to load")
+            (@arg RAW_ENDIAN: --("raw-endian") +takes_value possible_value[big little] "Byte order of raw images (.raw, .bin, .img, default big)")
+            (@arg RAW_PARTIAL: --("raw-partial") +takes_value possible_value[drop reject pad] "Handling of trailing bytes of raw images that don't make up a full word: drop, reject or pad with zeros (default drop)")
+            (@arg RAW_HEADER: --("raw-header") +takes_value "Number of bytes to skip at the start of raw images (decimal or 0x-prefixed hex)")
+            (@arg RAM: --ram +takes_value "RAM behind the memory image: sparse (allocated on demand) or a size in words (decimal or 0x-prefixed hex, default none)")
+            (@arg MOUNT_FILE: --("mount-file") +takes_value +multiple number_of_values(1) {is_mount_file} "Mount a file as memory at ADDR (hex), written back when the emulator stops: ADDR[+WORDS]:PATH. WORDS creates or extends the file.")
+            (@arg RESUME: --resume +takes_value conflicts_with[MEMORY BIOS MOUNT_FILE] "Resume from a snapshot file instead of loading images or mounting files")
+            (@arg SAVE_SNAPSHOT: --("save-snapshot") +takes_value "Save a snapshot to a file when the emulator stops")
+            (@arg DEBUG: --debug "Start the interactive debugger")
+            (@arg SCRIPT: --script +takes_value "Run debugger commands from a file (implies --debug)")
             (@arg MAX_STEP: --maxstep +takes_value "Maximum number of CPU cycles (0 means infinite)")
-            (@arg PRINT: --print +takes_value "Addresses to print after CPU halts")
-        ).get_matches();
+            (@arg PRINT: --print +takes_value "Addresses (hex or labels like loop+2) to print after CPU halts")
+            (@arg UART: --uart "Attach a UART connected to stdin and stdout")
+            (@arg UART_IN: --("uart-in") +takes_value "File the UART reads from (implies --uart)")
+            (@arg UART_OUT: --("uart-out") +takes_value "File the UART writes to (implies --uart)")
+            (@arg TRACE: --trace +takes_value "Write an execution trace to a file (- for stdout)")
+            (@arg TRACE_FORMAT: --("trace-format") +takes_value possible_value[text csv json] "Format of the execution trace (default text)")
+            (@arg TRACE_FILTER: --("trace-filter") +takes_value "Only trace instructions in the given ranges (START-END) or mounts (e.g. bios), separated by commas")
+            (@arg PROFILE: --profile +takes_value "Write a hot-spot report to a file when the emulator stops (- for stdout)")
+            (@arg CALLGRIND: --callgrind +takes_value "Write a profile in the callgrind format to a file when the emulator stops")
+            (@arg COVERAGE: --coverage +takes_value "Write code coverage to a file when the emulator stops")
+            (@arg COVERAGE_FORMAT: --("coverage-format") +takes_value possible_value[lcov listing] "Format of the code coverage (default lcov for assembly sources, listing otherwise)")
+            (@arg GDB: --gdb +takes_value "Serve the GDB remote protocol on a localhost TCP port (- for stdin and stdout)")
+            (@arg ASSEMBLE: --assemble +takes_value "Assemble the BIOS source to an image (.hex or .raw) with a symbol file next to it instead of running")
+            (@arg DISASM: --disasm +takes_value {is_range} "Disassemble an address range (START-END) instead of running")
+    )
+}
+
+fn main() {
+    let matches = app().get_matches();
 
     let max_steps = matches
         .value_of("MAX_STEP")
         .map(|s| s.parse::<usize>().expect("--max-step needs to be a number"))
         .unwrap_or(0);
 
-    let prints = matches.value_of("PRINT").map(|s| -> Vec<u32> {
+    let prints = matches.value_of("PRINT").map(|s| -> Vec<usize> {
+        let symbols = symbols(&matches);
         s.split(",")
-            .map(|s| u32::from_str_radix(s.trim(), 16).expect("Invalid address"))
+            .map(|s| parse_address(s.trim(), &symbols).expect("Invalid address"))
             .collect()
     });
 
-    let image_mem = matches
-        .value_of("MEMORY")
-        .ok_or(loader::LoadError::new())
-        .map(|path| Path::new(path))
-        .and_then(load_file)
-        .unwrap_or_else(|_err| Box::new(SliceMemory::new(0)));
+    if let Some(path) = matches.value_of("ASSEMBLE") {
+        let bios = matches.value_of("BIOS").expect("--assemble needs a BIOS source");
+        assemble(Path::new(bios), Path::new(path)).expect("Could not assemble BIOS");
+        return;
+    }
 
-    let bios_mem = matches
-        .value_of("BIOS")
-        .ok_or(loader::LoadError::new())
-        .map(|path| Path::new(path))
-        .and_then(load_file)
-        .expect("Could not load bios");
+    if let Some((start, end)) = matches.value_of("DISASM").and_then(parse_range) {
+        let emulator = build_emulator(&matches);
+        let symbols = symbols(&matches);
+        for line in disassembler::disassemble_range(&emulator.memory, start, end) {
+            match line {
+                Ok(line) => println!("{}", line.with_symbols(&symbols)),
+                Err(fault) => println!("0x{:08X}: {}", fault.ip, fault),
+            }
+        }
+        return;
+    }
+
+    let limits = RunLimits {
+        max_cycles: if max_steps == 0 { None } else { Some(max_steps) },
+        ..RunLimits::default()
+    };
 
-    let step_mode = matches.is_present("STEP");
+    if let Some(target) = matches.value_of("GDB") {
+        let mut emulator = build_emulator(&matches);
+        let result = match target {
+            "-" => {
+                let stdin = io::stdin();
+                let stdout = io::stdout();
+                let mut stub = GdbStub::new(&mut emulator, stdin.lock(), stdout.lock());
+                stub.set_limits(limits);
+                stub.serve()
+            }
+            port => {
+                let port = port
+                    .parse::<u16>()
+                    .expect("--gdb needs a port number or -");
+                let listener =
+                    TcpListener::bind(("127.0.0.1", port)).expect("Could not listen for GDB");
+                eprintln!("Waiting for GDB on 127.0.0.1:{}", port);
+                let (stream, _) = listener.accept().expect("Could not accept GDB connection");
+                let input = stream.try_clone().expect("Could not use GDB connection");
+                let mut stub = GdbStub::new(&mut emulator, input, stream);
+                stub.set_limits(limits);
+                stub.serve()
+            }
+        };
+        result.expect("GDB connection failed");
+        return;
+    }
 
-    let mut emulator = Emulator::new(bios_mem, image_mem);
+    if matches.is_present("DEBUG") || matches.is_present("SCRIPT") {
+        let factory_matches = matches.clone();
+        let mut debugger = debugger::Debugger::new(
+            Box::new(move || build_emulator(&factory_matches)),
+            limits,
+        );
+        let stdout = io::stdout();
+        let result = match matches.value_of("SCRIPT") {
+            Some(path) => {
+                let script = File::open(path).expect("Could not open debugger script");
+                debugger.repl(BufReader::new(script), &mut stdout.lock(), true)
+            }
+            None => {
+                let stdin = io::stdin();
+                let input = stdin.lock();
+                debugger.repl(input, &mut stdout.lock(), false)
+            }
+        };
+        result.expect("Could not run debugger");
+        return;
+    }
 
-    for count in RangeIterator::new(1, max_steps) {
-        if emulator.is_halted() {
-            println!("cycles: {:4}  HALTED\n", count);
-            break;
+    let mut emulator = build_emulator(&matches);
+    let profiler = if matches.is_present("PROFILE") || matches.is_present("CALLGRIND") {
+        let mut profiler = Profiler::new();
+        profiler.set_symbols(symbols(&matches));
+        Some(emulator.attach_tracer(profiler))
+    } else {
+        None
+    };
+    if matches.is_present("COVERAGE") {
+        emulator.enable_coverage();
+    }
+    let outcome = emulator.run(limits);
+    // Dropping the tracers flushes the trace.
+    emulator.detach_tracers();
+    emulator
+        .memory
+        .flush()
+        .expect("Could not write back mounted files");
+    println!("cycles: {:4}  {}\n", outcome.cycles, outcome.reason);
+    if let Some(path) = matches.value_of("COVERAGE") {
+        let mut file = BufWriter::new(File::create(path).expect("Could not create coverage file"));
+        write_coverage(&mut file, &emulator, &matches)
+            .and_then(|_| file.flush())
+            .expect("Could not write coverage");
+    }
+    if let Some(profiler) = profiler {
+        let profiler = profiler.borrow();
+        if let Some(path) = matches.value_of("PROFILE") {
+            let mut output: Box<dyn Write> = match path {
+                "-" => Box::new(io::stdout()),
+                path => Box::new(BufWriter::new(
+                    File::create(path).expect("Could not create profile file"),
+                )),
+            };
+            profiler
+                .write_report(&mut output, PROFILE_REPORT_LIMIT)
+                .and_then(|_| output.flush())
+                .expect("Could not write profile");
         }
-        println!(
-            "cycles: {:4}, ip: 0x{:08X}, r0: 0x{:08X}, r1: 0x{:08X}, r2: 0x{:08X}, r3: \
-             0x{:08X}",
-            count,
-            emulator.ip,
-            emulator.get_register(0),
-            emulator.get_register(1),
-            emulator.get_register(2),
-            emulator.get_register(3)
-        );
-        emulator.step();
-        if step_mode {
-            let mut buffer = String::new();
-            let _ = io::stdin().read_line(&mut buffer);
+        if let Some(path) = matches.value_of("CALLGRIND") {
+            let mut file =
+                BufWriter::new(File::create(path).expect("Could not create callgrind file"));
+            profiler
+                .write_callgrind(&mut file)
+                .and_then(|_| file.flush())
+                .expect("Could not write callgrind file");
         }
     }
-    if emulator.is_halted() && prints.is_some() {
+    if let Some(path) = matches.value_of("SAVE_SNAPSHOT") {
+        let mut file = BufWriter::new(File::create(path).expect("Could not create snapshot file"));
+        emulator
+            .save_snapshot(&mut file)
+            .and_then(|_| file.flush().map_err(From::from))
+            .expect("Could not save snapshot");
+    }
+    if let (true, Some(prints)) = (emulator.is_halted(), prints) {
+        let symbols = symbols(&matches);
         let result = prints
-            .unwrap()
             .iter()
-            .map(|addr| format!("0x{:08X}", emulator.memory.get(*addr as usize)))
+            .map(|addr| {
+                let value = match emulator.memory.try_get(*addr) {
+                    Ok(value) => format!("0x{:08X}", value),
+                    Err(err) => format!("<{}>", err),
+                };
+                if symbols.is_empty() {
+                    value
+                } else {
+                    format!("{}={}", symbols.format(*addr), value)
+                }
+            })
             .collect::<Vec<String>>()
             .join(", ");
         println!("Final state:");
         println!("{}", result);
     }
-    if !emulator.is_halted() {
+    if outcome.reason != StopReason::Halted {
         std::process::exit(1);
     }
 }
 
-struct RangeIterator {
-    count: usize,
-    max: usize,
+/// Assembles `source` and writes the image in the format given by the extension of `output` and the symbols to the symbol file next to it.
+fn assemble(source: &Path, output: &Path) -> Result<(), loader::LoadError> {
+    let mut text = String::new();
+    File::open(source)?.read_to_string(&mut text)?;
+    let program = assembler::assemble(&text)?;
+    let mut file = BufWriter::new(File::create(output)?);
+    match output.extension().and_then(|ext| ext.to_str()) {
+        Some("hex") => {
+            for chunk in program.words.chunks(4) {
+                let words: Vec<String> = chunk.iter().map(|word| format!("{:08X}", word)).collect();
+                writeln!(file, "{}", words.join(" "))?;
+            }
+        }
+        Some("img") | Some("bin") | Some("raw") => {
+            for word in &program.words {
+                file.write_all(&word.to_be_bytes())?;
+            }
+        }
+        ext => {
+            return Err(loader::LoadError::from_message(format!(
+                "Unknown file extension: {:?}",
+                ext
+            )))
+        }
+    }
+    file.flush()?;
+    let mut sidecar = BufWriter::new(File::create(symbols::sidecar_path(output))?);
+    Symbols::from_program(&program, source.to_str()).write(&mut sidecar)?;
+    sidecar.flush()?;
+    Ok(())
+}
+
+/// Parses a hex address or a label with an optional decimal offset, e.g. `loop+2`.
+fn parse_address(s: &str, symbols: &Symbols) -> Option<usize> {
+    let (name, offset) = match s.find('+') {
+        Some(idx) => (&s[..idx], s[idx + 1..].parse::<usize>().ok()?),
+        None => (s, 0),
+    };
+    // Labels like `add` or `beef` are also valid hex numbers, so names take precedence.
+    match symbols
+        .address_of(name)
+        .or_else(|| disassembler::address_by_name(name))
+    {
+        Some(addr) => Some(addr + offset),
+        None => usize::from_str_radix(s, 16).ok(),
+    }
+}
+
+/// Loads the memory images or the snapshot and attaches the peripherals given on the command line.
+fn build_emulator(matches: &ArgMatches) -> Emulator {
+    if matches.is_present("RESUME") {
+        // The images are replaced with the ones in the snapshot.
+        let mut emulator = Emulator::with_ram(
+            Box::new(SliceMemory::new(0)),
+            Box::new(SliceMemory::new(0)),
+            ram(matches),
+        );
+        mount_files(&mut emulator, matches);
+        attach_peripherals(&mut emulator, matches);
+        return emulator;
+    }
+
+    let options = raw_options(matches);
+    let image_mem = matches
+        .value_of("MEMORY")
+        .ok_or(loader::LoadError::new())
+        .map(Path::new)
+        .and_then(|path| load_file_with_options(path, &options))
+        .unwrap_or_else(|_err| Box::new(SliceMemory::new(0)));
+
+    let bios_mem = matches
+        .value_of("BIOS")
+        .ok_or(loader::LoadError::new())
+        .map(Path::new)
+        .and_then(|path| load_file_with_options(path, &options))
+        .expect("Could not load bios");
+
+    let mut emulator = Emulator::with_ram(bios_mem, image_mem, ram(matches));
+    mount_files(&mut emulator, matches);
+    attach_peripherals(&mut emulator, matches);
+    emulator
 }
 
-impl RangeIterator {
-    fn new(start: usize, max: usize) -> RangeIterator {
-        RangeIterator { count: start, max }
+/// Mounts the files given with `--mount-file` in order.
+fn mount_files(emulator: &mut Emulator, matches: &ArgMatches) {
+    for spec in matches.values_of("MOUNT_FILE").into_iter().flatten() {
+        let (addr, size, path) = parse_mount_file(spec).expect("Invalid --mount-file");
+        let memory = match size {
+            Some(size) => FileMemory::create(path, size),
+            None => FileMemory::open(path),
+        };
+        let memory = memory.unwrap_or_else(|err| panic!("Could not open {}: {}", path, err));
+        emulator
+            .memory
+            .mount_named(addr, path, Box::new(memory))
+            .unwrap_or_else(|err| panic!("Could not mount {}: {}", path, err));
     }
 }
 
-impl Iterator for RangeIterator {
-    type Item = usize;
-    fn next(&mut self) -> Option<usize> {
-        if self.count == self.max && self.max != 0 {
-            return None;
+/// Parses a `--mount-file` argument in the form `ADDR[+WORDS]:PATH` into the address, the size and the path.
+///
+/// Fails if the mount would reach beyond `MAX_ADDRESS`. The size of an existing file is only checked when it is mounted.
+fn parse_mount_file(spec: &str) -> Result<(usize, Option<usize>, &str), String> {
+    let (mount, path) = spec
+        .split_once(':')
+        .ok_or_else(|| String::from("needs ADDR[+WORDS]:PATH"))?;
+    let (addr, size) = match mount.split_once('+') {
+        Some((addr, size)) => {
+            let size = parse_number(size).ok_or_else(|| format!("invalid size '{}'", size))?;
+            (addr, Some(size))
         }
-        let result = Some(self.count);
-        if self.count != std::usize::MAX {
-            self.count = self.count + 1;
+        None => (mount, None),
+    };
+    let addr = usize::from_str_radix(addr, 16).map_err(|_| format!("invalid address '{}'", addr))?;
+    if addr.saturating_add(size.unwrap_or(1)) > address::MAX_ADDRESS + 1 {
+        return Err(format!(
+            "mount at 0x{:X} reaches beyond 0x{:08X}",
+            addr,
+            address::MAX_ADDRESS
+        ));
+    }
+    Ok((addr, size, path))
+}
+
+/// Validates an argument that `parse_mount_file()` has to accept.
+fn is_mount_file(s: String) -> Result<(), String> {
+    parse_mount_file(&s).map(|_| ())
+}
+
+/// Parses an address range in the form `START-END` (hex).
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (start, end) = s.split_once('-')?;
+    let start = usize::from_str_radix(start.trim(), 16).ok()?;
+    let end = usize::from_str_radix(end.trim(), 16).ok()?;
+    Some((start, end))
+}
+
+/// Validates an argument that `parse_range()` has to accept.
+fn is_range(s: String) -> Result<(), String> {
+    match parse_range(&s) {
+        Some(_) => Ok(()),
+        None => Err(String::from("needs a range of hex addresses in the form START-END")),
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse::<usize>().ok(),
+    }
+}
+
+/// Returns the options for loading raw images.
+fn raw_options(matches: &ArgMatches) -> rawloader::Options {
+    let mut options = rawloader::Options::default();
+    if matches.value_of("RAW_ENDIAN") == Some("little") {
+        options.endianness = Endianness::Little;
+    }
+    match matches.value_of("RAW_PARTIAL") {
+        Some("reject") => options.partial_word = PartialWord::Reject,
+        Some("pad") => options.partial_word = PartialWord::Pad,
+        _ => {}
+    }
+    if let Some(header) = matches.value_of("RAW_HEADER") {
+        options.header = parse_number(header).expect("--raw-header needs to be a number") as u64;
+    }
+    options
+}
+
+fn ram(matches: &ArgMatches) -> Ram {
+    match matches.value_of("RAM") {
+        None | Some("none") => Ram::None,
+        Some("sparse") => Ram::Sparse,
+        Some(size) => Ram::Fixed(
+            parse_number(size).expect("--ram needs to be sparse, none or a number of words"),
+        ),
+    }
+}
+
+/// Loads the symbols of the images (see `utils::load_symbols()`).
+fn image_symbols(matches: &ArgMatches) -> Vec<Symbols> {
+    matches
+        .values_of("MEMORY")
+        .into_iter()
+        .chain(matches.values_of("BIOS"))
+        .flatten()
+        .filter_map(|path| load_symbols(Path::new(path)).expect("Could not load symbols"))
+        .collect()
+}
+
+/// Merges the symbols of all images.
+fn symbols(matches: &ArgMatches) -> Symbols {
+    let mut symbols = Symbols::new();
+    for image in image_symbols(matches) {
+        symbols.extend(&image);
+    }
+    symbols
+}
+
+/// Writes the coverage as lcov if any image has a line table or as an annotated hex listing of the images otherwise.
+fn write_coverage<W: Write>(
+    output: &mut W,
+    emulator: &Emulator,
+    matches: &ArgMatches,
+) -> io::Result<()> {
+    let coverage = emulator.coverage().unwrap();
+    let sources: Vec<Symbols> = image_symbols(matches)
+        .into_iter()
+        .filter(|symbols| symbols.source().is_some() && !symbols.lines().is_empty())
+        .collect();
+    let format = matches
+        .value_of("COVERAGE_FORMAT")
+        .unwrap_or(if sources.is_empty() { "listing" } else { "lcov" });
+    if format == "lcov" {
+        for symbols in &sources {
+            coverage.write_lcov(output, symbols)?;
+        }
+        return Ok(());
+    }
+    for mount in emulator.memory.mounts() {
+        if mount.name != "image" && mount.name != "bios" {
+            continue;
+        }
+        writeln!(output, "## {} at 0x{:08X}", mount.name, mount.start_address)?;
+        let memory = emulator.memory.borrow(&mount.token);
+        coverage.write_listing(output, memory, mount.start_address, mount.size)?;
+    }
+    Ok(())
+}
+
+/// Attaches the UART and the tracer and restores the snapshot to resume from.
+fn attach_peripherals(emulator: &mut Emulator, matches: &ArgMatches) {
+    let uart = matches.is_present("UART") || matches.is_present("UART_IN")
+        || matches.is_present("UART_OUT");
+    if uart {
+        let input: Box<dyn Read> = match matches.value_of("UART_IN") {
+            Some(path) => Box::new(File::open(path).expect("Could not open UART input")),
+            None => Box::new(io::stdin()),
+        };
+        let output: Box<dyn Write> = match matches.value_of("UART_OUT") {
+            Some(path) => Box::new(File::create(path).expect("Could not create UART output")),
+            None => Box::new(io::stdout()),
+        };
+        emulator.attach_device(address::UART_START_ADDRESS, Uart::new(input, output));
+    }
+
+    if let Some(path) = matches.value_of("RESUME") {
+        let file = File::open(path).expect("Could not open snapshot file");
+        if let Err(err) = emulator.load_snapshot(&mut BufReader::new(file)) {
+            panic!("Could not resume from snapshot: {}", err);
+        }
+    }
+
+    if let Some(path) = matches.value_of("TRACE") {
+        let output: Box<dyn Write> = match path {
+            "-" => Box::new(io::stdout()),
+            path => Box::new(BufWriter::new(
+                File::create(path).expect("Could not create trace file"),
+            )),
+        };
+        let format = matches
+            .value_of("TRACE_FORMAT")
+            .unwrap_or("text")
+            .parse::<TraceFormat>()
+            .unwrap();
+        let mut filter = TraceFilter::new();
+        if let Some(filters) = matches.value_of("TRACE_FILTER") {
+            for item in filters.split(',').map(|s| s.trim()) {
+                match item.find('-') {
+                    Some(idx) => {
+                        let start = usize::from_str_radix(&item[..idx], 16)
+                            .expect("Invalid address");
+                        let end = usize::from_str_radix(&item[idx + 1..], 16)
+                            .expect("Invalid address");
+                        filter.add_range(start..end);
+                    }
+                    None => {
+                        if !filter.add_mount(&emulator.memory, item) {
+                            panic!("Unknown mount '{}'", item);
+                        }
+                    }
+                }
+            }
         }
-        result
+        let mut tracer = Tracer::new(output, format, filter);
+        tracer.set_symbols(symbols(matches));
+        emulator.attach_tracer(tracer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use osciemu::memory::address;
+    use osciemu::symbols::Symbols;
+
+    #[test]
+    fn parse_address() {
+        let mut symbols = Symbols::new();
+        symbols.add_label(0x40000000, "beef");
+        symbols.add_label(0x40000010, "loop");
+        let parse = |s| super::parse_address(s, &symbols);
+        assert_eq!(parse("beef"), Some(0x40000000));
+        assert_eq!(parse("beef+2"), Some(0x40000002));
+        assert_eq!(parse("loop+4"), Some(0x40000014));
+        assert_eq!(parse("face"), Some(0xFACE));
+        assert_eq!(parse("sp"), Some(address::STACK_POINTER_ADDRESS));
+        assert_eq!(parse("nope"), None);
+    }
+
+    #[test]
+    fn parse_range() {
+        assert_eq!(super::parse_range("40000000-40000010"), Some((0x40000000, 0x40000010)));
+        assert_eq!(super::parse_range("10 - 20"), Some((0x10, 0x20)));
+        assert_eq!(super::parse_range("10"), None);
+        assert_eq!(super::parse_range("10-20-30"), None);
+        assert_eq!(super::parse_range("10-xyz"), None);
+        assert!(super::is_range(String::from("0-4")).is_ok());
+        assert!(super::is_range(String::from("0-")).is_err());
+    }
+
+    #[test]
+    fn parse_mount_file() {
+        assert_eq!(
+            super::parse_mount_file("1000+0x10:nvram.bin"),
+            Ok((0x1000, Some(16), "nvram.bin"))
+        );
+        assert_eq!(
+            super::parse_mount_file("7FFFFFF0+16:nvram.bin"),
+            Ok((0x7FFFFFF0, Some(16), "nvram.bin"))
+        );
+        assert_eq!(super::parse_mount_file("0:a:b"), Ok((0, None, "a:b")));
+        assert!(super::parse_mount_file("7FFFFFF0+17:nvram.bin").is_err());
+        assert!(super::parse_mount_file("80000000:nvram.bin").is_err());
+        assert!(super::parse_mount_file("FFFFFFFFFFFF:nvram.bin").is_err());
+        assert!(super::parse_mount_file("1000+x:nvram.bin").is_err());
+        assert!(super::parse_mount_file("nvram.bin").is_err());
+    }
+    #[test]
+    fn resume_conflicts_with_mount_file() {
+        let parse = |args: &[&str]| super::app().get_matches_from_safe(args);
+        assert!(parse(&["osci-cli", "--resume", "state.snap"]).is_ok());
+        let err = parse(&["osci-cli", "--resume", "state.snap", "--mount-file", "1000:nvram.bin"])
+            .unwrap_err();
+        assert_eq!(err.kind, ::clap::ErrorKind::ArgumentConflict);
     }
 }
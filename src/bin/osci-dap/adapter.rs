@@ -0,0 +1,650 @@
+//! Debug adapter state and request handling.
+//!
+//! The adapter has a single thread (id 1) with a single stack frame. Stepping executes one cycle. `continue` runs until the program stops on its own or `maxCycles` is reached, without reading further requests in the meantime, so `pause` is not supported. Breakpoints can be set on lines of `.asm` files, which are assembled by the adapter to know the address of every line. Other image formats can be debugged without source breakpoints.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use osciemu::assembler::{self, Program};
+use osciemu::disassembler;
+use osciemu::emulator::{Emulator, RunLimits, StepResult, StopReason};
+use osciemu::memory::{address, Memory, SliceMemory};
+use osciemu::utils::load_file;
+
+use json::Value;
+
+const THREAD_ID: usize = 1;
+const REGISTERS_REFERENCE: usize = 1;
+const FLAGS_REFERENCE: usize = 2;
+const MEMORY_REFERENCE: usize = 3;
+/// `variablesReference` of the first mount. The following mounts use the following numbers.
+const MOUNT_REFERENCE_BASE: usize = 1000;
+/// Maximum number of words shown for a mount.
+const MAX_MOUNT_WORDS: usize = 256;
+
+/// An assembled source file mounted in the emulator.
+struct Source {
+    path: String,
+    program: Program,
+    mount_start: usize,
+    is_bios: bool,
+}
+
+/// Whether the adapter should keep reading requests.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+pub struct Adapter {
+    emulator: Option<Emulator>,
+    sources: Vec<Source>,
+    breakpoints: HashMap<String, Vec<usize>>,
+    stop_on_entry: bool,
+    limits: RunLimits,
+    seq: usize,
+    outbox: Vec<Value>,
+}
+
+impl Adapter {
+    pub fn new() -> Adapter {
+        Adapter {
+            emulator: None,
+            sources: Vec::new(),
+            breakpoints: HashMap::new(),
+            stop_on_entry: false,
+            limits: RunLimits::default(),
+            seq: 0,
+            outbox: Vec::new(),
+        }
+    }
+
+    /// Returns the messages produced since the last call.
+    pub fn take_messages(&mut self) -> Vec<Value> {
+        self.outbox.drain(..).collect()
+    }
+
+    /// Handles a request. Responses and events are queued for `take_messages()`.
+    pub fn handle(&mut self, request: &Value) -> Flow {
+        let command = request.get("command").as_str().unwrap_or("");
+        let args = request.get("arguments");
+        let result = match command {
+            "initialize" => Ok(Value::object(vec![
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsSteppingGranularity", false.into()),
+            ])),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(Value::object(vec![])),
+            "threads" => Ok(Value::object(vec![(
+                "threads",
+                vec![Value::object(vec![
+                    ("id", THREAD_ID.into()),
+                    ("name", "osci".into()),
+                ])]
+                .into(),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(Value::object(vec![(
+                "scopes",
+                vec![
+                    scope("Registers", REGISTERS_REFERENCE, false),
+                    scope("Flags", FLAGS_REFERENCE, false),
+                    scope("Memory", MEMORY_REFERENCE, true),
+                ]
+                .into(),
+            )])),
+            "variables" => self.variables(args),
+            "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" => {
+                self.emulator().map(|_| Value::object(vec![]))
+            }
+            "disconnect" => {
+                self.respond(request, Ok(Value::object(vec![])));
+                return Flow::Quit;
+            }
+            _ => Err(format!("Unsupported command '{}'", command)),
+        };
+        let ok = result.is_ok();
+        self.respond(request, result);
+        if !ok {
+            return Flow::Continue;
+        }
+
+        // Execution happens after the response has been sent.
+        match command {
+            "launch" => self.event("initialized", Value::Null),
+            "configurationDone" if self.stop_on_entry => self.stopped("entry", None),
+            "configurationDone" | "continue" => {
+                let limits = self.limits;
+                let outcome = self.emulator.as_mut().unwrap().run(limits);
+                self.report(outcome.reason);
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let emulator = self.emulator.as_mut().unwrap();
+                let reason = match emulator.step() {
+                    StepResult::Fault(fault) => StopReason::Fault(fault),
+                    _ if emulator.is_halted() => StopReason::Halted,
+                    _ => StopReason::CycleLimit,
+                };
+                self.report(reason);
+            }
+            _ => {}
+        }
+        Flow::Continue
+    }
+
+    fn emulator(&self) -> Result<&Emulator, String> {
+        self.emulator
+            .as_ref()
+            .ok_or_else(|| String::from("No program has been launched"))
+    }
+
+    fn next_seq(&mut self) -> usize {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let seq = self.next_seq();
+        let mut pairs = vec![
+            ("seq", seq.into()),
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").clone()),
+            ("command", request.get("command").clone()),
+        ];
+        match result {
+            Ok(body) => {
+                pairs.push(("success", true.into()));
+                pairs.push(("body", body));
+            }
+            Err(message) => {
+                pairs.push(("success", false.into()));
+                pairs.push(("message", message.into()));
+            }
+        }
+        self.outbox.push(Value::object(pairs));
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        let seq = self.next_seq();
+        let mut pairs = vec![
+            ("seq", seq.into()),
+            ("type", "event".into()),
+            ("event", event.into()),
+        ];
+        if body != Value::Null {
+            pairs.push(("body", body));
+        }
+        self.outbox.push(Value::object(pairs));
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) {
+        let mut pairs = vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(text) = text {
+            pairs.push(("description", text.clone().into()));
+            pairs.push(("text", text.into()));
+        }
+        self.event("stopped", Value::object(pairs));
+    }
+
+    /// Reports why execution stopped. `CycleLimit` is used for a completed step.
+    fn report(&mut self, reason: StopReason) {
+        match reason {
+            StopReason::Halted => {
+                self.event("exited", Value::object(vec![("exitCode", 0usize.into())]));
+                self.event("terminated", Value::Null);
+            }
+            StopReason::CycleLimit => self.stopped("step", None),
+            StopReason::Breakpoint(_) => self.stopped("breakpoint", None),
+            StopReason::Watchpoint(_) => self.stopped("data breakpoint", None),
+            StopReason::Fault(fault) => self.stopped("exception", Some(fault.to_string())),
+            StopReason::Spin(_) => self.stopped("pause", Some(reason.to_string())),
+        }
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let bios_path = args
+            .get("bios")
+            .as_str()
+            .ok_or_else(|| String::from("Missing launch argument 'bios'"))?;
+        let (bios, bios_program) = load(bios_path)?;
+        let (image, image_program) = match args.get("memory").as_str() {
+            Some(path) => {
+                let (image, program) = load(path)?;
+                (image, program.map(|program| (path, program)))
+            }
+            None => (Box::new(SliceMemory::new(0)) as Box<dyn Memory>, None),
+        };
+
+        self.sources.clear();
+        if let Some(program) = bios_program {
+            self.sources.push(Source {
+                path: String::from(bios_path),
+                program,
+                mount_start: address::BIOS_START_ADDRESS,
+                is_bios: true,
+            });
+        }
+        if let Some((path, program)) = image_program {
+            self.sources.push(Source {
+                path: String::from(path),
+                program,
+                mount_start: 0,
+                is_bios: false,
+            });
+        }
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+        self.limits = RunLimits::default();
+        if let Some(max_cycles) = args.get("maxCycles").as_u64() {
+            self.limits.max_cycles = Some(max_cycles as usize);
+        }
+        self.breakpoints.clear();
+        self.emulator = Some(Emulator::new(bios, image));
+        Ok(Value::object(vec![]))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        self.emulator()?;
+        let path = args
+            .get("source")
+            .get("path")
+            .as_str()
+            .ok_or_else(|| String::from("Missing source path"))?;
+        let source = self.sources.iter().find(|s| same_file(&s.path, path));
+        let mut addresses = Vec::new();
+        let mut results = Vec::new();
+        for breakpoint in args.get("breakpoints").as_array().unwrap_or(&[]) {
+            let line = breakpoint.get("line").as_u64().unwrap_or(0) as usize;
+            let location = source.and_then(|source| {
+                source
+                    .program
+                    .line_from(line)
+                    .map(|l| (l.line, source.mount_start + l.offset))
+            });
+            results.push(match location {
+                Some((line, addr)) => {
+                    addresses.push(addr);
+                    Value::object(vec![
+                        ("verified", true.into()),
+                        ("line", line.into()),
+                        ("instructionReference", format!("0x{:08X}", addr).into()),
+                    ])
+                }
+                None => Value::object(vec![
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "No code at or after this line".into()),
+                ]),
+            });
+        }
+
+        let emulator = self.emulator.as_mut().unwrap();
+        for addr in self.breakpoints.remove(path).unwrap_or_default() {
+            emulator.remove_breakpoint(addr);
+        }
+        for addr in &addresses {
+            emulator.add_breakpoint(*addr);
+        }
+        // Breakpoints of other sources at the same address must survive.
+        for other in self.breakpoints.values().flatten() {
+            emulator.add_breakpoint(*other);
+        }
+        self.breakpoints.insert(String::from(path), addresses);
+        Ok(Value::object(vec![("breakpoints", results.into())]))
+    }
+
+    /// Finds the source line of the instruction at `addr`.
+    fn source_line(&self, addr: usize) -> Option<(&Source, usize)> {
+        let bios_enabled = !self.emulator().ok()?.is_flag_set(address::FLAG_BIOS_DONE);
+        self.sources
+            .iter()
+            .filter(|source| bios_enabled || !source.is_bios)
+            .filter(|source| addr >= source.mount_start)
+            .filter_map(|source| {
+                source
+                    .program
+                    .line_at(addr - source.mount_start)
+                    .map(|line| (source, line.line))
+            })
+            .next()
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let emulator = self.emulator()?;
+        let ip = emulator.ip;
+        let name = match disassembler::address_name(ip) {
+            Some(name) => name,
+            None => format!("0x{:08X}", ip),
+        };
+        let mut frame = vec![
+            ("id", 1usize.into()),
+            ("name", name.into()),
+            ("column", 1usize.into()),
+            (
+                "instructionPointerReference",
+                format!("0x{:08X}", ip).into(),
+            ),
+        ];
+        match self.source_line(ip) {
+            Some((source, line)) => {
+                let file_name = Path::new(&source.path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                frame.push(("line", line.into()));
+                frame.push((
+                    "source",
+                    Value::object(vec![
+                        ("name", file_name.into()),
+                        ("path", source.path.clone().into()),
+                    ]),
+                ));
+            }
+            None => frame.push(("line", 0usize.into())),
+        }
+        Ok(Value::object(vec![
+            ("stackFrames", vec![Value::object(frame)].into()),
+            ("totalFrames", 1usize.into()),
+        ]))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let emulator = self.emulator()?;
+        let reference = args.get("variablesReference").as_u64().unwrap_or(0) as usize;
+        let variables = match reference {
+            REGISTERS_REFERENCE => {
+                let mut variables = vec![variable("ip", format!("0x{:08X}", emulator.ip), 0)];
+                for idx in 0..address::NUM_REGISTERS {
+                    variables.push(word_variable(
+                        &format!("r{}", idx),
+                        emulator.get_register(idx),
+                    ));
+                }
+                variables.push(word_variable(
+                    "sp",
+                    emulator.memory.get(address::STACK_POINTER_ADDRESS),
+                ));
+                variables
+            }
+            FLAGS_REFERENCE => {
+                let mut flags = vec![
+                    ("halted", address::FLAG_HALTED),
+                    ("bios_done", address::FLAG_BIOS_DONE),
+                    ("interrupt_enable", address::FLAG_INTERRUPT_ENABLE),
+                    ("interrupt_return", address::FLAG_INTERRUPT_RETURN),
+                ]
+                .into_iter()
+                .map(|(name, flag)| variable(name, emulator.is_flag_set(flag).to_string(), 0))
+                .collect::<Vec<_>>();
+                for line in 0..address::NUM_INTERRUPT_LINES {
+                    let pending = emulator.is_flag_set(address::FLAG_INTERRUPT_PENDING + line);
+                    flags.push(variable(
+                        &format!("pending{}", line),
+                        pending.to_string(),
+                        0,
+                    ));
+                }
+                flags
+            }
+            MEMORY_REFERENCE => emulator
+                .memory
+                .mounts()
+                .iter()
+                .enumerate()
+                .filter(|&(_, mount)| mount.name != "null")
+                .map(|(idx, mount)| {
+                    variable(
+                        &mount.name,
+                        format!(
+                            "0x{:08X}-0x{:08X}{}",
+                            mount.start_address,
+                            mount.start_address + mount.size,
+                            if mount.enabled { "" } else { " (disabled)" }
+                        ),
+                        MOUNT_REFERENCE_BASE + idx,
+                    )
+                })
+                .collect(),
+            reference if reference >= MOUNT_REFERENCE_BASE => {
+                let mounts = emulator.memory.mounts();
+                let mount = mounts
+                    .get(reference - MOUNT_REFERENCE_BASE)
+                    .ok_or_else(|| String::from("Invalid variables reference"))?;
+                let memory = emulator.memory.borrow(&mount.token);
+                (0..mount.size.min(MAX_MOUNT_WORDS))
+                    .map(|offset| {
+                        let value = match memory.peek(offset) {
+                            Some(value) => format!("0x{:08X}", value),
+                            None => String::from("(not readable)"),
+                        };
+                        variable(&format!("0x{:08X}", mount.start_address + offset), value, 0)
+                    })
+                    .collect()
+            }
+            _ => return Err(String::from("Invalid variables reference")),
+        };
+        Ok(Value::object(vec![("variables", variables.into())]))
+    }
+}
+
+fn scope(name: &str, reference: usize, expensive: bool) -> Value {
+    Value::object(vec![
+        ("name", name.into()),
+        ("variablesReference", reference.into()),
+        ("expensive", expensive.into()),
+    ])
+}
+
+fn variable(name: &str, value: String, reference: usize) -> Value {
+    Value::object(vec![
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", reference.into()),
+    ])
+}
+
+fn word_variable(name: &str, value: i32) -> Value {
+    variable(name, format!("0x{:08X} ({})", value, value), 0)
+}
+
+/// Loads an image. `.asm` files are assembled here to keep their line mapping.
+fn load(path: &str) -> Result<(Box<dyn Memory>, Option<Program>), String> {
+    let error = |err: String| format!("Could not load {}: {}", path, err);
+    if Path::new(path).extension().and_then(|ext| ext.to_str()) != Some("asm") {
+        return load_file(Path::new(path))
+            .map(|memory| (memory, None))
+            .map_err(|err| error(err.to_string()));
+    }
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|err| error(err.to_string()))?;
+    let program = assembler::assemble(&source).map_err(|err| error(err.to_string()))?;
+    let memory = SliceMemory::from_slice(program.words.clone().into_boxed_slice());
+    Ok((Box::new(memory), Some(program)))
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    a == b
+        || match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Adapter, Flow};
+    use json::{self, Value};
+    use std::env;
+    use std::fs;
+
+    const BIOS: &str = "
+        .addr 0x40000000
+        ; Count register0 down from 2
+        two 0 register0 0
+        loop: register0 one register0 done
+        0 0 register1 loop
+        done: one 0 flags0 0
+        one: .dw 1
+        two: .dw 2
+        ";
+
+    fn request(adapter: &mut Adapter, seq: usize, command: &str, args: &str) -> Vec<Value> {
+        let request = json::parse(&format!(
+            r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+            seq, command, args
+        ))
+        .unwrap();
+        assert_eq!(adapter.handle(&request), Flow::Continue);
+        adapter.take_messages()
+    }
+
+    fn events(messages: &[Value]) -> Vec<String> {
+        messages
+            .iter()
+            .filter_map(|m| m.get("event").as_str())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn session() {
+        let path = env::temp_dir().join(format!("osci-dap-test-{}.asm", ::std::process::id()));
+        fs::write(&path, BIOS).unwrap();
+        let path = path.to_str().unwrap().replace('\\', "\\\\");
+        let mut adapter = Adapter::new();
+
+        let messages = request(&mut adapter, 1, "initialize", "{}");
+        assert_eq!(messages[0].get("success"), &Value::Bool(true));
+
+        let messages = request(
+            &mut adapter,
+            2,
+            "launch",
+            &format!(r#"{{"bios":"{}","stopOnEntry":true}}"#, path),
+        );
+        assert_eq!(messages[0].get("request_seq").as_u64(), Some(2));
+        assert_eq!(events(&messages), vec!["initialized"]);
+
+        let messages = request(
+            &mut adapter,
+            3,
+            "setBreakpoints",
+            &format!(
+                r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":6}},{{"line":99}}]}}"#,
+                path
+            ),
+        );
+        let breakpoints = messages[0]
+            .get("body")
+            .get("breakpoints")
+            .as_array()
+            .unwrap();
+        assert_eq!(breakpoints[0].get("line").as_u64(), Some(6));
+        assert_eq!(
+            breakpoints[0].get("instructionReference").as_str(),
+            Some("0x40000008")
+        );
+        assert_eq!(breakpoints[1].get("verified"), &Value::Bool(false));
+
+        let messages = request(&mut adapter, 4, "configurationDone", "{}");
+        assert_eq!(
+            messages[1].get("body").get("reason").as_str(),
+            Some("entry")
+        );
+
+        let messages = request(&mut adapter, 5, "variables", r#"{"variablesReference":1}"#);
+        let variables = messages[0].get("body").get("variables").as_array().unwrap();
+        assert_eq!(variables[0].get("value").as_str(), Some("0x40000000"));
+        assert_eq!(variables[1].get("name").as_str(), Some("r0"));
+
+        request(&mut adapter, 6, "setVariable", "{}");
+        let messages = request(&mut adapter, 7, "next", r#"{"threadId":1}"#);
+        assert_eq!(messages[1].get("body").get("reason").as_str(), Some("step"));
+
+        let messages = request(&mut adapter, 8, "stackTrace", r#"{"threadId":1}"#);
+        let frame = &messages[0]
+            .get("body")
+            .get("stackFrames")
+            .as_array()
+            .unwrap()[0];
+        assert_eq!(frame.get("line").as_u64(), Some(5));
+
+        let messages = request(&mut adapter, 9, "variables", r#"{"variablesReference":3}"#);
+        let mounts = messages[0].get("body").get("variables").as_array().unwrap();
+        let bios = mounts
+            .iter()
+            .find(|m| m.get("name").as_str() == Some("bios"))
+            .unwrap();
+        let reference = bios.get("variablesReference").as_u64().unwrap();
+        let messages = request(
+            &mut adapter,
+            10,
+            "variables",
+            &format!(r#"{{"variablesReference":{}}}"#, reference),
+        );
+        let words = messages[0].get("body").get("variables").as_array().unwrap();
+        assert_eq!(words[0].get("name").as_str(), Some("0x40000000"));
+        assert_eq!(words[0].get("value").as_str(), Some("0x40000014"));
+
+        let messages = request(&mut adapter, 11, "continue", r#"{"threadId":1}"#);
+        assert_eq!(events(&messages), vec!["stopped"]);
+        assert_eq!(
+            messages[1].get("body").get("reason").as_str(),
+            Some("breakpoint")
+        );
+        let messages = request(&mut adapter, 12, "continue", r#"{"threadId":1}"#);
+        assert_eq!(events(&messages), vec!["exited", "terminated"]);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn max_cycles() {
+        let path = env::temp_dir().join(format!("osci-dap-limits-{}.asm", ::std::process::id()));
+        fs::write(&path, BIOS).unwrap();
+        let path = path.to_str().unwrap().replace('\\', "\\\\");
+        let mut adapter = Adapter::new();
+
+        let launch = format!(r#"{{"bios":"{}","maxCycles":1}}"#, path);
+        request(&mut adapter, 1, "launch", &launch);
+        let messages = request(&mut adapter, 2, "configurationDone", "{}");
+        assert_eq!(messages[1].get("body").get("reason").as_str(), Some("step"));
+
+        // The limit of the previous launch does not apply.
+        request(
+            &mut adapter,
+            3,
+            "launch",
+            &format!(r#"{{"bios":"{}"}}"#, path),
+        );
+        let messages = request(&mut adapter, 4, "configurationDone", "{}");
+        assert_eq!(events(&messages), vec!["exited", "terminated"]);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn errors() {
+        let mut adapter = Adapter::new();
+        let messages = request(&mut adapter, 1, "threads", "{}");
+        assert_eq!(messages[0].get("success"), &Value::Bool(true));
+        let messages = request(&mut adapter, 2, "stackTrace", "{}");
+        assert_eq!(messages[0].get("success"), &Value::Bool(false));
+        let messages = request(&mut adapter, 3, "launch", r#"{"bios":"/nonexistent.asm"}"#);
+        assert!(messages[0]
+            .get("message")
+            .as_str()
+            .unwrap()
+            .starts_with("Could not load /nonexistent.asm"));
+        let messages = request(&mut adapter, 4, "pause", r#"{"threadId":1}"#);
+        assert_eq!(messages[0].get("success"), &Value::Bool(false));
+    }
+}
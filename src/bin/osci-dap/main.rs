@@ -0,0 +1,110 @@
+//! Debug Adapter Protocol server for osci.
+//!
+//! `osci-dap` speaks DAP over stdin and stdout and can be registered as a debug adapter in editors like VS Code. The `launch` request accepts the following arguments:
+//!
+//! - `bios` (required): BIOS image to load
+//! - `memory`: Memory image to load
+//! - `stopOnEntry`: Stop before the first instruction is executed
+//! - `maxCycles`: Stop after executing this many cycles on `continue`
+//!
+//! Images ending in `.asm` are assembled by the adapter so breakpoints can be set on source lines.
+extern crate osciemu;
+
+mod adapter;
+mod json;
+
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use adapter::{Adapter, Flow};
+
+/// Reads a single message. Returns `None` at the end of the input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header")
+    })?;
+    let mut body = vec![0u8; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Message is not UTF-8"))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &json::Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn serve<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<()> {
+    let mut adapter = Adapter::new();
+    while let Some(body) = read_message(input)? {
+        let request = match json::parse(&body) {
+            Ok(request) => request,
+            Err(err) => {
+                eprintln!("Ignoring invalid message: {}", err);
+                continue;
+            }
+        };
+        let flow = adapter.handle(&request);
+        for message in adapter.take_messages() {
+            write_message(output, &message)?;
+        }
+        if flow == Flow::Quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(err) = serve(&mut stdin.lock(), &mut stdout.lock()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+
+    #[test]
+    fn framing() {
+        let mut input = Vec::new();
+        for body in &[
+            r#"{"seq":1,"type":"request","command":"initialize"}"#,
+            r#"{"seq":2,"type":"request","command":"disconnect"}"#,
+            r#"{"seq":3,"type":"request","command":"threads"}"#,
+        ] {
+            input.extend_from_slice(
+                format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes(),
+            );
+        }
+        let mut output = Vec::new();
+        serve(&mut input.as_slice(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let bodies: Vec<&str> = output.split("Content-Length: ").skip(1).collect();
+        assert_eq!(bodies.len(), 2);
+        assert!(bodies[0].contains(r#""command":"initialize""#));
+        assert!(bodies[1].contains(r#""command":"disconnect""#));
+        let header_end = bodies[1].find("\r\n\r\n").unwrap();
+        let length: usize = bodies[1][..header_end].parse().unwrap();
+        assert_eq!(bodies[1].len(), header_end + 4 + length);
+    }
+}
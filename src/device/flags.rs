@@ -0,0 +1,117 @@
+//! The flags words.
+//!
+//! The `Flags` device holds the flags words at `FLAGS_START_ADDRESS` and implements the behavior of the `bD` flag. See the `memory` module for a description of the individual flags.
+use device::Device;
+use memory::mappedmemory::MemoryToken;
+use memory::{address, MappedMemory, Memory};
+
+/// Flags words that mount and unmount the BIOS according to the `bD` flag.
+#[derive(Clone)]
+pub struct Flags {
+    words: [i32; address::NUM_FLAGS],
+    bios_memory_token: MemoryToken,
+}
+
+impl Flags {
+    /// Creates the flags for a BIOS mounted with the given token.
+    pub fn new(bios_memory_token: MemoryToken) -> Flags {
+        Flags {
+            words: [0; address::NUM_FLAGS],
+            bios_memory_token,
+        }
+    }
+
+    /// Checks if a flag is set.
+    pub fn is_set(&self, flag_idx: usize) -> bool {
+        self.words[flag_idx / 32] & (1 << (flag_idx % 32)) != 0
+    }
+
+    /// Checks if the halted flag is set.
+    pub fn is_halted(&self) -> bool {
+        self.is_set(address::FLAG_HALTED)
+    }
+}
+
+impl Memory for Flags {
+    fn get(&self, addr: usize) -> i32 {
+        self.words[addr]
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        self.words[addr] = value;
+    }
+
+    fn size(&self) -> usize {
+        address::NUM_FLAGS
+    }
+}
+
+impl Device for Flags {
+    fn name(&self) -> &str {
+        "flags"
+    }
+
+    fn is_idle(&self, memory: &MappedMemory) -> bool {
+        self.is_set(address::FLAG_BIOS_DONE) != memory.is_enabled_mount(&self.bios_memory_token)
+    }
+
+    fn fork_device(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn save_state(&self) -> Vec<i32> {
+        self.words.to_vec()
+    }
+
+    fn load_state(&mut self, state: &[i32]) -> Result<(), String> {
+        if state.len() != self.words.len() {
+            return Err(String::from("Invalid flags state"));
+        }
+        self.words.copy_from_slice(state);
+        Ok(())
+    }
+
+    fn tick(&mut self, _cycles: usize, memory: &mut MappedMemory) {
+        let bios_done = self.is_set(address::FLAG_BIOS_DONE);
+        let bios_mounted = memory.is_enabled_mount(&self.bios_memory_token);
+        if bios_done && bios_mounted {
+            memory.disable_mount(&self.bios_memory_token);
+        } else if !bios_done && !bios_mounted {
+            memory.enable_mount(&self.bios_memory_token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use device::Device;
+    use memory::{address, MappedMemory, Memory, NullMemory, SliceMemory};
+
+    #[test]
+    fn toggles_bios() {
+        let mut mm = MappedMemory::new();
+        mm.mount(0, Box::new(NullMemory::new())).unwrap();
+        let bios = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1])))).unwrap();
+        let mut flags = super::Flags::new(bios.clone());
+
+        flags.set(0, 1 << address::FLAG_BIOS_DONE);
+        flags.tick(1, &mut mm);
+        assert!(!mm.is_enabled_mount(&bios));
+        assert_eq!(mm.get(0), 0);
+
+        flags.set(0, 0);
+        flags.tick(1, &mut mm);
+        assert!(mm.is_enabled_mount(&bios));
+        assert_eq!(mm.get(0), 1);
+    }
+
+    #[test]
+    fn is_halted() {
+        let mut mm = MappedMemory::new();
+        let bios = mm.mount(0, Box::new(NullMemory::new())).unwrap();
+        let mut flags = super::Flags::new(bios);
+        assert!(!flags.is_halted());
+        flags.set(0, 1 << address::FLAG_HALTED);
+        assert!(flags.is_halted());
+    }
+}
@@ -0,0 +1,164 @@
+//! Memory-mapped peripherals.
+//!
+//! A peripheral implements the `Device` trait and is attached to the emulator using `Emulator::attach_device()`. The emulator mounts the device into its memory and calls `tick()` after every cycle. `Emulator::run()` leaves out the ticks of idle devices (see `Device::is_idle()`).
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::device::Device;
+//! # use osciemu::emulator::Emulator;
+//! # use osciemu::memory::{Memory, MappedMemory, SliceMemory};
+//! // A device that counts the cycles since it has been attached.
+//! struct CycleCounter(i32);
+//!
+//! impl Memory for CycleCounter {
+//!     fn get(&self, _addr: usize) -> i32 {
+//!         self.0
+//!     }
+//!     fn set(&mut self, _addr: usize, value: i32) {
+//!         self.0 = value;
+//!     }
+//!     fn size(&self) -> usize {
+//!         1
+//!     }
+//! }
+//!
+//! impl Device for CycleCounter {
+//!     fn tick(&mut self, cycles: usize, _memory: &mut MappedMemory) {
+//!         self.0 += cycles as i32;
+//!     }
+//! }
+//!
+//! let mut emu = Emulator::from_bios_only(Box::new(SliceMemory::new(4)));
+//! emu.attach_device(0x100, CycleCounter(0));
+//! emu.step();
+//! emu.step();
+//! assert_eq!(emu.memory.get(0x100), 2);
+//! ```
+pub mod flags;
+pub mod timer;
+pub mod uart;
+
+pub use self::flags::Flags;
+pub use self::timer::Timer;
+pub use self::uart::Uart;
+
+use memory::{MappedMemory, Memory, Result};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::result;
+
+/// A memory-mapped peripheral.
+///
+/// The `Memory` implementation provides access to the device’s registers. Addresses are relative to the address the device has been attached at.
+pub trait Device: Memory {
+    /// Returns a short name of the device used to describe the memory layout.
+    fn name(&self) -> &str {
+        "device"
+    }
+
+    /// Advances the device by the given number of cycles.
+    ///
+    /// `memory` is the emulator’s memory the device is mounted in. The device must not access its own registers through `memory` as it is borrowed mutably during the call.
+    fn tick(&mut self, _cycles: usize, _memory: &mut MappedMemory) {}
+
+    /// Returns the interrupt line the device wants to raise, if any.
+    ///
+    /// This is called after every `tick()`. A device should only return a line once for every event.
+    fn interrupt(&mut self) -> Option<usize> {
+        None
+    }
+
+    /// Checks if `tick()` won’t do anything until the device’s registers are written.
+    ///
+    /// `memory` is the emulator’s memory the device is mounted in. `Emulator::run()` doesn’t tick idle devices as long as no instruction writes to a device. The default implementation returns `false`, so the device is ticked after every cycle.
+    fn is_idle(&self, _memory: &MappedMemory) -> bool {
+        false
+    }
+
+    /// Returns the device’s internal state to be stored in a snapshot.
+    ///
+    /// The default implementation returns an empty state, which is right for stateless devices.
+    fn save_state(&self) -> Vec<i32> {
+        Vec::new()
+    }
+
+    /// Restores a state returned by `save_state()`.
+    ///
+    /// Fails with a description of the problem if `state` is not a valid state of this device.
+    fn load_state(&mut self, state: &[i32]) -> result::Result<(), String> {
+        if state.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Unexpected state for {}", self.name()))
+        }
+    }
+
+    /// Returns an independent copy of the device for `Emulator::fork()`.
+    ///
+    /// The default implementation returns `None`, so emulators with this device can’t be forked. Devices connected to the host (like the `Uart`) should keep it that way.
+    fn fork_device(&self) -> Option<Box<dyn Device>> {
+        None
+    }
+}
+
+impl<D: Device + ?Sized> Device for Box<D> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn tick(&mut self, cycles: usize, memory: &mut MappedMemory) {
+        (**self).tick(cycles, memory)
+    }
+
+    fn interrupt(&mut self) -> Option<usize> {
+        (**self).interrupt()
+    }
+
+    fn is_idle(&self, memory: &MappedMemory) -> bool {
+        (**self).is_idle(memory)
+    }
+
+    fn save_state(&self) -> Vec<i32> {
+        (**self).save_state()
+    }
+
+    fn load_state(&mut self, state: &[i32]) -> result::Result<(), String> {
+        (**self).load_state(state)
+    }
+
+    fn fork_device(&self) -> Option<Box<dyn Device>> {
+        (**self).fork_device()
+    }
+}
+
+/// A `Memory` that forwards all accesses to a shared `Device`.
+///
+/// This allows a device to be mounted in a `MappedMemory` while the emulator keeps a handle to tick it.
+pub struct DeviceMemory(pub Rc<RefCell<dyn Device>>);
+
+impl Memory for DeviceMemory {
+    fn get(&self, addr: usize) -> i32 {
+        self.0.borrow().get(addr)
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        self.0.borrow_mut().set(addr, value)
+    }
+
+    fn size(&self) -> usize {
+        self.0.borrow().size()
+    }
+
+    fn try_get(&self, addr: usize) -> Result<i32> {
+        self.0.borrow().try_get(addr)
+    }
+
+    fn try_set(&mut self, addr: usize, value: i32) -> Result<()> {
+        self.0.borrow_mut().try_set(addr, value)
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        self.0.borrow().peek(addr)
+    }
+}
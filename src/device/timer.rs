@@ -0,0 +1,221 @@
+//! Programmable interval timer.
+//!
+//! The timer occupies three words:
+//!
+//! - Counter (`TIMER_COUNTER_ADDRESS`): Decremented by one every cycle while the timer is enabled. The timer expires when the counter reaches 0.
+//! - Reload (`TIMER_RELOAD_ADDRESS`): Value the counter is reset to when a periodic timer expires.
+//! - Control (`TIMER_CONTROL_ADDRESS`): See the `CONTROL_*` constants for the meaning of the individual bits.
+//!
+//! When the timer expires, it sets `CONTROL_EXPIRED` and, if `CONTROL_INTERRUPT` is set, raises its interrupt line. A one-shot timer clears `CONTROL_ENABLE` on expiry. A periodic timer reloads the counter and keeps running.
+//!
+//! The timer only counts emulated cycles and is therefore fully deterministic: A counter value of `n` written before the cycle that enables the timer expires at the end of the `n`th cycle, counting the enabling cycle.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::device::{Device, Timer};
+//! # use osciemu::device::timer;
+//! # use osciemu::memory::{MappedMemory, Memory};
+//! # let mut mm = MappedMemory::new();
+//! let mut t = Timer::new(0);
+//! t.set(0, 2);
+//! t.set(2, timer::CONTROL_ENABLE | timer::CONTROL_INTERRUPT);
+//! t.tick(1, &mut mm);
+//! assert_eq!(t.interrupt(), None);
+//! t.tick(1, &mut mm);
+//! assert_eq!(t.interrupt(), Some(0));
+//! assert!(t.get(2) & timer::CONTROL_EXPIRED != 0);
+//! ```
+use device::Device;
+use memory::{MappedMemory, Memory};
+
+/// Control bit that enables the timer.
+pub const CONTROL_ENABLE: i32 = 1 << 0;
+/// Control bit that makes the timer reload the counter on expiry instead of stopping.
+pub const CONTROL_PERIODIC: i32 = 1 << 1;
+/// Control bit that makes the timer raise an interrupt on expiry.
+pub const CONTROL_INTERRUPT: i32 = 1 << 2;
+/// Control bit that is set when the timer expires. Must be cleared by the guest.
+pub const CONTROL_EXPIRED: i32 = 1 << 3;
+
+const COUNTER: usize = 0;
+const RELOAD: usize = 1;
+const CONTROL: usize = 2;
+
+/// A timer that counts down emulated cycles.
+#[derive(Clone)]
+pub struct Timer {
+    words: [i32; 3],
+    line: usize,
+    interrupt_pending: bool,
+}
+
+impl Timer {
+    /// Creates a new, disabled timer that raises interrupts on the given line.
+    pub fn new(line: usize) -> Timer {
+        Timer {
+            words: [0; 3],
+            line,
+            interrupt_pending: false,
+        }
+    }
+
+    fn expire(&mut self) {
+        let control = self.words[CONTROL];
+        if control & CONTROL_INTERRUPT != 0 {
+            self.interrupt_pending = true;
+        }
+        if control & CONTROL_PERIODIC != 0 {
+            self.words[COUNTER] = self.words[RELOAD];
+            self.words[CONTROL] |= CONTROL_EXPIRED;
+        } else {
+            self.words[CONTROL] = (control | CONTROL_EXPIRED) & !CONTROL_ENABLE;
+        }
+    }
+}
+
+impl Memory for Timer {
+    fn get(&self, addr: usize) -> i32 {
+        self.words[addr]
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        self.words[addr] = value;
+    }
+
+    fn size(&self) -> usize {
+        self.words.len()
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn is_idle(&self, _memory: &MappedMemory) -> bool {
+        self.words[CONTROL] & CONTROL_ENABLE == 0 && !self.interrupt_pending
+    }
+
+    fn fork_device(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn tick(&mut self, cycles: usize, _memory: &mut MappedMemory) {
+        for _ in 0..cycles {
+            if self.words[CONTROL] & CONTROL_ENABLE == 0 {
+                return;
+            }
+            if self.words[COUNTER] > 0 {
+                self.words[COUNTER] -= 1;
+            }
+            if self.words[COUNTER] <= 0 {
+                self.expire();
+            }
+        }
+    }
+
+    fn interrupt(&mut self) -> Option<usize> {
+        if self.interrupt_pending {
+            self.interrupt_pending = false;
+            Some(self.line)
+        } else {
+            None
+        }
+    }
+
+    fn save_state(&self) -> Vec<i32> {
+        let mut state = self.words.to_vec();
+        state.push(self.interrupt_pending as i32);
+        state
+    }
+
+    fn load_state(&mut self, state: &[i32]) -> Result<(), String> {
+        if state.len() != self.words.len() + 1 {
+            return Err(String::from("Invalid timer state"));
+        }
+        self.words.copy_from_slice(&state[..3]);
+        self.interrupt_pending = state[3] != 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use device::Device;
+    use memory::{MappedMemory, Memory};
+
+    #[test]
+    fn disabled() {
+        let mut mm = MappedMemory::new();
+        let mut t = super::Timer::new(0);
+        t.set(0, 2);
+        t.tick(10, &mut mm);
+        assert_eq!(t.get(0), 2);
+        assert_eq!(t.interrupt(), None);
+    }
+
+    #[test]
+    fn one_shot() {
+        let mut mm = MappedMemory::new();
+        let mut t = super::Timer::new(3);
+        t.set(0, 3);
+        t.set(2, super::CONTROL_ENABLE | super::CONTROL_INTERRUPT);
+        t.tick(2, &mut mm);
+        assert_eq!(t.get(0), 1);
+        assert_eq!(t.interrupt(), None);
+        t.tick(1, &mut mm);
+        assert_eq!(t.interrupt(), Some(3));
+        assert_eq!(t.interrupt(), None);
+        assert_eq!(t.get(2), super::CONTROL_EXPIRED | super::CONTROL_INTERRUPT);
+        t.tick(5, &mut mm);
+        assert_eq!(t.interrupt(), None);
+    }
+
+    #[test]
+    fn periodic() {
+        let mut mm = MappedMemory::new();
+        let mut t = super::Timer::new(0);
+        t.set(0, 2);
+        t.set(1, 4);
+        t.set(
+            2,
+            super::CONTROL_ENABLE | super::CONTROL_PERIODIC | super::CONTROL_INTERRUPT,
+        );
+        let expired: Vec<usize> = (1..12)
+            .filter(|_| {
+                t.tick(1, &mut mm);
+                t.interrupt().is_some()
+            })
+            .collect();
+        assert_eq!(expired, vec![2, 6, 10]);
+        assert!(t.get(2) & super::CONTROL_ENABLE != 0);
+    }
+
+    #[test]
+    fn without_interrupt() {
+        let mut mm = MappedMemory::new();
+        let mut t = super::Timer::new(0);
+        t.set(0, 1);
+        t.set(2, super::CONTROL_ENABLE);
+        t.tick(1, &mut mm);
+        assert_eq!(t.interrupt(), None);
+        assert_eq!(t.get(2), super::CONTROL_EXPIRED);
+    }
+
+    #[test]
+    fn state() {
+        let mut mm = MappedMemory::new();
+        let mut t = super::Timer::new(0);
+        t.set(0, 1);
+        t.set(2, super::CONTROL_ENABLE | super::CONTROL_INTERRUPT);
+        t.tick(1, &mut mm);
+        let state = t.save_state();
+
+        let mut t2 = super::Timer::new(0);
+        assert!(t2.load_state(&[1]).is_err());
+        t2.load_state(&state).unwrap();
+        assert_eq!(t2.get(2), super::CONTROL_EXPIRED | super::CONTROL_INTERRUPT);
+        assert_eq!(t2.interrupt(), Some(0));
+    }
+}
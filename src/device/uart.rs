@@ -0,0 +1,200 @@
+//! UART-style character device.
+//!
+//! The UART occupies two words:
+//!
+//! - Data word (`UART_DATA_ADDRESS`): Writing a value sends its lowest byte to the output. Reading returns the next byte of the input or -1 if the input has been exhausted.
+//! - Status word (`UART_STATUS_ADDRESS`): Read-only. See the `STATUS_*` constants for the meaning of the individual bits.
+//!
+//! Reading the data word consumes a byte of the input, even if the read is done by the host (e.g. when dumping memory). `peek()` doesn’t support the data word for that reason. Reading from an interactive input blocks until a byte is available.
+//!
+//! The state saved in a snapshot only consists of the status bits, not the position in the input or output.
+//!
+//! # Examples
+//!
+//! ```
+//! # use std::io::{self, Cursor};
+//! # use osciemu::memory::Memory;
+//! # use osciemu::device::uart::{self, Uart};
+//! let mut uart = Uart::new(Box::new(Cursor::new(vec![b'A'])), Box::new(io::sink()));
+//! assert_eq!(uart.get(0), b'A' as i32);
+//! assert_eq!(uart.get(0), -1);
+//! assert!(uart.get(1) & uart::STATUS_EOF != 0);
+//! ```
+use device::Device;
+use memory::{MappedMemory, Memory};
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read, Write};
+
+/// Status bit that is set when the UART can accept another byte.
+///
+/// The UART writes synchronously, so this bit is always set.
+pub const STATUS_TX_READY: i32 = 1 << 0;
+/// Status bit that is set once a read hit the end of the input.
+pub const STATUS_EOF: i32 = 1 << 1;
+/// Status bit that is set when writing to the output failed.
+pub const STATUS_TX_ERROR: i32 = 1 << 2;
+
+/// A character device backed by a host input and output stream.
+pub struct Uart {
+    input: RefCell<Box<dyn Read>>,
+    output: Box<dyn Write>,
+    eof: Cell<bool>,
+    tx_error: bool,
+}
+
+impl Uart {
+    /// Creates a new `Uart` that reads from `input` and writes to `output`.
+    pub fn new(input: Box<dyn Read>, output: Box<dyn Write>) -> Uart {
+        Uart {
+            input: RefCell::new(input),
+            output,
+            eof: Cell::new(false),
+            tx_error: false,
+        }
+    }
+
+    /// Creates a new `Uart` that is connected to the host’s stdin and stdout.
+    pub fn stdio() -> Uart {
+        Uart::new(Box::new(io::stdin()), Box::new(io::stdout()))
+    }
+
+    fn read_byte(&self) -> i32 {
+        if self.eof.get() {
+            return -1;
+        }
+        let mut buf = [0u8; 1];
+        loop {
+            match self.input.borrow_mut().read(&mut buf) {
+                Ok(1) => return buf[0] as i32,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                _ => {
+                    self.eof.set(true);
+                    return -1;
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> i32 {
+        let mut status = STATUS_TX_READY;
+        if self.eof.get() {
+            status |= STATUS_EOF;
+        }
+        if self.tx_error {
+            status |= STATUS_TX_ERROR;
+        }
+        status
+    }
+}
+
+impl Memory for Uart {
+    fn get(&self, addr: usize) -> i32 {
+        match addr {
+            0 => self.read_byte(),
+            _ => self.status(),
+        }
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        if addr != 0 {
+            return;
+        }
+        let result = self
+            .output
+            .write_all(&[value as u8])
+            .and_then(|_| self.output.flush());
+        if result.is_err() {
+            self.tx_error = true;
+        }
+    }
+
+    fn size(&self) -> usize {
+        2
+    }
+
+    /// The data word can’t be peeked as reading it consumes input.
+    fn peek(&self, addr: usize) -> Option<i32> {
+        match addr {
+            1 => Some(self.status()),
+            _ => None,
+        }
+    }
+}
+
+impl Device for Uart {
+    fn name(&self) -> &str {
+        "uart"
+    }
+
+    fn is_idle(&self, _memory: &MappedMemory) -> bool {
+        true
+    }
+
+    fn save_state(&self) -> Vec<i32> {
+        vec![self.eof.get() as i32, self.tx_error as i32]
+    }
+
+    fn load_state(&mut self, state: &[i32]) -> Result<(), String> {
+        if state.len() != 2 {
+            return Err(String::from("Invalid uart state"));
+        }
+        self.eof.set(state[0] != 0);
+        self.tx_error = state[1] != 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use std::cell::RefCell;
+    use std::io::{self, Cursor, Write};
+    use std::rc::Rc;
+
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read() {
+        let input = Cursor::new(b"hi".to_vec());
+        let uart = super::Uart::new(Box::new(input), Box::new(io::sink()));
+        assert_eq!(uart.get(1) & super::STATUS_EOF, 0);
+        assert_eq!(uart.get(0), b'h' as i32);
+        assert_eq!(uart.get(0), b'i' as i32);
+        assert_eq!(uart.get(0), -1);
+        assert_eq!(uart.get(1) & super::STATUS_EOF, super::STATUS_EOF);
+    }
+
+    #[test]
+    fn peek() {
+        let input = Cursor::new(b"h".to_vec());
+        let uart = super::Uart::new(Box::new(input), Box::new(io::sink()));
+        assert_eq!(uart.peek(0), None);
+        assert_eq!(uart.peek(1), Some(super::STATUS_TX_READY));
+        assert_eq!(uart.get(0), b'h' as i32);
+    }
+
+    #[test]
+    fn write() {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let mut uart = super::Uart::new(
+            Box::new(io::empty()),
+            Box::new(SharedBuffer(buffer.clone())),
+        );
+        assert_eq!(uart.get(1) & super::STATUS_TX_READY, super::STATUS_TX_READY);
+        uart.set(0, b'o' as i32);
+        uart.set(0, 0x100 + b'k' as i32);
+        // Writes to the status word are ignored.
+        uart.set(1, b'!' as i32);
+        assert_eq!(&buffer.borrow()[..], b"ok");
+    }
+}
@@ -0,0 +1,317 @@
+//! Disassembler for osci instructions.
+//!
+//! The disassembler renders instructions as text. Indirect operands are shown as `*addr` and addresses of the control registers are replaced by their symbolic names (see `address_name()`). Common idioms are annotated with a comment. Instructions that can’t be read yield the `Fault` of fetching them.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::memory::{address, SliceMemory};
+//! # use osciemu::disassembler;
+//! let r0 = address::REGISTERS_START_ADDRESS as i32;
+//! let mem = SliceMemory::from_slice(Box::new([
+//!     r0, r0, r0, 4,
+//!     8, 8, 0, 0,
+//!     -r0, 9, 10, 11,
+//! ]));
+//! let lines: Vec<String> = disassembler::disassemble_range(&mem, 0, 12)
+//!     .iter()
+//!     .map(|d| d.as_ref().unwrap().to_string())
+//!     .collect();
+//! assert_eq!(lines[0], "0x00000000: r0 r0 r0 0x00000004 ; clear r0");
+//! assert_eq!(lines[1], "0x00000004: 0x00000008 0x00000008 0x00000000 0x00000000 ; jump 0x00000000");
+//! assert_eq!(lines[2], "0x00000008: *r0 0x00000009 0x0000000A 0x0000000B");
+//! ```
+use instruction::{self, Instruction, INSTRUCTION_SIZE};
+use memory::{address, Memory};
+use std::fmt;
+use symbols::Symbols;
+
+/// Common instruction patterns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Idiom {
+    /// `x x t j`: The result is always 0, so the jump is always taken.
+    Jump(i32),
+    /// `x x a next`: Sets `a` to 0 and continues with the next instruction. Usually written as `a a a next`.
+    Clear(i32),
+}
+
+/// A disassembled instruction.
+pub struct Disassembly {
+    /// Address of the instruction
+    pub address: usize,
+    /// The decoded instruction
+    pub instruction: Instruction,
+    /// The idiom the instruction matches, if any
+    pub idiom: Option<Idiom>,
+}
+
+/// Returns the symbolic name of a control register or peripheral address.
+pub fn address_name(addr: usize) -> Option<String> {
+    if addr == address::STACK_POINTER_ADDRESS {
+        return Some(String::from("sp"));
+    }
+    if addr == address::TIMER_COUNTER_ADDRESS {
+        return Some(String::from("timer_counter"));
+    }
+    if addr == address::TIMER_RELOAD_ADDRESS {
+        return Some(String::from("timer_reload"));
+    }
+    if addr == address::TIMER_CONTROL_ADDRESS {
+        return Some(String::from("timer_control"));
+    }
+    if addr == address::UART_DATA_ADDRESS {
+        return Some(String::from("uart_data"));
+    }
+    if addr == address::UART_STATUS_ADDRESS {
+        return Some(String::from("uart_status"));
+    }
+    if (address::REGISTERS_START_ADDRESS..address::IVT_START_ADDRESS).contains(&addr) {
+        return Some(format!("r{}", addr - address::REGISTERS_START_ADDRESS));
+    }
+    if (address::IVT_START_ADDRESS..address::FLAGS_START_ADDRESS).contains(&addr) {
+        return Some(format!("ivt{}", addr - address::IVT_START_ADDRESS));
+    }
+    if (address::FLAGS_START_ADDRESS..address::FLAGS_START_ADDRESS + address::NUM_FLAGS)
+        .contains(&addr)
+    {
+        return Some(format!("flags{}", addr - address::FLAGS_START_ADDRESS));
+    }
+    None
+}
+
+/// Returns the address of a symbolic name as returned by `address_name()`.
+pub fn address_by_name(name: &str) -> Option<usize> {
+    let indexed = |prefix: &str, start: usize, count: usize| {
+        name.strip_prefix(prefix)
+            .and_then(|idx| idx.parse::<usize>().ok())
+            .filter(|&idx| idx < count)
+            .map(|idx| start + idx)
+    };
+    match name {
+        "sp" => Some(address::STACK_POINTER_ADDRESS),
+        "timer_counter" => Some(address::TIMER_COUNTER_ADDRESS),
+        "timer_reload" => Some(address::TIMER_RELOAD_ADDRESS),
+        "timer_control" => Some(address::TIMER_CONTROL_ADDRESS),
+        "uart_data" => Some(address::UART_DATA_ADDRESS),
+        "uart_status" => Some(address::UART_STATUS_ADDRESS),
+        _ => indexed("r", address::REGISTERS_START_ADDRESS, address::NUM_REGISTERS)
+            .or_else(|| indexed("ivt", address::IVT_START_ADDRESS, address::NUM_IVT_ENTRIES))
+            .or_else(|| indexed("flags", address::FLAGS_START_ADDRESS, address::NUM_FLAGS)),
+    }
+}
+
+/// Formats an address using its symbolic name if it has one.
+pub fn format_address(addr: usize) -> String {
+    address_name(addr).unwrap_or_else(|| format!("0x{:08X}", addr))
+}
+
+/// Formats an operand. Indirect operands are prefixed with `*`.
+pub fn format_operand(op: i32) -> String {
+    if op < 0 {
+        format!("*{}", format_address(op.wrapping_neg() as u32 as usize))
+    } else {
+        format_address(op as usize)
+    }
+}
+
+/// Checks if an instruction at `addr` matches one of the known idioms.
+pub fn idiom(addr: usize, instr: &Instruction) -> Option<Idiom> {
+    if instr.op_a != instr.op_b {
+        return None;
+    }
+    if instr.jmp >= 0 && instr.jmp as usize == addr + INSTRUCTION_SIZE {
+        return Some(Idiom::Clear(instr.target));
+    }
+    Some(Idiom::Jump(instr.jmp))
+}
+
+/// Disassembles the instruction at the given address.
+pub fn disassemble(addr: usize, mem: &dyn Memory) -> instruction::Result<Disassembly> {
+    let instruction = Instruction::try_from_memory(addr, mem)?;
+    Ok(Disassembly {
+        address: addr,
+        idiom: idiom(addr, &instruction),
+        instruction,
+    })
+}
+
+/// Disassembles all instructions starting at `start` up to (excluding) `end`.
+pub fn disassemble_range(
+    mem: &dyn Memory,
+    start: usize,
+    end: usize,
+) -> Vec<instruction::Result<Disassembly>> {
+    (start..end)
+        .step_by(INSTRUCTION_SIZE)
+        .map(|addr| disassemble(addr, mem))
+        .collect()
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:08X}: {} {} {} {}",
+            self.address,
+            format_operand(self.instruction.op_a),
+            format_operand(self.instruction.op_b),
+            format_operand(self.instruction.target),
+            format_operand(self.instruction.jmp)
+        )?;
+        match self.idiom {
+            Some(Idiom::Jump(jmp)) => write!(f, " ; jump {}", format_operand(jmp)),
+            Some(Idiom::Clear(target)) => write!(f, " ; clear {}", format_operand(target)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Disassembly {
+    /// Returns a value that formats the disassembly using `symbols` for the address and the operands.
+    pub fn with_symbols<'a>(&'a self, symbols: &'a Symbols) -> WithSymbols<'a> {
+        WithSymbols {
+            disassembly: self,
+            symbols,
+        }
+    }
+}
+
+/// A `Disassembly` formatted with symbols, see `Disassembly::with_symbols()`.
+///
+/// Labeled addresses are followed by the label in angle brackets, e.g. `0x40000008 <loop>:`.
+pub struct WithSymbols<'a> {
+    disassembly: &'a Disassembly,
+    symbols: &'a Symbols,
+}
+
+impl<'a> fmt::Display for WithSymbols<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let d = self.disassembly;
+        let s = self.symbols;
+        write!(f, "0x{:08X}", d.address)?;
+        if s.lookup(d.address).is_some() {
+            write!(f, " <{}>", s.format(d.address))?;
+        }
+        write!(
+            f,
+            ": {} {} {} {}",
+            s.format_operand(d.instruction.op_a),
+            s.format_operand(d.instruction.op_b),
+            s.format_operand(d.instruction.target),
+            s.format_operand(d.instruction.jmp)
+        )?;
+        match d.idiom {
+            Some(Idiom::Jump(jmp)) => write!(f, " ; jump {}", s.format_operand(jmp)),
+            Some(Idiom::Clear(target)) => write!(f, " ; clear {}", s.format_operand(target)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use instruction::{FaultKind, Instruction};
+    use memory::{address, SliceMemory};
+    use symbols::Symbols;
+
+    #[test]
+    fn address_names() {
+        assert_eq!(
+            super::address_name(address::STACK_POINTER_ADDRESS),
+            Some(String::from("sp"))
+        );
+        assert_eq!(
+            super::address_name(address::REGISTERS_START_ADDRESS + 3),
+            Some(String::from("r3"))
+        );
+        assert_eq!(
+            super::address_name(address::IVT_START_ADDRESS),
+            Some(String::from("ivt0"))
+        );
+        assert_eq!(
+            super::address_name(address::FLAGS_START_ADDRESS),
+            Some(String::from("flags0"))
+        );
+        assert_eq!(super::address_name(0), None);
+    }
+
+    #[test]
+    fn addresses_by_name() {
+        for addr in address::CONTROLS_ADDRESS..address::MAX_ADDRESS + 1 {
+            if let Some(name) = super::address_name(addr) {
+                assert_eq!(super::address_by_name(&name), Some(addr));
+            }
+        }
+        assert_eq!(super::address_by_name("r4"), None);
+        assert_eq!(super::address_by_name("foo"), None);
+    }
+
+    #[test]
+    fn operands() {
+        let r1 = address::REGISTERS_START_ADDRESS as i32 + 1;
+        assert_eq!(super::format_operand(r1), "r1");
+        assert_eq!(super::format_operand(-r1), "*r1");
+        assert_eq!(super::format_operand(0x10), "0x00000010");
+        assert_eq!(super::format_operand(-0x10), "*0x00000010");
+    }
+
+    #[test]
+    fn with_symbols() {
+        let r0 = address::REGISTERS_START_ADDRESS as i32;
+        let mem = SliceMemory::from_slice(Box::new([r0, 8, r0, 0, 0, 0, 0, 4, 1]));
+        let mut symbols = Symbols::new();
+        symbols.add_label(0, "loop");
+        symbols.add_label(8, "one");
+        let lines: Vec<String> = super::disassemble_range(&mem, 0, 8)
+            .iter()
+            .map(|d| d.as_ref().unwrap().with_symbols(&symbols).to_string())
+            .collect();
+        assert_eq!(lines[0], "0x00000000 <loop>: r0 one r0 loop");
+        assert_eq!(lines[1], "0x00000004 <loop+4>: loop loop loop loop+4 ; jump loop+4");
+    }
+
+    #[test]
+    fn faults() {
+        let mem = SliceMemory::from_slice(Box::new([0, 0, 0, 0, 0, 0]));
+        let lines = super::disassemble_range(&mem, 0, 8);
+        assert!(lines[0].is_ok());
+        let fault = lines[1].as_ref().err().unwrap();
+        assert_eq!((fault.kind, fault.address, fault.ip), (FaultKind::BadFetch, 6, 4));
+    }
+
+    #[test]
+    fn idioms() {
+        let jump = Instruction {
+            op_a: 1,
+            op_b: 1,
+            target: 2,
+            jmp: 8,
+        };
+        assert_eq!(super::idiom(0, &jump), Some(super::Idiom::Jump(8)));
+
+        let clear = Instruction {
+            op_a: 2,
+            op_b: 2,
+            target: 2,
+            jmp: 4,
+        };
+        assert_eq!(super::idiom(0, &clear), Some(super::Idiom::Clear(2)));
+        assert_eq!(super::idiom(4, &clear), Some(super::Idiom::Jump(4)));
+
+        let zero = Instruction {
+            op_a: 1,
+            op_b: 1,
+            target: 2,
+            jmp: 4,
+        };
+        assert_eq!(super::idiom(0, &zero), Some(super::Idiom::Clear(2)));
+
+        let sub = Instruction {
+            op_a: 1,
+            op_b: 2,
+            target: 2,
+            jmp: 4,
+        };
+        assert_eq!(super::idiom(0, &sub), None);
+    }
+}
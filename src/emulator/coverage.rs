@@ -0,0 +1,229 @@
+//! Code coverage.
+//!
+//! When enabled with `Emulator::enable_coverage()`, the emulator counts for every executed instruction how often its jump has been taken and how often execution continued with the next instruction. Cycles spent dispatching interrupts and faulting instructions are not counted.
+//!
+//! Coverage can be exported in the [lcov] tracefile format for programs with a line table (`write_lcov()`, see the `symbols` module) or as a hex listing annotated with the counters (`write_listing()`). Every osci instruction is a conditional jump, so lcov branch records are written for each instruction: branch 0 is the taken jump, branch 1 the fall-through.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::emulator::{Emulator, RunLimits};
+//! let mut bios_code = std::io::Cursor::new("
+//!     ## Set the halted flag
+//!     40000004 40000005 7FFFFFFE 0
+//!     1 0
+//! ");
+//! let bios = osciemu::loader::hexloader::load(&mut bios_code).unwrap();
+//! let mut emu = Emulator::from_bios_only(bios);
+//! emu.enable_coverage();
+//! emu.run(RunLimits::default());
+//! let counts = emu.coverage().unwrap().get(0x40000000).unwrap();
+//! assert_eq!((counts.taken, counts.not_taken), (0, 1));
+//! ```
+//!
+//! [lcov]: https://manpages.debian.org/unstable/lcov/geninfo.1.en.html#TRACEFILE_FORMAT
+use memory::Memory;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use symbols::Symbols;
+
+/// Branch outcomes of a single instruction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCounts {
+    /// Number of executions that jumped
+    pub taken: u64,
+    /// Number of executions that continued with the next instruction
+    pub not_taken: u64,
+}
+
+impl BranchCounts {
+    /// Returns how often the instruction has been executed.
+    pub fn executed(&self) -> u64 {
+        self.taken + self.not_taken
+    }
+}
+
+/// Branch outcomes of all executed instructions by their address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    counts: BTreeMap<usize, BranchCounts>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Records an execution of the instruction at `ip`.
+    pub fn record(&mut self, ip: usize, jumped: bool) {
+        let counts = self.counts.entry(ip).or_default();
+        if jumped {
+            counts.taken += 1;
+        } else {
+            counts.not_taken += 1;
+        }
+    }
+
+    /// Returns the outcomes of the instruction at `ip` or `None` if it has never been executed.
+    pub fn get(&self, ip: usize) -> Option<BranchCounts> {
+        self.counts.get(&ip).cloned()
+    }
+
+    /// Returns the outcomes of all executed instructions.
+    pub fn counts(&self) -> &BTreeMap<usize, BranchCounts> {
+        &self.counts
+    }
+
+    /// Writes an lcov record for the source the `symbols` have been created from (see `Symbols::source()`).
+    ///
+    /// Only lines with instructions are reported.
+    pub fn write_lcov<W: Write>(&self, w: &mut W, symbols: &Symbols) -> io::Result<()> {
+        writeln!(w, "TN:")?;
+        writeln!(w, "SF:{}", symbols.source().unwrap_or(""))?;
+        let lines: Vec<_> = symbols
+            .lines()
+            .iter()
+            .filter(|line| line.instruction)
+            .map(|line| (line.line, self.get(line.address)))
+            .collect();
+        let mut branches_hit = 0;
+        for &(line, counts) in &lines {
+            match counts {
+                Some(counts) => {
+                    writeln!(w, "BRDA:{},0,0,{}", line, counts.taken)?;
+                    writeln!(w, "BRDA:{},0,1,{}", line, counts.not_taken)?;
+                    branches_hit += (counts.taken > 0) as usize + (counts.not_taken > 0) as usize;
+                }
+                None => {
+                    writeln!(w, "BRDA:{},0,0,-", line)?;
+                    writeln!(w, "BRDA:{},0,1,-", line)?;
+                }
+            }
+        }
+        writeln!(w, "BRF:{}", 2 * lines.len())?;
+        writeln!(w, "BRH:{}", branches_hit)?;
+        for &(line, counts) in &lines {
+            writeln!(w, "DA:{},{}", line, counts.map_or(0, |c| c.executed()))?;
+        }
+        writeln!(w, "LF:{}", lines.len())?;
+        writeln!(
+            w,
+            "LH:{}",
+            lines.iter().filter(|(_, counts)| counts.is_some()).count()
+        )?;
+        writeln!(w, "end_of_record")
+    }
+
+    /// Writes the first `size` words of `memory` mounted at `mount_start` as a hex listing with four words per line.
+    ///
+    /// Each line is annotated with its address and the outcomes of the instructions starting in it. The listing can be read by the `hexloader`.
+    pub fn write_listing<W: Write>(
+        &self,
+        w: &mut W,
+        memory: &dyn Memory,
+        mount_start: usize,
+        size: usize,
+    ) -> io::Result<()> {
+        for offset in (0..size).step_by(4) {
+            let end = (offset + 4).min(size);
+            let words: Vec<String> = (offset..end)
+                .map(|addr| format!("{:08X}", memory.peek(addr).unwrap_or(0)))
+                .collect();
+            let start = mount_start + offset;
+            let executed: Vec<String> = self
+                .counts
+                .range(start..mount_start + end)
+                .map(|(addr, counts)| {
+                    format!(
+                        "0x{:08X} executed {} (taken {}, not taken {})",
+                        addr,
+                        counts.executed(),
+                        counts.taken,
+                        counts.not_taken
+                    )
+                })
+                .collect();
+            if executed.is_empty() {
+                writeln!(w, "{:35}  # 0x{:08X}", words.join(" "), start)?;
+            } else {
+                writeln!(w, "{:35}  # {}", words.join(" "), executed.join("; "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coverage;
+    use assembler;
+    use loader::hexloader;
+    use symbols::Symbols;
+
+    #[test]
+    fn lcov() {
+        let program = assembler::assemble(
+            "
+            .addr 0xF8
+            start: 0 0 0 0
+            data: .dw 1
+            0 0 0 0
+            0 0 0 0
+            ",
+        )
+        .unwrap();
+        let mut coverage = Coverage::new();
+        coverage.record(0x100, true);
+        coverage.record(0x100, false);
+        coverage.record(0x100, false);
+        let mut output = Vec::new();
+        coverage
+            .write_lcov(
+                &mut output,
+                &Symbols::from_program(&program, Some("test.asm")),
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "TN:\n\
+             SF:test.asm\n\
+             BRDA:3,0,0,-\n\
+             BRDA:3,0,1,-\n\
+             BRDA:5,0,0,1\n\
+             BRDA:5,0,1,2\n\
+             BRDA:6,0,0,-\n\
+             BRDA:6,0,1,-\n\
+             BRF:6\n\
+             BRH:2\n\
+             DA:3,0\n\
+             DA:5,3\n\
+             DA:6,0\n\
+             LF:3\n\
+             LH:1\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    fn listing() {
+        let memory = hexloader::load(&mut "1 2 3 4 -1 6 7".as_bytes()).unwrap();
+        let mut coverage = Coverage::new();
+        coverage.record(0x100, false);
+        coverage.record(0x106, true);
+        let mut output = Vec::new();
+        coverage
+            .write_listing(&mut output, &*memory, 0x100, 7)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "00000001 00000002 00000003 00000004  # 0x00000100 executed 1 (taken 0, not taken 1)\n\
+             FFFFFFFF 00000006 00000007           # 0x00000106 executed 1 (taken 1, not taken 0)\n"
+        );
+
+        let listed = hexloader::load(&mut output.as_bytes()).unwrap();
+        for addr in 0..7 {
+            assert_eq!(listed.get(addr), memory.get(addr));
+        }
+    }
+}
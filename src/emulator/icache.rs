@@ -0,0 +1,154 @@
+//! Cache of decoded instructions.
+//!
+//! Decoding an instruction reads four words through the `MappedMemory`, which has to find the responsible mount for each of them. The emulator keeps decoded instructions in an `InstructionCache` keyed by their address and only decodes an instruction again when one of its words might have changed. The cache is enabled by default and can be turned off with `Emulator::disable_instruction_cache()`.
+//!
+//! The cache relies on the change tracking of `MappedMemory`:
+//!
+//! - Every write through the `MappedMemory` invalidates the cached instructions containing the written word. This covers self-modifying code, writes from the stack of interrupt dispatch, writes of devices while being ticked and writes through `Emulator::memory`.
+//! - Every change of the memory layout flushes the cache. This covers toggling the BIOS mount with the flags, restoring a snapshot and `MappedMemory::borrow_mut()`.
+//!
+//! Instructions that have a word in a device mount are never cached because devices can change their contents on their own.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::emulator::{Emulator, RunLimits};
+//! # use osciemu::memory::Memory;
+//! let mut bios_code = std::io::Cursor::new("
+//!     ## Count register 0 down from 2, then halt
+//!     7FFFFFF9 40000011 7FFFFFF9 40000008
+//!     0 0 0 40000000
+//!     40000011 0 7FFFFFFE 0
+//!     0 0 0 0
+//!     3 1
+//! ");
+//! let bios = osciemu::loader::hexloader::load(&mut bios_code).unwrap();
+//! let mut emu = Emulator::from_bios_only(bios);
+//! emu.memory.set(0x7FFFFFF9, 2);
+//! emu.run(RunLimits::default());
+//! let cache = emu.instruction_cache().unwrap();
+//! // Only the first lookup of every address misses.
+//! assert_eq!(cache.hits(), 3);
+//! assert_eq!(cache.misses(), 3);
+//! ```
+use instruction::{Instruction, INSTRUCTION_SIZE};
+
+/// Number of cached instructions. Must be a power of two.
+pub const CACHE_SIZE: usize = 4096;
+
+/// A direct-mapped cache of decoded instructions.
+pub struct InstructionCache {
+    slots: Vec<Option<(usize, Instruction)>>,
+    generation: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl InstructionCache {
+    /// Creates an empty cache for the given `MappedMemory` generation.
+    pub fn new(generation: usize) -> InstructionCache {
+        InstructionCache {
+            slots: vec![None; CACHE_SIZE],
+            generation,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the generation the cached instructions have been decoded in.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Returns the instruction at `addr`, if cached, and counts a hit or a miss.
+    pub fn get(&mut self, addr: usize) -> Option<Instruction> {
+        match self.slots[addr & (CACHE_SIZE - 1)] {
+            Some((cached, instr)) if cached == addr => {
+                self.hits += 1;
+                Some(instr)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Caches the instruction at `addr`, replacing the instruction in the same slot.
+    pub fn insert(&mut self, addr: usize, instr: Instruction) {
+        self.slots[addr & (CACHE_SIZE - 1)] = Some((addr, instr));
+    }
+
+    /// Removes all cached instructions that contain the word at `addr`.
+    pub fn invalidate(&mut self, addr: usize) {
+        for offset in 0..INSTRUCTION_SIZE.min(addr + 1) {
+            let start = addr - offset;
+            let slot = &mut self.slots[start & (CACHE_SIZE - 1)];
+            if slot.is_some_and(|(cached, _)| cached == start) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Removes all cached instructions and sets the generation they will be decoded in.
+    pub fn flush(&mut self, generation: usize) {
+        for slot in &mut self.slots {
+            *slot = None;
+        }
+        self.generation = generation;
+    }
+
+    /// Returns the number of lookups that found a cached instruction.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of lookups that had to decode the instruction.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstructionCache, CACHE_SIZE};
+    use instruction::Instruction;
+
+    fn instr(jmp: i32) -> Instruction {
+        Instruction {
+            op_a: 0,
+            op_b: 0,
+            target: 0,
+            jmp,
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let mut cache = InstructionCache::new(7);
+        assert!(cache.get(0x100).is_none());
+        cache.insert(0x100, instr(1));
+        assert_eq!(cache.get(0x100), Some(instr(1)));
+        // Same slot, different address
+        assert!(cache.get(0x100 + CACHE_SIZE).is_none());
+        cache.insert(0x100 + CACHE_SIZE, instr(2));
+        assert!(cache.get(0x100).is_none());
+        assert_eq!((cache.hits(), cache.misses()), (1, 3));
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut cache = InstructionCache::new(7);
+        cache.insert(0x100, instr(1));
+        cache.insert(0x104, instr(2));
+        cache.invalidate(0x104 + CACHE_SIZE);
+        assert_eq!(cache.get(0x104), Some(instr(2)));
+        cache.invalidate(0x103);
+        assert!(cache.get(0x100).is_none());
+        assert_eq!(cache.get(0x104), Some(instr(2)));
+        cache.invalidate(0);
+        cache.flush(8);
+        assert!(cache.get(0x104).is_none());
+        assert_eq!(cache.generation(), 8);
+    }
+}
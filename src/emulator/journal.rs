@@ -0,0 +1,281 @@
+//! Write journal for reverse execution.
+//!
+//! When enabled with `Emulator::enable_journal()`, the emulator records the old value of every memory cell it changes, together with the cycle and the `ip` before the cycle. Every cycle starts with a `Change::Cycle` entry, so cycles that don’t change any journaled cell can be undone as well. The changes include the result written by each instruction, the stack and flags updates of interrupt dispatch and return, and toggles of the BIOS mount. `Emulator::step_back()` uses the journal to undo cycles.
+//!
+//! Changes a device makes to its own state while being ticked (e.g. the timer counter) and writes to cells that can’t be read without side effects (see `Memory::peek()`) are not journaled and therefore not undone.
+use memory::{self, Memory};
+use std::collections::VecDeque;
+
+/// A change to the emulator state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// A cycle has started.
+    Cycle,
+    /// A memory cell has been written.
+    Write {
+        /// The written address
+        address: usize,
+        /// The value before the write
+        old_value: i32,
+    },
+    /// The BIOS has been mounted or unmounted.
+    BiosMount {
+        /// Whether the BIOS was mounted before
+        was_enabled: bool,
+    },
+}
+
+/// A journaled change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Number of cycles executed before the change
+    pub cycle: usize,
+    /// `ip` at the start of the cycle
+    pub ip: usize,
+    /// The change
+    pub change: Change,
+}
+
+/// A bounded list of changes.
+///
+/// When the journal is full, all entries of the oldest cycle are dropped. The most recent cycle is never dropped, so a cycle with more entries than the capacity exceeds it until the next cycle starts.
+pub struct Journal {
+    entries: VecDeque<JournalEntry>,
+    capacity: usize,
+}
+
+impl Journal {
+    /// Creates a journal that keeps at most `capacity` entries.
+    pub fn new(capacity: usize) -> Journal {
+        Journal {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Returns the maximum number of entries.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Adds an entry.
+    pub fn push(&mut self, entry: JournalEntry) {
+        let current = entry.cycle;
+        self.entries.push_back(entry);
+        while self.entries.len() > self.capacity {
+            let oldest = self.entries[0].cycle;
+            if oldest == current {
+                break;
+            }
+            while self.entries.front().is_some_and(|e| e.cycle == oldest) {
+                self.entries.pop_front();
+            }
+        }
+    }
+
+    /// Starts recording the given cycle by adding its `Change::Cycle` entry.
+    ///
+    /// Does nothing if the cycle has already been started, e.g. by an earlier attempt that faulted.
+    pub fn begin_cycle(&mut self, cycle: usize, ip: usize) {
+        if self.entries.back().is_some_and(|e| e.cycle == cycle) {
+            return;
+        }
+        self.push(JournalEntry {
+            cycle,
+            ip,
+            change: Change::Cycle,
+        });
+    }
+
+    /// Removes and returns the entries of the given cycle in the order they have been recorded.
+    ///
+    /// Only the most recent cycle can be popped.
+    pub fn pop_cycle(&mut self, cycle: usize) -> Vec<JournalEntry> {
+        let mut entries = Vec::new();
+        while self.entries.back().is_some_and(|e| e.cycle == cycle) {
+            entries.push(self.entries.pop_back().unwrap());
+        }
+        entries.reverse();
+        entries
+    }
+
+    /// Removes all entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns all entries, oldest first.
+    pub fn entries(&self) -> &VecDeque<JournalEntry> {
+        &self.entries
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks if the journal is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Wraps a memory and journals all writes made through it.
+pub(crate) struct JournaledMemory<'a> {
+    memory: &'a mut dyn Memory,
+    journal: &'a mut Journal,
+    cycle: usize,
+    ip: usize,
+}
+
+impl<'a> JournaledMemory<'a> {
+    pub fn new(
+        memory: &'a mut dyn Memory,
+        journal: &'a mut Journal,
+        cycle: usize,
+        ip: usize,
+    ) -> Self {
+        JournaledMemory {
+            memory,
+            journal,
+            cycle,
+            ip,
+        }
+    }
+
+    fn record(&mut self, addr: usize, old_value: Option<i32>) {
+        if let Some(old_value) = old_value {
+            self.journal.push(JournalEntry {
+                cycle: self.cycle,
+                ip: self.ip,
+                change: Change::Write {
+                    address: addr,
+                    old_value,
+                },
+            });
+        }
+    }
+}
+
+impl<'a> Memory for JournaledMemory<'a> {
+    fn get(&self, addr: usize) -> i32 {
+        self.memory.get(addr)
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        // Writes that are discarded (or panic) must not be journaled.
+        if self.try_set(addr, value).is_err() {
+            self.memory.set(addr, value);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.memory.size()
+    }
+
+    fn try_get(&self, addr: usize) -> memory::Result<i32> {
+        self.memory.try_get(addr)
+    }
+
+    fn try_set(&mut self, addr: usize, value: i32) -> memory::Result<()> {
+        let old_value = self.memory.peek(addr);
+        self.memory.try_set(addr, value)?;
+        self.record(addr, old_value);
+        Ok(())
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        self.memory.peek(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Change, Journal, JournalEntry, JournaledMemory};
+    use memory::{Memory, ReadOnlyMemory, SliceMemory};
+
+    fn write(cycle: usize, address: usize) -> JournalEntry {
+        JournalEntry {
+            cycle,
+            ip: 0,
+            change: Change::Write {
+                address,
+                old_value: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn bounded() {
+        let mut journal = Journal::new(3);
+        journal.push(write(0, 0));
+        journal.push(write(0, 1));
+        journal.push(write(1, 0));
+        assert_eq!(journal.len(), 3);
+        journal.push(write(2, 0));
+        // Both entries of cycle 0 have been dropped.
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal.entries()[0].cycle, 1);
+
+        assert_eq!(journal.pop_cycle(1), vec![]);
+        assert_eq!(journal.pop_cycle(2), vec![write(2, 0)]);
+        assert_eq!(journal.pop_cycle(1), vec![write(1, 0)]);
+        assert!(journal.is_empty());
+    }
+
+    #[test]
+    fn keeps_current_cycle() {
+        let mut journal = Journal::new(1);
+        journal.begin_cycle(0, 4);
+        journal.push(write(0, 0));
+        journal.begin_cycle(0, 4);
+        // The cycle being recorded exceeds the capacity.
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal.entries()[0].change, Change::Cycle);
+
+        journal.begin_cycle(1, 8);
+        assert_eq!(journal.len(), 1);
+        assert_eq!(
+            journal.pop_cycle(1),
+            vec![JournalEntry {
+                cycle: 1,
+                ip: 8,
+                change: Change::Cycle,
+            }]
+        );
+        assert!(journal.is_empty());
+    }
+
+    #[test]
+    fn journals_writes() {
+        let mut m = SliceMemory::from_slice(Box::new([1, 2]));
+        let mut journal = Journal::new(10);
+        {
+            let mut jm = JournaledMemory::new(&mut m, &mut journal, 5, 8);
+            jm.set(1, 7);
+            assert!(jm.try_set(2, 7).is_err());
+        }
+        assert_eq!(m.get(1), 7);
+        assert_eq!(
+            journal.pop_cycle(5),
+            vec![JournalEntry {
+                cycle: 5,
+                ip: 8,
+                change: Change::Write {
+                    address: 1,
+                    old_value: 2,
+                },
+            }]
+        );
+
+        // Failed writes to memory that can be read are not journaled either.
+        let mut rom = ReadOnlyMemory::new(Box::new(m));
+        {
+            let mut jm = JournaledMemory::new(&mut rom, &mut journal, 6, 12);
+            jm.set(0, 7);
+            assert!(jm.try_set(1, 8).is_err());
+        }
+        assert_eq!((rom.get(0), rom.get(1)), (1, 7));
+        assert!(journal.is_empty());
+    }
+}
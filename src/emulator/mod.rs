@@ -18,48 +18,322 @@
 //! emu.step();
 //! assert_eq!(emu.get_register(0), 0x10 - 0x3);
 //! ```
-use super::memory::{self, address, Memory, SliceMemory};
+//!
+//! `run()` executes cycles until the emulator halts or another stop condition occurs:
+//!
+//! ```
+//! # use osciemu::emulator::{Emulator, RunLimits, StopReason};
+//! let mut bios_code = std::io::Cursor::new("
+//!     ## Set the halted flag
+//!     40000004 40000005 7FFFFFFE 0
+//!     1 0
+//! ");
+//! let mut bios = osciemu::loader::hexloader::load(&mut bios_code).unwrap();
+//! let mut emu = Emulator::from_bios_only(bios);
+//! let outcome = emu.run(RunLimits::default());
+//! assert_eq!(outcome.reason, StopReason::Halted);
+//! assert_eq!(outcome.cycles, 1);
+//! ```
+//!
+//! Breakpoints and watchpoints (see the `watchpoint` module) stop `run()` as well:
+//!
+//! ```
+//! # use osciemu::emulator::{Emulator, RunLimits, StopReason};
+//! # use osciemu::emulator::watchpoint::WatchKind;
+//! # use osciemu::memory::address;
+//! let mut bios_code = std::io::Cursor::new("
+//!     ## Store 0x10 - 0x3 in register 0
+//!     40000004 40000005 7FFFFFF9 0
+//!     10 3
+//! ");
+//! let mut bios = osciemu::loader::hexloader::load(&mut bios_code).unwrap();
+//! let mut emu = Emulator::from_bios_only(bios);
+//! let r0 = address::REGISTERS_START_ADDRESS;
+//! emu.add_watchpoint(r0..r0 + 1, WatchKind::Change);
+//! match emu.run(RunLimits::default()).reason {
+//!     StopReason::Watchpoint(hit) => assert_eq!(hit.value, 0x10 - 0x3),
+//!     reason => panic!("Unexpected {}", reason),
+//! }
+//! ```
+//!
+//! Invalid memory accesses don’t panic but are reported by `step()` as a `Fault`:
+//!
+//! ```
+//! # use osciemu::emulator::{Emulator, StepResult};
+//! # use osciemu::instruction::FaultKind;
+//! let mut bios_code = std::io::Cursor::new("
+//!     ## Write to the (read-only) BIOS
+//!     0 0 40000000 0
+//! ");
+//! let mut bios = osciemu::loader::hexloader::load(&mut bios_code).unwrap();
+//! let mut emu = Emulator::from_bios_only(bios);
+//! match emu.step() {
+//!     StepResult::Fault(fault) => assert_eq!(fault.kind, FaultKind::WriteToRom),
+//!     _ => panic!("Expected a fault"),
+//! }
+//! ```
+use super::memory::{self, address, Memory, MemoryError, SliceMemory, SparseMemory};
 use super::memory::mappedmemory::MemoryToken;
-use super::instruction::Instruction;
+use super::instruction::{Fault, FaultKind, Instruction};
+use super::device::{Device, DeviceMemory, Flags, Timer};
+use super::trace::{TraceRecord, TraceSink};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::rc::Rc;
+
+pub mod coverage;
+pub mod icache;
+pub mod journal;
+pub mod observer;
+pub mod snapshot;
+pub mod watchpoint;
+
+use self::coverage::Coverage;
+use self::icache::InstructionCache;
+use self::journal::{Change, Journal, JournalEntry, JournaledMemory};
+use self::observer::{AccessKind, MemoryObserver, ObservedMemory};
+use self::snapshot::{Content, MountSnapshot, Snapshot, SnapshotError};
+use self::watchpoint::{WatchHit, WatchKind, WatchedMemory, Watchpoint, WatchpointId};
+
+/// Outcome of a single cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// An instruction has been executed.
+    Executed,
+    /// The interrupt on the given line has been dispatched.
+    Interrupt(usize),
+    /// An instruction has been executed and triggered a watchpoint.
+    Watchpoint(WatchHit),
+    /// The cycle faulted. The emulator state is unchanged, except for a `FaultKind::BadReturn` fault, which is reported after the instruction has been executed.
+    Fault(Fault),
+}
+
+/// Conditions under which `Emulator::run()` stops in addition to the halted flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunLimits {
+    /// Maximum number of cycles to execute. `None` means no limit.
+    pub max_cycles: Option<usize>,
+    /// Stop when the CPU is stuck in a jump to itself with interrupts disabled.
+    pub detect_spin: bool,
+}
+
+impl Default for RunLimits {
+    fn default() -> RunLimits {
+        RunLimits {
+            max_cycles: None,
+            detect_spin: true,
+        }
+    }
+}
+
+/// The reason `Emulator::run()` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The halted flag is set.
+    Halted,
+    /// The maximum number of cycles has been executed.
+    CycleLimit,
+    /// `ip` reached the breakpoint at the given address.
+    Breakpoint(usize),
+    /// The last instruction triggered a watchpoint.
+    Watchpoint(WatchHit),
+    /// A cycle faulted.
+    Fault(Fault),
+    /// The instruction at the given address jumps to itself and interrupts are disabled, so the CPU will never make progress.
+    Spin(usize),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StopReason::Halted => write!(f, "Halted"),
+            StopReason::CycleLimit => write!(f, "Cycle limit reached"),
+            StopReason::Breakpoint(addr) => write!(f, "Breakpoint at 0x{:08X}", addr),
+            StopReason::Watchpoint(ref hit) => write!(
+                f,
+                "Watchpoint {} ({:?}) at 0x{:08X}: 0x{:08X} (ip 0x{:08X})",
+                hit.id.0, hit.kind, hit.address, hit.value, hit.ip
+            ),
+            StopReason::Fault(ref fault) => write!(f, "{}", fault),
+            StopReason::Spin(addr) => write!(f, "Spinning at 0x{:08X}", addr),
+        }
+    }
+}
+
+/// Result of `Emulator::run()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunOutcome {
+    /// Why the emulator stopped
+    pub reason: StopReason,
+    /// Number of cycles executed by this call
+    pub cycles: usize,
+}
+
+/// Memory behind the image, see `Emulator::with_ram()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ram {
+    /// No memory besides the image. Writes beyond the end of the image are discarded.
+    None,
+    /// A `SliceMemory` with the given number of words, allocated up front.
+    Fixed(usize),
+    /// A `SparseMemory` spanning all addresses below the control registers. Pages are allocated when they are first written.
+    Sparse,
+}
 
 // Emulator for osci.
 pub struct Emulator {
+    devices: Vec<(MemoryToken, Rc<RefCell<dyn Device>>)>,
+    tracers: Vec<Rc<RefCell<dyn TraceSink>>>,
+    observers: Vec<Rc<RefCell<dyn MemoryObserver>>>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+    next_watchpoint_id: usize,
+    journal: Option<Journal>,
+    coverage: Option<Coverage>,
+    instruction_cache: Option<InstructionCache>,
+    /// Address ranges of the device mounts and the generation of `memory` they have been collected in
+    device_ranges: Option<(usize, Vec<Range<usize>>)>,
     bios_memory_token: MemoryToken,
+    null_memory_token: MemoryToken,
+    cycle_ip: usize,
     /// Memory
     pub memory: memory::MappedMemory,
     /// Instruction pointer
     pub ip: usize,
+    /// Number of cycles executed so far
+    pub cycles: usize,
 }
 
 impl Emulator {
     /// Initializes an `Emulator` with the given BIOS.
     ///
     /// Equivalent to calling `new()` with an empty `SliceMemory` as the main memory.
-    pub fn from_bios_only(bios: Box<Memory>) -> Emulator {
+    pub fn from_bios_only(bios: Box<dyn Memory>) -> Emulator {
         Emulator::new(bios, Box::new(SliceMemory::new(0)))
     }
 
     /// Initializes an `Emulator` with the given BIOS and main memory.
-    pub fn new(bios: Box<Memory>, img: Box<Memory>) -> Emulator {
+    ///
+    /// Equivalent to calling `with_ram()` with `Ram::None`.
+    pub fn new(bios: Box<dyn Memory>, img: Box<dyn Memory>) -> Emulator {
+        Emulator::with_ram(bios, img, Ram::None)
+    }
+
+    /// Initializes an `Emulator` with the given BIOS, main memory and RAM.
+    ///
+    /// The RAM is mounted as “ram” at address 0 below the image, so the image shadows the beginning of the RAM. The flags words and the timer are attached as devices.
+    ///
+    /// # Panics
+    /// Panics if the image or the BIOS reach beyond `MAX_ADDRESS`.
+    pub fn with_ram(bios: Box<dyn Memory>, img: Box<dyn Memory>, ram: Ram) -> Emulator {
         let mut memory = memory::MappedMemory::new();
-        memory.mount(0, Box::new(memory::NullMemory::new()));
+        let null_memory_token = memory
+            .mount_named(0, "null", Box::new(memory::NullMemory::new()))
+            .unwrap();
 
-        memory.mount(0, img);
-        let bios_memory_token = memory.mount(
-            address::BIOS_START_ADDRESS,
-            Box::new(memory::ReadOnlyMemory::new(bios)),
-        );
+        match ram {
+            Ram::None => {}
+            Ram::Fixed(size) => {
+                let size = size.min(address::CONTROLS_ADDRESS);
+                memory
+                    .mount_named(0, "ram", Box::new(SliceMemory::new(size)))
+                    .unwrap();
+            }
+            Ram::Sparse => {
+                let ram = SparseMemory::new(address::CONTROLS_ADDRESS);
+                memory.mount_named(0, "ram", Box::new(ram)).unwrap();
+            }
+        }
+        memory
+            .mount_named(0, "image", img)
+            .expect("Image reaches beyond MAX_ADDRESS");
+        let bios_memory_token = memory
+            .mount_named(
+                address::BIOS_START_ADDRESS,
+                "bios",
+                Box::new(memory::ReadOnlyMemory::new(bios)),
+            )
+            .expect("BIOS reaches beyond MAX_ADDRESS");
 
         let controls_memory = Box::new(memory::SliceMemory::new(
             address::MAX_ADDRESS - address::CONTROLS_ADDRESS + 1,
         ));
-        memory.mount(address::CONTROLS_ADDRESS, controls_memory);
+        memory
+            .mount_named(address::CONTROLS_ADDRESS, "controls", controls_memory)
+            .unwrap();
 
-        Emulator {
+        let mut emulator = Emulator {
             memory,
-            bios_memory_token,
+            devices: Vec::new(),
+            tracers: Vec::new(),
+            observers: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            next_watchpoint_id: 0,
+            journal: None,
+            coverage: None,
+            instruction_cache: None,
+            device_ranges: None,
+            bios_memory_token: bios_memory_token.clone(),
+            null_memory_token,
+            cycle_ip: address::BIOS_START_ADDRESS,
             ip: address::BIOS_START_ADDRESS,
-        }
+            cycles: 0,
+        };
+        emulator.attach_device(address::FLAGS_START_ADDRESS, Flags::new(bios_memory_token));
+        emulator.attach_device(
+            address::TIMER_START_ADDRESS,
+            Timer::new(address::TIMER_INTERRUPT_LINE),
+        );
+        emulator.enable_instruction_cache();
+        emulator
+    }
+
+    /// Mounts a `Device` at the given address using the device’s name.
+    ///
+    /// The device will be ticked after every cycle in the order the devices have been attached. `run()` skips the ticks of idle devices (see `Device::is_idle()`).
+    ///
+    /// # Panics
+    /// Panics if the device reaches beyond `MAX_ADDRESS`.
+    pub fn attach_device<D: Device + 'static>(&mut self, addr: usize, device: D) -> MemoryToken {
+        let name = String::from(device.name());
+        let device = Rc::new(RefCell::new(device));
+        let token = self.memory
+            .mount_named(addr, &name, Box::new(DeviceMemory(device.clone())))
+            .expect("Device reaches beyond MAX_ADDRESS");
+        self.devices.push((token.clone(), device));
+        token
+    }
+
+    /// Attaches a `TraceSink` that receives a record for every executed instruction.
+    ///
+    /// Returns a shared handle to the sink so it can be inspected while it is attached.
+    pub fn attach_tracer<T: TraceSink + 'static>(&mut self, sink: T) -> Rc<RefCell<T>> {
+        let sink = Rc::new(RefCell::new(sink));
+        self.tracers.push(sink.clone());
+        sink
+    }
+
+    /// Detaches all `TraceSink`s.
+    pub fn detach_tracers(&mut self) {
+        self.tracers.clear();
+    }
+
+    /// Attaches a `MemoryObserver` that is called for every memory access of the CPU.
+    ///
+    /// See the `observer` module for which accesses are reported and in which order. Returns a shared handle to the observer so it can be inspected while it is attached.
+    pub fn attach_observer<T: MemoryObserver + 'static>(&mut self, observer: T) -> Rc<RefCell<T>> {
+        let observer = Rc::new(RefCell::new(observer));
+        self.observers.push(observer.clone());
+        observer
+    }
+
+    /// Detaches all `MemoryObserver`s.
+    pub fn detach_observers(&mut self) {
+        self.observers.clear();
     }
 
     /// Checks if a flag is set.
@@ -71,33 +345,766 @@ impl Emulator {
             .get(address::FLAGS_START_ADDRESS + flag_idx / 32) & (1 << bit) != 0
     }
 
+    /// Sets or clears a flag.
+    ///
+    /// Use with the constant from `osciemu::memory::address`.
+    pub fn set_flag(&mut self, flag_idx: usize, value: bool) {
+        let addr = address::FLAGS_START_ADDRESS + flag_idx / 32;
+        let mask = 1 << (flag_idx % 32);
+        let flags = self.memory.get(addr);
+        self.memory
+            .set(addr, if value { flags | mask } else { flags & !mask });
+    }
+
     /// Gets the current value of the given register.
     pub fn get_register(&self, reg_idx: usize) -> i32 {
         self.memory.get(address::REGISTERS_START_ADDRESS + reg_idx)
     }
 
+    /// Raises an interrupt on the given line by setting its pending bit.
+    ///
+    /// The interrupt will be dispatched at the start of the next cycle in which the `IE` flag is set.
+    ///
+    /// # Panics
+    /// Panics if `line` is not smaller than `NUM_INTERRUPT_LINES`.
+    pub fn raise_interrupt(&mut self, line: usize) {
+        assert!(
+            line < address::NUM_INTERRUPT_LINES,
+            "Invalid interrupt line {}",
+            line
+        );
+        self.set_flag(address::FLAG_INTERRUPT_PENDING + line, true);
+    }
+
+    /// Returns the lowest interrupt line with a set pending bit.
+    pub fn pending_interrupt(&self) -> Option<usize> {
+        (0..address::NUM_INTERRUPT_LINES)
+            .find(|line| self.is_flag_set(address::FLAG_INTERRUPT_PENDING + line))
+    }
+
     /// Executes one cycle.
     ///
+    /// If an interrupt is pending and interrupts are enabled, the cycle is spent dispatching the interrupt instead of executing an instruction.
+    ///
+    /// After the cycle, all attached devices are ticked. If the cycle faults, the devices are not ticked and `ip` keeps pointing to the faulting instruction.
+    ///
+    /// A `FaultKind::BadReturn` fault is the exception: the instruction that set the `IR` flag has been executed, the devices have been ticked and `ip` points to the next instruction. The `IR` flag stays set and the fault’s `ip` is the address of the instruction.
+    ///
     /// This method will execute a cycle even if the halted flag is set.
-    pub fn step(&mut self) {
-        let instr = Instruction::from_memory(self.ip, &self.memory);
-        instr.execute(&mut self.ip, &mut self.memory);
+    pub fn step(&mut self) -> StepResult {
+        self.cycle_ip = self.ip;
+        if let Some(ref mut journal) = self.journal {
+            journal.begin_cycle(self.cycles, self.ip);
+        }
+        let result = if self.is_flag_set(address::FLAG_INTERRUPT_ENABLE) {
+            match self.pending_interrupt() {
+                Some(line) => self
+                    .dispatch_interrupt()
+                    .map(|_| StepResult::Interrupt(line)),
+                None => self.execute(),
+            }
+        } else {
+            self.execute()
+        };
+
+        match result {
+            Ok(result) => {
+                self.tick_devices(1);
+                self.cycles += 1;
+                result
+            }
+            Err(fault) => StepResult::Fault(fault),
+        }
+    }
+
+    /// Executes cycles until the halted flag is set or one of the `limits` is reached.
+    ///
+    /// A breakpoint stops execution before the instruction at its address is executed. The breakpoint at the current `ip` is ignored for the first cycle so that calling `run()` again continues execution. A watchpoint stops execution after the instruction that triggered it.
+    ///
+    /// Without journal, coverage, tracers, observers and watchpoints, and while all devices are idle (see `Device::is_idle()`), instructions that don’t write to a device are executed without checking the flags and ticking the devices after every cycle. Only writes to a device can change the flags or wake up a device, so the result is the same as calling `step()` repeatedly.
+    pub fn run(&mut self, limits: RunLimits) -> RunOutcome {
+        let start = self.cycles;
+        let reason = loop {
+            let cycles = self.cycles - start;
+            if self.is_halted() {
+                break StopReason::Halted;
+            }
+            if cycles > 0 && self.breakpoints.contains(&self.ip) {
+                break StopReason::Breakpoint(self.ip);
+            }
+            if limits.max_cycles.is_some_and(|max| cycles >= max) {
+                break StopReason::CycleLimit;
+            }
+            if limits.detect_spin && self.is_spinning() {
+                break StopReason::Spin(self.ip);
+            }
+            if self.can_run_fast() {
+                if let Some(reason) = self.run_fast(limits, start) {
+                    break reason;
+                }
+            }
+            match self.step() {
+                StepResult::Fault(fault) => break StopReason::Fault(fault),
+                StepResult::Watchpoint(hit) => break StopReason::Watchpoint(hit),
+                _ => {}
+            }
+        };
+        RunOutcome {
+            reason,
+            cycles: self.cycles - start,
+        }
+    }
+
+    /// Checks if `run_fast()` can be used for the next cycles.
+    fn can_run_fast(&self) -> bool {
+        self.journal.is_none()
+            && self.coverage.is_none()
+            && self.tracers.is_empty()
+            && self.observers.is_empty()
+            && self.watchpoints.is_empty()
+            && self.devices.iter().all(|(_, device)| device.borrow().is_idle(&self.memory))
+            && !self.is_flag_set(address::FLAG_INTERRUPT_RETURN)
+            && !(self.is_flag_set(address::FLAG_INTERRUPT_ENABLE)
+                && self.pending_interrupt().is_some())
+    }
+
+    /// Executes instructions that don’t write to a device, starting with the one at `ip`, which has passed the checks of `run()`.
+    ///
+    /// As long as no device is written, no flag changes and the devices stay idle, so the cycles only consist of executing the instruction. Returns `None` when the instruction at `ip` has passed the checks of `run()` but writes to a device and has to be executed with `step()`.
+    fn run_fast(&mut self, limits: RunLimits, start: usize) -> Option<StopReason> {
+        let detect_spin =
+            limits.detect_spin && !self.is_flag_set(address::FLAG_INTERRUPT_ENABLE);
+        self.sync_instruction_cache();
+        let device_ranges = self.device_ranges();
+        let mut checked = true;
+        loop {
+            let ip = self.ip;
+            if !checked {
+                let cycles = self.cycles - start;
+                if !self.breakpoints.is_empty() && self.breakpoints.contains(&ip) {
+                    return Some(StopReason::Breakpoint(ip));
+                }
+                if limits.max_cycles.is_some_and(|max| cycles >= max) {
+                    return Some(StopReason::CycleLimit);
+                }
+            }
+            // Only the writes of the instructions executed here can change the cached instructions.
+            let instr = match self.fetch_synced(ip) {
+                Ok(instr) => instr,
+                Err(fault) => return Some(StopReason::Fault(fault)),
+            };
+            let spinning = instr.op_a == instr.op_b && instr.jmp >= 0 && instr.jmp as usize == ip;
+            if !checked && detect_spin && spinning {
+                return Some(StopReason::Spin(ip));
+            }
+            checked = false;
+            match instr.target_address(&self.memory) {
+                Some(target) if !device_ranges.iter().any(|range| range.contains(&target)) => {}
+                _ => return None,
+            }
+            self.cycle_ip = ip;
+            if let Err(fault) = instr.try_execute(&mut self.ip, &mut self.memory) {
+                return Some(StopReason::Fault(fault));
+            }
+            if let Some(ref mut cache) = self.instruction_cache {
+                for addr in self.memory.drain_written() {
+                    cache.invalidate(addr);
+                }
+            }
+            self.cycles += 1;
+        }
+    }
+
+    /// Returns the address ranges of the device mounts.
+    fn device_ranges(&mut self) -> Vec<Range<usize>> {
+        let generation = self.memory.generation();
+        if self.device_ranges.as_ref().is_none_or(|(g, _)| *g != generation) {
+            let ranges = self.memory
+                .mounts()
+                .into_iter()
+                .filter(|mount| self.device_for_token(&mount.token).is_some())
+                .map(|mount| mount.start_address..mount.start_address + mount.size)
+                .collect();
+            self.device_ranges = Some((generation, ranges));
+        }
+        self.device_ranges.as_ref().unwrap().1.clone()
+    }
+
+    /// Checks if the instruction at `ip` is an unconditional jump to itself that can’t be left by an interrupt.
+    fn is_spinning(&mut self) -> bool {
+        if self.is_flag_set(address::FLAG_INTERRUPT_ENABLE) {
+            return false;
+        }
+        let ip = self.ip;
+        match self.fetch(ip) {
+            Ok(instr) => {
+                instr.op_a == instr.op_b && instr.jmp >= 0 && instr.jmp as usize == self.ip
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Adds an execution breakpoint at `addr`.
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes the execution breakpoint at `addr`. Returns `false` if there was none.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Returns the addresses of all execution breakpoints.
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    /// Adds a watchpoint for the given address range.
+    pub fn add_watchpoint(&mut self, range: Range<usize>, kind: WatchKind) -> WatchpointId {
+        let id = WatchpointId(self.next_watchpoint_id);
+        self.next_watchpoint_id += 1;
+        self.watchpoints.push(Watchpoint { id, range, kind });
+        id
+    }
+
+    /// Removes a watchpoint. Returns `false` if there was none with the given id.
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|wp| wp.id != id);
+        self.watchpoints.len() != len
+    }
+
+    /// Returns all watchpoints.
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
 
-        self.check_bios_mount()
+    /// Captures the current state. See the `snapshot` module.
+    pub fn snapshot(&self) -> Snapshot {
+        let mounts = self.memory
+            .mounts()
+            .into_iter()
+            .map(|mount| {
+                let content = if mount.token == self.null_memory_token {
+                    Content::None
+                } else if let Some(device) = self.device_for_token(&mount.token) {
+                    Content::Device(device.borrow().save_state())
+                } else {
+                    let memory = self.memory.borrow(&mount.token);
+                    let words = |range: Range<usize>| -> Vec<i32> {
+                        range.map(|addr| memory.peek(addr).unwrap_or(0)).collect()
+                    };
+                    let allocated = memory.allocated();
+                    if allocated.len() == 1 && allocated[0] == (0..mount.size) {
+                        Content::Memory(words(0..mount.size))
+                    } else {
+                        Content::Sparse(
+                            allocated
+                                .into_iter()
+                                .map(|range| (range.start, words(range)))
+                                .collect(),
+                        )
+                    }
+                };
+                MountSnapshot {
+                    name: mount.name,
+                    start_address: mount.start_address,
+                    size: mount.size,
+                    enabled: mount.enabled,
+                    content,
+                }
+            })
+            .collect();
+        Snapshot {
+            ip: self.ip,
+            cycles: self.cycles,
+            mounts,
+        }
+    }
+
+    /// Restores a state captured by `snapshot()`.
+    ///
+    /// Fails without changing the emulator if the memory layout doesn’t match. If a device rejects its saved state, the emulator is left partially restored. The journal is cleared.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> snapshot::Result<()> {
+        let mounts = self.memory.mounts();
+        if mounts.len() != snapshot.mounts.len() {
+            let saved_names: Vec<&str> = snapshot.mounts.iter().map(|m| &m.name[..]).collect();
+            let names: Vec<&str> = mounts.iter().map(|m| &m.name[..]).collect();
+            return Err(SnapshotError::Mismatch(format!(
+                "Expected mounts {}, found {}",
+                saved_names.join(", "),
+                names.join(", ")
+            )));
+        }
+        for (mount, saved) in mounts.iter().zip(&snapshot.mounts) {
+            let is_device = self.device_for_token(&mount.token).is_some();
+            let content_matches = match saved.content {
+                Content::None => mount.token == self.null_memory_token,
+                Content::Memory(_) | Content::Sparse(_) => {
+                    !is_device && mount.token != self.null_memory_token
+                }
+                Content::Device(_) => is_device,
+            };
+            if mount.name != saved.name
+                || mount.start_address != saved.start_address
+                || !content_matches
+            {
+                return Err(SnapshotError::Mismatch(format!(
+                    "Expected '{}' at 0x{:08X}, found '{}' at 0x{:08X}",
+                    saved.name, saved.start_address, mount.name, mount.start_address
+                )));
+            }
+        }
+
+        for (mount, saved) in mounts.iter().zip(&snapshot.mounts) {
+            match saved.content {
+                Content::None => {}
+                Content::Device(ref state) => {
+                    self.device_for_token(&mount.token)
+                        .unwrap()
+                        .borrow_mut()
+                        .load_state(state)
+                        .map_err(SnapshotError::Mismatch)?;
+                }
+                Content::Memory(_) | Content::Sparse(_) => {
+                    let mut memory: Box<dyn Memory> = match saved.content {
+                        Content::Sparse(ref ranges) => {
+                            let mut memory = SparseMemory::new(saved.size);
+                            for &(start, ref words) in ranges {
+                                for (offset, word) in words.iter().enumerate() {
+                                    memory.set(start + offset, *word);
+                                }
+                            }
+                            Box::new(memory)
+                        }
+                        Content::Memory(ref words) => {
+                            Box::new(SliceMemory::from_slice(words.clone().into_boxed_slice()))
+                        }
+                        _ => unreachable!(),
+                    };
+                    if mount.token == self.bios_memory_token {
+                        memory = Box::new(memory::ReadOnlyMemory::new(memory));
+                    }
+                    self.memory.replace(&mount.token, memory);
+                }
+            }
+            if saved.enabled {
+                self.memory.enable_mount(&mount.token);
+            } else {
+                self.memory.disable_mount(&mount.token);
+            }
+        }
+        self.ip = snapshot.ip;
+        self.cycle_ip = snapshot.ip;
+        self.cycles = snapshot.cycles;
+        if let Some(ref mut journal) = self.journal {
+            journal.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes a snapshot of the current state to `w`.
+    pub fn save_snapshot<W: Write>(&self, w: &mut W) -> snapshot::Result<()> {
+        self.snapshot().write(w)
+    }
+
+    /// Reads a snapshot from `r` and restores it like `restore()`.
+    pub fn load_snapshot<R: Read>(&mut self, r: &mut R) -> snapshot::Result<()> {
+        let snapshot = Snapshot::read(r)?;
+        self.restore(&snapshot)
+    }
+
+    /// Creates an independent copy of the machine state to continue from, e.g. to try different inputs from the same state.
+    ///
+    /// The copy has the same `ip`, cycle count and memory layout. Mounted memories are copied with `Memory::fork()` and devices with `Device::fork_device()`, neither of which changes this emulator. `SparseMemory` and `CowMemory` share their pages with the copy until one side writes to them, so the RAM is forked in time proportional to its allocated pages rather than the size of the address space. A mounted `FileMemory` keeps writing to its file, while the copy gets its contents in host memory.
+    ///
+    /// Breakpoints, watchpoints and collected coverage are copied. The journal and the instruction cache are enabled in the copy if they are enabled here, but start out empty. Tracers and observers are not copied. Returns `None` if a memory or a device can’t be forked.
+    pub fn fork(&self) -> Option<Emulator> {
+        let mut devices = Vec::with_capacity(self.devices.len());
+        for (token, device) in &self.devices {
+            let device: Rc<RefCell<dyn Device>> = Rc::new(RefCell::new(device.borrow().fork_device()?));
+            devices.push((token.clone(), device));
+        }
+        let memory = {
+            let devices = &devices;
+            self.memory.fork_with(|token, memory| {
+                let device = devices.iter().find(|(device_token, _)| device_token == token);
+                match device {
+                    Some((_, device)) => Some(Box::new(DeviceMemory(device.clone()))),
+                    None => memory.fork(),
+                }
+            })?
+        };
+        let mut forked = Emulator {
+            devices,
+            tracers: Vec::new(),
+            observers: Vec::new(),
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: self.watchpoints.clone(),
+            next_watchpoint_id: self.next_watchpoint_id,
+            journal: self.journal.as_ref().map(|journal| Journal::new(journal.capacity())),
+            coverage: self.coverage.clone(),
+            instruction_cache: None,
+            device_ranges: None,
+            bios_memory_token: self.bios_memory_token.clone(),
+            null_memory_token: self.null_memory_token.clone(),
+            cycle_ip: self.cycle_ip,
+            memory,
+            ip: self.ip,
+            cycles: self.cycles,
+        };
+        if self.instruction_cache.is_some() {
+            forked.enable_instruction_cache();
+        }
+        Some(forked)
+    }
+
+    fn device_for_token(&self, token: &MemoryToken) -> Option<&Rc<RefCell<dyn Device>>> {
+        self.devices
+            .iter()
+            .find(|(device_token, _)| device_token == token)
+            .map(|(_, device)| device)
+    }
+
+    /// Starts recording changes in a journal of the given capacity, replacing the current journal.
+    ///
+    /// See the `journal` module for what is recorded.
+    pub fn enable_journal(&mut self, capacity: usize) {
+        self.journal = Some(Journal::new(capacity));
+    }
+
+    /// Stops recording changes and drops the journal.
+    pub fn disable_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Returns the journal, if enabled.
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Starts collecting code coverage, discarding previously collected coverage.
+    ///
+    /// See the `coverage` module.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    /// Stops collecting code coverage and returns the collected coverage.
+    pub fn disable_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Returns the collected coverage, if enabled.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Starts caching decoded instructions with an empty cache.
+    ///
+    /// See the `icache` module. This enables write tracking of `memory`.
+    pub fn enable_instruction_cache(&mut self) {
+        self.memory.track_writes(true);
+        self.instruction_cache = Some(InstructionCache::new(self.memory.generation()));
+    }
+
+    /// Stops caching decoded instructions and disables write tracking of `memory`.
+    pub fn disable_instruction_cache(&mut self) {
+        self.memory.track_writes(false);
+        self.instruction_cache = None;
+    }
+
+    /// Returns the instruction cache, if enabled.
+    pub fn instruction_cache(&self) -> Option<&InstructionCache> {
+        self.instruction_cache.as_ref()
+    }
+
+    /// Undoes up to `n` cycles using the journal.
+    ///
+    /// Returns the number of cycles that have been undone, which is smaller than `n` if the journal doesn’t reach back far enough.
+    pub fn step_back(&mut self, n: usize) -> usize {
+        (0..n).take_while(|_| self.undo_cycle().is_some()).count()
+    }
+
+    /// Undoes cycles until a cycle that wrote to an address in `range` has been undone.
+    ///
+    /// Afterwards, `ip` points to the instruction that did the write. Returns the number of cycles that have been undone or `None` if the journal doesn’t contain such a write. In that case, all journaled cycles have been undone.
+    pub fn step_back_to_write(&mut self, range: Range<usize>) -> Option<usize> {
+        let mut count = 0;
+        while let Some(entries) = self.undo_cycle() {
+            count += 1;
+            let wrote = entries.iter().any(|entry| match entry.change {
+                Change::Write { address, .. } => range.contains(&address),
+                _ => false,
+            });
+            if wrote {
+                return Some(count);
+            }
+        }
+        None
+    }
+
+    fn undo_cycle(&mut self) -> Option<Vec<JournalEntry>> {
+        if self.cycles == 0 {
+            return None;
+        }
+        let cycle = self.cycles - 1;
+        let journal = self.journal.as_mut()?;
+        // A cycle that faulted has only been started.
+        journal.pop_cycle(self.cycles);
+        let entries = journal.pop_cycle(cycle);
+        if entries.is_empty() {
+            return None;
+        }
+        for entry in entries.iter().rev() {
+            match entry.change {
+                Change::Cycle => {}
+                Change::Write { address, old_value } => {
+                    let _ = self.memory.try_set(address, old_value);
+                }
+                Change::BiosMount { was_enabled: true } => {
+                    self.memory.enable_mount(&self.bios_memory_token)
+                }
+                Change::BiosMount { was_enabled: false } => {
+                    self.memory.disable_mount(&self.bios_memory_token)
+                }
+            }
+        }
+        self.ip = entries[0].ip;
+        self.cycles = cycle;
+        Some(entries)
+    }
+
+    fn journal_change(&mut self, change: Change) {
+        let entry = JournalEntry {
+            cycle: self.cycles,
+            ip: self.cycle_ip,
+            change,
+        };
+        if let Some(ref mut journal) = self.journal {
+            journal.push(entry);
+        }
+    }
+
+    fn journal_write(&mut self, addr: usize) {
+        if self.journal.is_none() {
+            return;
+        }
+        if let Some(old_value) = self.memory.peek(addr) {
+            self.journal_change(Change::Write {
+                address: addr,
+                old_value,
+            });
+        }
+    }
+
+    /// Sets or clears a flag as part of the current cycle.
+    fn change_flag(&mut self, flag_idx: usize, value: bool) {
+        self.journal_write(address::FLAGS_START_ADDRESS + flag_idx / 32);
+        self.set_flag(flag_idx, value);
+    }
+
+    /// Sets or clears a flag on behalf of the CPU and reports the write to the observers.
+    fn cpu_change_flag(&mut self, flag_idx: usize, value: bool) {
+        self.change_flag(flag_idx, value);
+        if !self.observers.is_empty() {
+            let addr = address::FLAGS_START_ADDRESS + flag_idx / 32;
+            let value = self.memory.get(addr);
+            self.observe(addr, value, AccessKind::Write);
+        }
+    }
+
+    /// Reports an access the CPU has made outside of an instruction to the observers.
+    fn observe(&self, addr: usize, value: i32, kind: AccessKind) {
+        observer::notify(&self.observers, self.cycles, self.cycle_ip, addr, value, kind);
+    }
+
+    fn fetch(&mut self, ip: usize) -> Result<Instruction, Fault> {
+        self.sync_instruction_cache();
+        self.fetch_synced(ip)
+    }
+
+    /// Removes the instructions from the cache that might have changed since the last call.
+    fn sync_instruction_cache(&mut self) {
+        let cache = match self.instruction_cache {
+            Some(ref mut cache) => cache,
+            None => return,
+        };
+        if cache.generation() != self.memory.generation() {
+            // The layout has changed or `memory` has been replaced.
+            if !self.memory.is_tracking_writes() {
+                self.memory.track_writes(true);
+            }
+            cache.flush(self.memory.generation());
+        } else {
+            for addr in self.memory.drain_written() {
+                cache.invalidate(addr);
+            }
+        }
     }
 
-    fn is_bios_mounted(&self) -> bool {
-        self.memory.is_enabled_mount(&self.bios_memory_token)
+    /// Fetches an instruction like `fetch()` without calling `sync_instruction_cache()` first.
+    fn fetch_synced(&mut self, ip: usize) -> Result<Instruction, Fault> {
+        let cache = match self.instruction_cache {
+            Some(ref mut cache) => cache,
+            None => return Instruction::try_from_memory(ip, &self.memory),
+        };
+        if let Some(instr) = cache.get(ip) {
+            return Ok(instr);
+        }
+        let instr = Instruction::try_from_memory(ip, &self.memory)?;
+        let devices = &self.devices;
+        let memory = &self.memory;
+        let on_device = (0..4).any(|i| {
+            memory
+                .token_at(ip + i)
+                .is_some_and(|token| devices.iter().any(|(device, _)| *device == token))
+        });
+        if !on_device {
+            cache.insert(ip, instr);
+        }
+        Ok(instr)
+    }
+
+    fn execute(&mut self) -> Result<StepResult, Fault> {
+        let ip = self.ip;
+        let cycle = self.cycles;
+        let instr = self.fetch(ip)?;
+        let (execution, hit) = {
+            let mut journaled;
+            let mut memory: &mut dyn Memory = match self.journal {
+                Some(ref mut journal) => {
+                    journaled = JournaledMemory::new(&mut self.memory, journal, cycle, ip);
+                    &mut journaled
+                }
+                None => &mut self.memory,
+            };
+            let mut observed;
+            if !self.observers.is_empty() {
+                observed = ObservedMemory::new(memory, &self.observers, cycle, ip);
+                observed.fetched(&instr);
+                memory = &mut observed;
+            }
+            if self.watchpoints.is_empty() {
+                (instr.try_execute(&mut self.ip, memory)?, None)
+            } else {
+                let mut memory = WatchedMemory::new(memory, &self.watchpoints, ip);
+                let execution = instr.try_execute(&mut self.ip, &mut memory)?;
+                (execution, memory.first_hit())
+            }
+        };
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.record(ip, execution.jumped);
+        }
+        if !self.tracers.is_empty() {
+            let record = TraceRecord {
+                cycle: self.cycles,
+                ip,
+                instruction: instr,
+                execution,
+            };
+            for tracer in &self.tracers {
+                tracer.borrow_mut().record(&record);
+            }
+        }
+        if let Err(fault) = self.check_interrupt_return(ip) {
+            return Ok(StepResult::Fault(fault));
+        }
+        Ok(hit.map_or(StepResult::Executed, StepResult::Watchpoint))
     }
 
-    fn check_bios_mount(&mut self) {
-        if self.is_flag_set(address::FLAG_BIOS_DONE) && self.is_bios_mounted() {
-            self.memory.disable_mount(&self.bios_memory_token);
-        } else if !self.is_flag_set(address::FLAG_BIOS_DONE) && !self.is_bios_mounted() {
-            self.memory.enable_mount(&self.bios_memory_token);
+    fn tick_devices(&mut self, cycles: usize) {
+        let bios_enabled = self.memory.is_enabled_mount(&self.bios_memory_token);
+        for i in 0..self.devices.len() {
+            let device = self.devices[i].1.clone();
+            let line = {
+                let mut device = device.borrow_mut();
+                device.tick(cycles, &mut self.memory);
+                device.interrupt()
+            };
+            if let Some(line) = line {
+                assert!(
+                    line < address::NUM_INTERRUPT_LINES,
+                    "Invalid interrupt line {}",
+                    line
+                );
+                self.change_flag(address::FLAG_INTERRUPT_PENDING + line, true);
+            }
+        }
+        if self.memory.is_enabled_mount(&self.bios_memory_token) != bios_enabled {
+            self.journal_change(Change::BiosMount {
+                was_enabled: bios_enabled,
+            });
         }
     }
 
+    fn push(&mut self, value: i32) -> Result<(), Fault> {
+        let old_sp = self.memory.get(address::STACK_POINTER_ADDRESS);
+        self.observe(address::STACK_POINTER_ADDRESS, old_sp, AccessKind::Read);
+        let sp = (old_sp as usize).wrapping_sub(1) & address::MAX_ADDRESS;
+        let old_value = self.memory.peek(sp);
+        self.memory
+            .try_set(sp, value)
+            .map_err(|err| self.stack_fault(FaultKind::BadWrite, err.address()))?;
+        if let Some(old_value) = old_value {
+            self.journal_change(Change::Write {
+                address: sp,
+                old_value,
+            });
+        }
+        self.observe(sp, value, AccessKind::Write);
+        self.journal_write(address::STACK_POINTER_ADDRESS);
+        self.memory.set(address::STACK_POINTER_ADDRESS, sp as i32);
+        self.observe(address::STACK_POINTER_ADDRESS, sp as i32, AccessKind::Write);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i32, MemoryError> {
+        let old_sp = self.memory.get(address::STACK_POINTER_ADDRESS);
+        self.observe(address::STACK_POINTER_ADDRESS, old_sp, AccessKind::Read);
+        let sp = old_sp as usize & address::MAX_ADDRESS;
+        let value = self.memory.try_get(sp)?;
+        self.observe(sp, value, AccessKind::Read);
+        let new_sp = ((sp + 1) & address::MAX_ADDRESS) as i32;
+        self.journal_write(address::STACK_POINTER_ADDRESS);
+        self.memory.set(address::STACK_POINTER_ADDRESS, new_sp);
+        self.observe(address::STACK_POINTER_ADDRESS, new_sp, AccessKind::Write);
+        Ok(value)
+    }
+
+    fn stack_fault(&self, kind: FaultKind, address: usize) -> Fault {
+        Fault {
+            kind,
+            address,
+            ip: self.ip,
+        }
+    }
+
+    fn dispatch_interrupt(&mut self) -> Result<(), Fault> {
+        let ip = self.ip as i32;
+        self.push(ip)?;
+        self.cpu_change_flag(address::FLAG_INTERRUPT_ENABLE, false);
+        let vector = self.memory.get(address::IVT_START_ADDRESS);
+        self.observe(address::IVT_START_ADDRESS, vector, AccessKind::Read);
+        self.ip = vector as usize & address::MAX_ADDRESS;
+        Ok(())
+    }
+
+    /// Returns from an interrupt if the instruction at `ip` has set the `IR` flag.
+    fn check_interrupt_return(&mut self, ip: usize) -> Result<(), Fault> {
+        if self.is_flag_set(address::FLAG_INTERRUPT_RETURN) {
+            let return_ip = self.pop().map_err(|err| Fault {
+                kind: FaultKind::BadReturn,
+                address: err.address(),
+                ip,
+            })?;
+            self.ip = return_ip as usize & address::MAX_ADDRESS;
+            self.cpu_change_flag(address::FLAG_INTERRUPT_RETURN, false);
+            self.cpu_change_flag(address::FLAG_INTERRUPT_ENABLE, true);
+        }
+        Ok(())
+    }
+
     /// Checks if the halted flag is set.
     ///
     /// Equivalent to calling `is_flag_set(osciemu::memory::address::FLAG_HALTED)`.
@@ -108,7 +1115,11 @@ impl Emulator {
 
 #[cfg(test)]
 mod tests {
-    use memory::{address, Memory, NullMemory, SliceMemory};
+    use std::io::{self, Cursor};
+    use device::Uart;
+    use emulator::watchpoint::WatchKind;
+    use instruction::{Fault, FaultKind};
+    use memory::{address, Memory, MemoryError, SliceMemory, SparseMemory};
 
     #[test]
     fn unmounts_bios() {
@@ -131,6 +1142,64 @@ mod tests {
         assert_eq!(emu.memory.get(address::BIOS_START_ADDRESS + 4), 0);
     }
 
+    #[test]
+    fn instruction_cache() {
+        for &cached in &[true, false] {
+            let img = SliceMemory::from_slice(Box::new([
+                // Redirect the target of the next instruction to 21
+                16, 17, 6, 4,
+                16, 18, 20, 0,
+                0, 0, 0, 0, 0, 0, 0, 0,
+                5, -16, 10, 0, 0, 0, 0,
+            ]));
+            let bios = SliceMemory::from_slice(Box::new([
+                address::BIOS_START_ADDRESS as i32 + 4,
+                address::BIOS_START_ADDRESS as i32 + 5,
+                address::FLAGS_START_ADDRESS as i32,
+                0,
+                2,
+                0,
+            ]));
+            let mut emu = super::Emulator::new(Box::new(bios), Box::new(img));
+            if !cached {
+                emu.disable_instruction_cache();
+            }
+
+            // Self-modifying code
+            emu.ip = 4;
+            emu.step();
+            assert_eq!(emu.memory.get(20), -5);
+            assert_eq!(emu.ip, 0);
+            emu.step();
+            assert_eq!(emu.ip, 4);
+            emu.step();
+            assert_eq!(emu.memory.get(21), -5);
+
+            // Writes from outside
+            emu.memory.set(6, 22);
+            emu.ip = 4;
+            emu.step();
+            assert_eq!(emu.memory.get(22), -5);
+
+            // Unmounting the BIOS reveals the null memory
+            emu.ip = address::BIOS_START_ADDRESS;
+            emu.step();
+            assert!(emu.is_flag_set(address::FLAG_BIOS_DONE));
+            emu.ip = address::BIOS_START_ADDRESS;
+            emu.step();
+            assert_eq!(emu.ip, 0);
+
+            assert_eq!(emu.instruction_cache().is_some(), cached);
+        }
+        // Instructions overlapping a device are not cached
+        let mut emu = super::Emulator::from_bios_only(Box::new(SliceMemory::new(0)));
+        emu.ip = address::FLAGS_START_ADDRESS - 3;
+        emu.step();
+        emu.ip = address::FLAGS_START_ADDRESS - 3;
+        emu.step();
+        assert_eq!(emu.instruction_cache().unwrap().hits(), 0);
+    }
+
     #[test]
     fn is_halted() {
         let bios = SliceMemory::from_slice(Box::new([
@@ -163,17 +1232,758 @@ mod tests {
             0,
         ]));
         let mut emu = super::Emulator::from_bios_only(Box::new(bios));
-        emu.step();
+        assert_eq!(
+            emu.step(),
+            super::StepResult::Fault(Fault {
+                kind: FaultKind::WriteToRom,
+                address: address::BIOS_START_ADDRESS + 6,
+                ip: address::BIOS_START_ADDRESS,
+            })
+        );
         assert_eq!(emu.memory.get(address::BIOS_START_ADDRESS + 6), 0);
+        assert_eq!(emu.ip, address::BIOS_START_ADDRESS);
+    }
+
+    #[test]
+    fn bad_fetch() {
+        let bios = SliceMemory::from_slice(Box::new([0, 0, 0, 0]));
+        let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+        emu.ip = address::FLAGS_START_ADDRESS;
+        match emu.step() {
+            super::StepResult::Fault(fault) => {
+                assert_eq!(fault.kind, FaultKind::BadFetch);
+                assert_eq!(fault.address, address::MAX_ADDRESS + 1);
+                assert_eq!(fault.ip, address::FLAGS_START_ADDRESS);
+            }
+            result => panic!("Unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn bad_operand() {
+        let bios = SliceMemory::from_slice(Box::new([
+            -(address::BIOS_START_ADDRESS as i32 + 4),
+            0,
+            0,
+            0,
+            -1,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+        match emu.step() {
+            super::StepResult::Fault(fault) => {
+                assert_eq!(fault.kind, FaultKind::BadOperand);
+                assert_eq!(fault.address, 0xFFFFFFFF);
+            }
+            result => panic!("Unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn dispatches_interrupts() {
+        let bios = SliceMemory::from_slice(Box::new([0, 0, 0, 0]));
+        let mut emu = super::Emulator::new(Box::new(bios), Box::new(SliceMemory::new(16)));
+        emu.memory.set(address::STACK_POINTER_ADDRESS, 16);
+        emu.memory.set(address::IVT_START_ADDRESS, 8);
+
+        emu.raise_interrupt(2);
+        assert_eq!(emu.pending_interrupt(), Some(2));
+        emu.step();
+        // Interrupts are disabled, so the instruction has been executed.
+        assert_eq!(emu.ip, 0);
+
+        emu.ip = address::BIOS_START_ADDRESS;
+        emu.set_flag(address::FLAG_INTERRUPT_ENABLE, true);
+        assert_eq!(emu.step(), super::StepResult::Interrupt(2));
+        assert_eq!(emu.ip, 8);
+        assert_eq!(emu.memory.get(address::STACK_POINTER_ADDRESS), 15);
+        assert_eq!(emu.memory.get(15), address::BIOS_START_ADDRESS as i32);
+        assert!(!emu.is_flag_set(address::FLAG_INTERRUPT_ENABLE));
+        assert!(emu.is_flag_set(address::FLAG_INTERRUPT_PENDING + 2));
+    }
+
+    #[test]
+    fn returns_from_interrupts() {
+        let ir = 1 << address::FLAG_INTERRUPT_RETURN;
+        let bios = SliceMemory::from_slice(Box::new([
+            address::BIOS_START_ADDRESS as i32 + 4,
+            address::BIOS_START_ADDRESS as i32 + 5,
+            address::FLAGS_START_ADDRESS as i32,
+            0,
+            ir,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::new(Box::new(bios), Box::new(SliceMemory::new(16)));
+        emu.memory.set(address::STACK_POINTER_ADDRESS, 15);
+        emu.memory.set(15, 0x1234);
+
+        emu.step();
+        assert_eq!(emu.ip, 0x1234);
+        assert_eq!(emu.memory.get(address::STACK_POINTER_ADDRESS), 16);
+        assert!(emu.is_flag_set(address::FLAG_INTERRUPT_ENABLE));
+        assert!(!emu.is_flag_set(address::FLAG_INTERRUPT_RETURN));
+    }
+
+    #[test]
+    fn bad_return() {
+        let ir = 1 << address::FLAG_INTERRUPT_RETURN;
+        let bios = SliceMemory::from_slice(Box::new([
+            address::BIOS_START_ADDRESS as i32 + 4,
+            address::BIOS_START_ADDRESS as i32 + 5,
+            address::FLAGS_START_ADDRESS as i32,
+            0,
+            ir,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::new(Box::new(bios), Box::new(SliceMemory::new(16)));
+        let null_memory_token = emu.null_memory_token.clone();
+        emu.memory.disable_mount(&null_memory_token);
+        emu.memory.set(address::STACK_POINTER_ADDRESS, 0x100);
+
+        match emu.step() {
+            super::StepResult::Fault(fault) => assert_eq!(
+                fault,
+                Fault {
+                    kind: FaultKind::BadReturn,
+                    address: 0x100,
+                    ip: address::BIOS_START_ADDRESS,
+                }
+            ),
+            result => panic!("Unexpected {:?}", result),
+        }
+        // The instruction has been executed, but the return has not.
+        assert_eq!(emu.cycles, 1);
+        assert_eq!(emu.ip, address::BIOS_START_ADDRESS + 4);
+        assert_eq!(emu.memory.get(address::STACK_POINTER_ADDRESS), 0x100);
+        assert!(emu.is_flag_set(address::FLAG_INTERRUPT_RETURN));
+        assert!(!emu.is_flag_set(address::FLAG_INTERRUPT_ENABLE));
+    }
+
+    #[test]
+    fn observers() {
+        use emulator::observer::{AccessKind, MemoryAccess, MemoryObserver};
+        use emulator::observer::AccessKind::*;
+        use std::rc::Rc;
+
+        #[derive(Default)]
+        struct Recorder(Vec<(usize, i32, AccessKind)>);
+
+        impl MemoryObserver for Recorder {
+            fn access(&mut self, access: &MemoryAccess) {
+                self.0.push((access.address, access.value, access.kind));
+            }
+        }
+
+        let bios = SliceMemory::from_slice(Box::new([0, 0, 0, 0]));
+        let mut emu = super::Emulator::new(Box::new(bios), Box::new(SliceMemory::new(16)));
+        for (addr, value) in [(0, -8), (1, 10), (2, 11), (3, 0), (8, 9), (9, 5), (10, 2)] {
+            emu.memory.set(addr, value);
+        }
+        emu.memory.set(address::STACK_POINTER_ADDRESS, 16);
+        emu.memory.set(address::IVT_START_ADDRESS, 12);
+        emu.ip = 0;
+        let recorder = emu.attach_observer(Recorder::default());
+
+        emu.step();
+        assert_eq!(
+            recorder.borrow().0,
+            vec![
+                (0, -8, Fetch),
+                (1, 10, Fetch),
+                (2, 11, Fetch),
+                (3, 0, Fetch),
+                (8, 9, Read),
+                (9, 5, Read),
+                (10, 2, Read),
+                (11, 3, Write),
+            ]
+        );
+
+        recorder.borrow_mut().0.clear();
+        emu.set_flag(address::FLAG_INTERRUPT_ENABLE, true);
+        emu.raise_interrupt(1);
+        assert_eq!(emu.step(), super::StepResult::Interrupt(1));
+        let sp = address::STACK_POINTER_ADDRESS;
+        let flags = emu.memory.get(address::FLAGS_START_ADDRESS);
+        assert_eq!(
+            recorder.borrow().0,
+            vec![
+                (sp, 16, Read),
+                (15, 4, Write),
+                (sp, 15, Write),
+                (address::FLAGS_START_ADDRESS, flags, Write),
+                (address::IVT_START_ADDRESS, 12, Read),
+            ]
+        );
+
+        // The same instruction, but with a fault
+        recorder.borrow_mut().0.clear();
+        emu.memory.set(8, -1);
+        emu.ip = 0;
+        assert!(matches!(emu.step(), super::StepResult::Fault(_)));
+        assert_eq!(recorder.borrow().0.len(), 5);
+
+        emu.detach_observers();
+        emu.memory.set(8, 9);
+        emu.ip = 0;
+        emu.step();
+        assert_eq!(Rc::strong_count(&recorder), 1);
+    }
+
+    #[test]
+    fn fork() {
+        let bios = SliceMemory::from_slice(Box::new([
+            address::BIOS_START_ADDRESS as i32 + 4,
+            address::BIOS_START_ADDRESS as i32 + 5,
+            address::REGISTERS_START_ADDRESS as i32,
+            0,
+            5,
+            3,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::with_ram(
+            Box::new(bios),
+            Box::new(SliceMemory::new(16)),
+            super::Ram::Sparse,
+        );
+        emu.memory.set(3, 7);
+        emu.memory.set(0x100000, 8);
+        emu.memory.set(address::TIMER_START_ADDRESS, 9);
+
+        let mut child = emu.fork().unwrap();
+        assert_eq!(child.ip, emu.ip);
+        child.step();
+        child.memory.set(3, 70);
+        child.memory.set(0x100000, 80);
+        child.set_flag(address::FLAG_HALTED, true);
+        assert_eq!(child.get_register(0), 2);
+        child.memory.set(address::TIMER_START_ADDRESS, 90);
+        assert_eq!(
+            child.memory.try_set(address::BIOS_START_ADDRESS, 0),
+            Err(MemoryError::ReadOnly(address::BIOS_START_ADDRESS))
+        );
+
+        assert_eq!(emu.ip, address::BIOS_START_ADDRESS);
+        assert_eq!(emu.get_register(0), 0);
+        assert_eq!((emu.memory.get(3), emu.memory.get(0x100000)), (7, 8));
+        assert_eq!(emu.memory.get(address::TIMER_START_ADDRESS), 9);
+        assert!(!emu.is_halted());
+
+        let grandchild = child.fork().unwrap();
+        child.memory.set(3, 700);
+        assert_eq!(grandchild.memory.get(3), 70);
+        assert_eq!(grandchild.memory.get(0x100000), 80);
+        assert!(grandchild.is_halted());
+        assert_eq!(grandchild.memory.mounts().len(), emu.memory.mounts().len());
+
+        emu.attach_device(
+            address::UART_START_ADDRESS,
+            Uart::new(Box::new(io::empty()), Box::new(io::sink())),
+        );
+        assert!(emu.fork().is_none());
+    }
+
+    #[test]
+    fn fork_with_file() {
+        use memory::FileMemory;
+        use std::{env, fs};
+
+        let path = env::temp_dir().join(format!("osciemu-fork-{}.bin", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let bios = SliceMemory::from_slice(Box::new([0, 0, 0, 0]));
+        let mut emu = super::Emulator::new(Box::new(bios), Box::new(SliceMemory::new(0)));
+        emu.memory
+            .mount(0x1000, Box::new(FileMemory::create(&path, 2).unwrap())).unwrap();
+        emu.memory.set(0x1000, 1);
+
+        let mut child = emu.fork().unwrap();
+        child.memory.set(0x1001, 20);
+        assert_eq!(child.memory.get(0x1000), 1);
+        emu.memory.set(0x1001, 2);
+        emu.memory.flush().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [0, 0, 0, 1, 0, 0, 0, 2]);
+        assert_eq!(child.memory.get(0x1001), 20);
+
+        child.memory.flush().unwrap();
+        drop(child);
+        assert_eq!(fs::read(&path).unwrap(), [0, 0, 0, 1, 0, 0, 0, 2]);
+        drop(emu);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn attach_uart() {
+        let bios = SliceMemory::from_slice(Box::new([
+            address::UART_DATA_ADDRESS as i32,
+            address::BIOS_START_ADDRESS as i32 + 4,
+            address::REGISTERS_START_ADDRESS as i32,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+        emu.attach_device(
+            address::UART_START_ADDRESS,
+            Uart::new(Box::new(Cursor::new(vec![7])), Box::new(io::sink())),
+        );
+        emu.step();
+        assert_eq!(emu.get_register(0), 7);
+    }
+
+    #[test]
+    fn run() {
+        let bios = SliceMemory::from_slice(Box::new([
+            // 0x00: Decrement r0 and exit the loop once it is 0
+            address::REGISTERS_START_ADDRESS as i32,
+            address::BIOS_START_ADDRESS as i32 + 12,
+            address::REGISTERS_START_ADDRESS as i32,
+            address::BIOS_START_ADDRESS as i32 + 8,
+            // 0x04: Jump to 0x00
+            0,
+            0,
+            address::REGISTERS_START_ADDRESS as i32 + 1,
+            address::BIOS_START_ADDRESS as i32,
+            // 0x08: Jump to self
+            0,
+            0,
+            address::REGISTERS_START_ADDRESS as i32 + 1,
+            address::BIOS_START_ADDRESS as i32 + 8,
+            // 0x0C: Constant 1
+            1,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+        emu.memory.set(address::REGISTERS_START_ADDRESS, 3);
+        let outcome = emu.run(super::RunLimits {
+            max_cycles: Some(3),
+            detect_spin: true,
+        });
+        assert_eq!(outcome.reason, super::StopReason::CycleLimit);
+        assert_eq!(outcome.cycles, 3);
+        assert_eq!(emu.cycles, 3);
+
+        emu.add_breakpoint(address::BIOS_START_ADDRESS);
+        let outcome = emu.run(super::RunLimits::default());
+        assert_eq!(
+            outcome.reason,
+            super::StopReason::Breakpoint(address::BIOS_START_ADDRESS)
+        );
+        assert_eq!(outcome.cycles, 1);
+
+        assert!(emu.remove_breakpoint(address::BIOS_START_ADDRESS));
+        let outcome = emu.run(super::RunLimits::default());
+        assert_eq!(
+            outcome.reason,
+            super::StopReason::Spin(address::BIOS_START_ADDRESS + 8)
+        );
+        assert_eq!(outcome.cycles, 1);
+        assert_eq!(emu.cycles, 5);
+    }
+
+    #[test]
+    fn run_matches_step() {
+        let b = address::BIOS_START_ADDRESS as i32;
+        let r0 = address::REGISTERS_START_ADDRESS as i32;
+        let bios = [
+            // 0x00: Start a one-shot timer of 5 cycles
+            b + 20,
+            b + 21,
+            address::TIMER_COUNTER_ADDRESS as i32,
+            b + 4,
+            b + 22,
+            b + 21,
+            address::TIMER_CONTROL_ADDRESS as i32,
+            b + 8,
+            // 0x08: Decrement r0 and exit the loop once it is not positive
+            r0,
+            b + 22,
+            r0,
+            b + 16,
+            // 0x0C: Jump to 0x08
+            b + 21,
+            b + 21,
+            r0 + 1,
+            b + 8,
+            // 0x10: Halt
+            b + 22,
+            b + 21,
+            address::FLAGS_START_ADDRESS as i32,
+            0,
+            // 0x14: Constants
+            5,
+            0,
+            1,
+            0,
+        ];
+        let emulator = || {
+            let bios = SliceMemory::from_slice(Box::new(bios));
+            let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+            emu.memory.set(address::REGISTERS_START_ADDRESS, 20);
+            emu
+        };
+
+        let mut run = emulator();
+        let outcome = run.run(super::RunLimits::default());
+        assert_eq!(outcome.reason, super::StopReason::Halted);
+        let mut step = emulator();
+        while !step.is_halted() {
+            step.step();
+        }
+        assert_eq!((run.cycles, run.ip), (step.cycles, step.ip));
+        let words = |emu: &super::Emulator| {
+            (address::TIMER_START_ADDRESS..=address::MAX_ADDRESS)
+                .map(|addr| emu.memory.get(addr))
+                .collect::<Vec<i32>>()
+        };
+        assert_eq!(words(&run), words(&step));
+        assert_eq!(
+            run.memory.get(address::TIMER_CONTROL_ADDRESS),
+            ::device::timer::CONTROL_EXPIRED
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        let r0 = address::REGISTERS_START_ADDRESS;
+        let bios = SliceMemory::from_slice(Box::new([
+            // 0x00: *0x0E = **0x0C - 0
+            -(address::BIOS_START_ADDRESS as i32 + 12),
+            address::BIOS_START_ADDRESS as i32 + 4,
+            -(address::BIOS_START_ADDRESS as i32 + 14),
+            0,
+            // 0x04: Write 0 to r1
+            0,
+            0,
+            r0 as i32 + 1,
+            address::BIOS_START_ADDRESS as i32 + 8,
+            // 0x08: Write 0 to r1
+            0,
+            0,
+            r0 as i32 + 1,
+            address::BIOS_START_ADDRESS as i32 + 8,
+            // 0x0C: Pointers and data
+            address::BIOS_START_ADDRESS as i32 + 15,
+            0,
+            r0 as i32,
+            7,
+        ]));
+        let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+        let read = emu.add_watchpoint(
+            address::BIOS_START_ADDRESS + 15..address::BIOS_START_ADDRESS + 16,
+            WatchKind::Read,
+        );
+        let write = emu.add_watchpoint(r0..r0 + 2, WatchKind::Write);
+        let change = emu.add_watchpoint(r0..r0 + 2, WatchKind::Change);
+        assert_eq!(emu.watchpoints().len(), 3);
+
+        match emu.step() {
+            super::StepResult::Watchpoint(hit) => {
+                assert_eq!(hit.id, read);
+                assert_eq!(hit.value, 7);
+            }
+            result => panic!("Unexpected {:?}", result),
+        }
+
+        assert!(emu.remove_watchpoint(read));
+        assert!(!emu.remove_watchpoint(read));
+        emu.ip = address::BIOS_START_ADDRESS;
+        emu.memory.set(r0, 0);
+        match emu.run(super::RunLimits::default()).reason {
+            super::StopReason::Watchpoint(hit) => {
+                // The indirect write to r0 is caught.
+                assert_eq!(hit.id, write);
+                assert_eq!(hit.address, r0);
+                assert_eq!(hit.value, 7);
+            }
+            reason => panic!("Unexpected {:?}", reason),
+        }
+
+        assert!(emu.remove_watchpoint(write));
+        emu.ip = address::BIOS_START_ADDRESS;
+        emu.memory.set(r0, 0);
+        emu.memory.set(r0 + 1, 1);
+        let outcome = emu.run(super::RunLimits::default());
+        match outcome.reason {
+            super::StopReason::Watchpoint(hit) => {
+                assert_eq!(hit.id, change);
+                assert_eq!(hit.address, r0);
+                assert_eq!(hit.old_value, Some(0));
+            }
+            reason => panic!("Unexpected {:?}", reason),
+        }
+        let outcome = emu.run(super::RunLimits::default());
+        match outcome.reason {
+            super::StopReason::Watchpoint(hit) => {
+                assert_eq!(hit.address, r0 + 1);
+                assert_eq!(hit.ip, address::BIOS_START_ADDRESS + 4);
+            }
+            reason => panic!("Unexpected {:?}", reason),
+        }
+        // Writing 0 to r1 again doesn't change it.
+        let outcome = emu.run(super::RunLimits::default());
+        assert_eq!(
+            outcome.reason,
+            super::StopReason::Spin(address::BIOS_START_ADDRESS + 8)
+        );
+    }
+
+    #[test]
+    fn run_until_fault() {
+        let bios = SliceMemory::from_slice(Box::new([
+            0,
+            0,
+            address::BIOS_START_ADDRESS as i32,
+            0,
+        ]));
+        let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+        let outcome = emu.run(super::RunLimits::default());
+        match outcome.reason {
+            super::StopReason::Fault(fault) => assert_eq!(fault.kind, FaultKind::WriteToRom),
+            reason => panic!("Unexpected {:?}", reason),
+        }
+        assert_eq!(outcome.cycles, 0);
     }
 
     #[test]
     fn get_register() {
         let mut emu =
-            super::Emulator::from_bios_only(Box::new(NullMemory::new()));
+            super::Emulator::from_bios_only(Box::new(SliceMemory::new(4)));
         emu.memory.set(address::REGISTERS_START_ADDRESS + 1, 101);
         emu.memory.set(address::REGISTERS_START_ADDRESS, 100);
         assert_eq!(emu.get_register(1), 101);
         assert_eq!(emu.get_register(0), 100);
     }
+
+    #[test]
+    fn step_back() {
+        let bios = SliceMemory::from_slice(Box::new([
+            address::BIOS_START_ADDRESS as i32 + 4,
+            address::BIOS_START_ADDRESS as i32 + 5,
+            address::FLAGS_START_ADDRESS as i32,
+            0,
+            2,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::new(Box::new(bios), Box::new(SliceMemory::new(16)));
+        emu.memory.set(address::STACK_POINTER_ADDRESS, 16);
+        assert_eq!(emu.step_back(1), 0);
+
+        emu.enable_journal(100);
+        emu.step();
+        assert!(emu.is_flag_set(address::FLAG_BIOS_DONE));
+        assert_eq!(emu.memory.get(address::BIOS_START_ADDRESS + 4), 0);
+
+        emu.ip = address::BIOS_START_ADDRESS;
+        emu.set_flag(address::FLAG_INTERRUPT_ENABLE, true);
+        emu.raise_interrupt(1);
+        assert_eq!(emu.step(), super::StepResult::Interrupt(1));
+        assert_eq!(emu.memory.get(address::STACK_POINTER_ADDRESS), 15);
+
+        assert_eq!(emu.step_back(1), 1);
+        assert_eq!(emu.cycles, 1);
+        assert_eq!(emu.ip, address::BIOS_START_ADDRESS);
+        assert_eq!(emu.memory.get(address::STACK_POINTER_ADDRESS), 16);
+        assert_eq!(emu.memory.get(15), 0);
+        assert!(emu.is_flag_set(address::FLAG_INTERRUPT_ENABLE));
+
+        assert_eq!(emu.step_back(5), 1);
+        assert_eq!(emu.cycles, 0);
+        assert!(!emu.is_flag_set(address::FLAG_BIOS_DONE));
+        // The BIOS has been mounted again.
+        assert_eq!(emu.memory.get(address::BIOS_START_ADDRESS + 4), 2);
+        assert!(emu.journal().unwrap().is_empty());
+    }
+
+    #[test]
+    fn journal_without_writes() {
+        let bios = SliceMemory::from_slice(Box::new([
+            // 0x00: Write 'A' to the UART, which can’t be journaled
+            address::BIOS_START_ADDRESS as i32 + 8,
+            address::BIOS_START_ADDRESS as i32 + 9,
+            address::UART_DATA_ADDRESS as i32,
+            address::BIOS_START_ADDRESS as i32 + 4,
+            // 0x04: Same again
+            address::BIOS_START_ADDRESS as i32 + 8,
+            address::BIOS_START_ADDRESS as i32 + 9,
+            address::UART_DATA_ADDRESS as i32,
+            address::BIOS_START_ADDRESS as i32,
+            // 0x08: Constants
+            65,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+        emu.attach_device(
+            address::UART_START_ADDRESS,
+            Uart::new(Box::new(io::empty()), Box::new(io::sink())),
+        );
+        // Too small for a single cycle, but the cycle being recorded is kept.
+        emu.enable_journal(0);
+        emu.step();
+        emu.step();
+        assert_eq!(emu.step_back(5), 1);
+        assert_eq!(emu.cycles, 1);
+        assert_eq!(emu.ip, address::BIOS_START_ADDRESS + 4);
+
+        emu.enable_journal(100);
+        emu.step();
+        emu.step();
+        assert_eq!(emu.step_back(5), 2);
+        assert_eq!(emu.cycles, 1);
+        assert_eq!(emu.ip, address::BIOS_START_ADDRESS + 4);
+    }
+
+    #[test]
+    fn snapshot() {
+        let bios = SliceMemory::from_slice(Box::new([
+            address::BIOS_START_ADDRESS as i32 + 4,
+            address::BIOS_START_ADDRESS as i32 + 5,
+            address::FLAGS_START_ADDRESS as i32,
+            0,
+            2,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::new(Box::new(bios), Box::new(SliceMemory::new(16)));
+        emu.memory.set(3, 5);
+        emu.memory.set(address::TIMER_START_ADDRESS, 7);
+        emu.memory.set(address::TIMER_START_ADDRESS + 2, 1);
+        emu.step();
+        let mut file = Vec::new();
+        emu.save_snapshot(&mut file).unwrap();
+
+        let mut restored = super::Emulator::from_bios_only(Box::new(SliceMemory::new(0)));
+        restored.load_snapshot(&mut file.as_slice()).unwrap();
+        assert_eq!(restored.ip, address::BIOS_START_ADDRESS + 4);
+        assert_eq!(restored.cycles, 1);
+        assert_eq!(restored.memory.get(3), 5);
+        assert_eq!(restored.memory.get(address::TIMER_START_ADDRESS), 6);
+        assert!(restored.is_flag_set(address::FLAG_BIOS_DONE));
+        // The BIOS is unmounted, but has been restored.
+        assert_eq!(restored.memory.get(address::BIOS_START_ADDRESS + 4), 0);
+        restored.set_flag(address::FLAG_BIOS_DONE, false);
+        restored.step();
+        assert_eq!(restored.memory.get(address::BIOS_START_ADDRESS + 4), 2);
+        assert_eq!(
+            restored.memory.try_set(address::BIOS_START_ADDRESS, 0),
+            Err(MemoryError::ReadOnly(address::BIOS_START_ADDRESS))
+        );
+
+        let mut other = super::Emulator::from_bios_only(Box::new(SliceMemory::new(0)));
+        other.attach_device(
+            address::UART_START_ADDRESS,
+            Uart::new(Box::new(io::empty()), Box::new(io::sink())),
+        );
+        match other.load_snapshot(&mut file.as_slice()) {
+            Err(super::snapshot::SnapshotError::Mismatch(_)) => {}
+            result => panic!("Unexpected {:?}", result),
+        }
+        assert_eq!(other.cycles, 0);
+    }
+
+    #[test]
+    fn ram() {
+        let bios = || {
+            Box::new(SliceMemory::from_slice(Box::new([
+                // Store 3 at 0x1000 and 0x20000000, then halt
+                address::BIOS_START_ADDRESS as i32 + 12,
+                address::BIOS_START_ADDRESS as i32 + 13,
+                0x1000,
+                0,
+                address::BIOS_START_ADDRESS as i32 + 12,
+                address::BIOS_START_ADDRESS as i32 + 13,
+                0x20000000,
+                0,
+                address::BIOS_START_ADDRESS as i32 + 14,
+                0,
+                address::FLAGS_START_ADDRESS as i32,
+                0,
+                4,
+                1,
+                1,
+            ])))
+        };
+        let image = || Box::new(SliceMemory::new(16));
+        let mut emu = super::Emulator::new(bios(), image());
+        emu.run(super::RunLimits::default());
+        assert_eq!(emu.memory.get(0x1000), 0);
+
+        let mut emu = super::Emulator::with_ram(bios(), image(), super::Ram::Fixed(0x2000));
+        emu.run(super::RunLimits::default());
+        assert_eq!(emu.memory.get(0x1000), 3);
+        assert_eq!(emu.memory.get(0x20000000), 0);
+
+        let mut emu = super::Emulator::with_ram(bios(), image(), super::Ram::Sparse);
+        emu.run(super::RunLimits::default());
+        assert_eq!(emu.memory.get(0x1000), 3);
+        assert_eq!(emu.memory.get(0x20000000), 3);
+
+        let mut file = Vec::new();
+        emu.save_snapshot(&mut file).unwrap();
+        assert!(file.len() < 3 * 4 * SparseMemory::PAGE_SIZE);
+        let mut restored = super::Emulator::with_ram(bios(), image(), super::Ram::Sparse);
+        restored.load_snapshot(&mut file.as_slice()).unwrap();
+        assert_eq!(restored.memory.get(0x1000), 3);
+        assert_eq!(restored.memory.get(0x20000000), 3);
+        assert_eq!(restored.memory.get(0x20000001), 0);
+    }
+
+    #[test]
+    fn step_back_to_write() {
+        let r0 = address::REGISTERS_START_ADDRESS;
+        let bios = SliceMemory::from_slice(Box::new([
+            // 0x00: Decrement r0 and exit the loop once it is 0
+            r0 as i32,
+            address::BIOS_START_ADDRESS as i32 + 12,
+            r0 as i32,
+            address::BIOS_START_ADDRESS as i32 + 8,
+            // 0x04: Jump to 0x00
+            0,
+            0,
+            r0 as i32 + 1,
+            address::BIOS_START_ADDRESS as i32,
+            // 0x08: Jump to self
+            0,
+            0,
+            r0 as i32 + 1,
+            address::BIOS_START_ADDRESS as i32 + 8,
+            // 0x0C: Constant 1
+            1,
+            0,
+            0,
+            0,
+        ]));
+        let mut emu = super::Emulator::from_bios_only(Box::new(bios));
+        emu.enable_journal(100);
+        emu.memory.set(r0, 3);
+        emu.run(super::RunLimits {
+            max_cycles: Some(10),
+            detect_spin: false,
+        });
+        assert_eq!(emu.get_register(0), 0);
+
+        assert_eq!(emu.step_back_to_write(r0..r0 + 1), Some(6));
+        assert_eq!(emu.ip, address::BIOS_START_ADDRESS);
+        assert_eq!(emu.get_register(0), 1);
+        assert_eq!(emu.cycles, 4);
+
+        assert_eq!(emu.step_back_to_write(r0 + 2..r0 + 3), None);
+        assert_eq!(emu.cycles, 0);
+        assert_eq!(emu.get_register(0), 3);
+    }
 }
@@ -0,0 +1,173 @@
+//! Memory access observers.
+//!
+//! A `MemoryObserver` attached with `Emulator::attach_observer()` is called for every memory access the CPU makes. It receives the accessed address, the value that has been read or written, the kind of access and the `ip` and cycle of the instruction (or interrupt) that made it.
+//!
+//! # Ordering
+//!
+//! Observers are called synchronously right after an access has succeeded, in the order the accesses happen. Failed accesses are not reported. Within a cycle that executes an instruction, the order is:
+//!
+//! 1. Four `Fetch` accesses for the words of the instruction at `ip` to `ip + 3`. They are reported even if the decoded instruction has been cached (see the `icache` module).
+//! 2. The accesses of `Instruction::try_execute()`: a `Read` of the pointer of each indirect operand in the order A, B, target, jump, then a `Read` of A and of B, then the `Write` of the result. If an access faults, it is not reported and no further accesses happen.
+//! 3. If the instruction has set the `interruptReturn` flag, the accesses of the interrupt return: a `Read` of the stack pointer, a `Read` of the stack slot, a `Write` of the stack pointer and a `Write` of the flags word for each flag that is changed.
+//!
+//! Tracers receive the `TraceRecord` of the instruction after step 2 and before step 3. Coverage and watchpoint hits are recorded during step 2 as well.
+//!
+//! A cycle that dispatches an interrupt reports a `Read` of the stack pointer, a `Write` of the stack slot, a `Write` of the stack pointer, a `Write` of the flags word and a `Read` of the interrupt vector, in that order.
+//!
+//! Devices are ticked after all accesses of a cycle have been reported. Accesses made by devices, the flag checks the CPU makes at the start of every cycle and accesses through `Emulator::memory` are not reported. Changes of a flag are reported as a single `Write` of the whole flags word.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::emulator::{Emulator, RunLimits};
+//! # use osciemu::emulator::observer::{AccessKind, MemoryAccess, MemoryObserver};
+//! # use std::collections::BTreeMap;
+//! /// Counts writes per address.
+//! #[derive(Default)]
+//! struct Heatmap(BTreeMap<usize, usize>);
+//!
+//! impl MemoryObserver for Heatmap {
+//!     fn access(&mut self, access: &MemoryAccess) {
+//!         if access.kind == AccessKind::Write {
+//!             *self.0.entry(access.address).or_insert(0) += 1;
+//!         }
+//!     }
+//! }
+//!
+//! let mut bios_code = std::io::Cursor::new("
+//!     ## Set the halted flag
+//!     40000004 40000005 7FFFFFFE 0
+//!     1 0
+//! ");
+//! let bios = osciemu::loader::hexloader::load(&mut bios_code).unwrap();
+//! let mut emu = Emulator::from_bios_only(bios);
+//! let heatmap = emu.attach_observer(Heatmap::default());
+//! emu.run(RunLimits::default());
+//! assert_eq!(heatmap.borrow().0.get(&0x7FFFFFFE), Some(&1));
+//! ```
+use instruction::Instruction;
+use memory::{self, Memory};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Kind of a memory access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    /// A word of an instruction has been fetched.
+    Fetch,
+    /// A word has been read.
+    Read,
+    /// A word has been written.
+    Write,
+}
+
+/// A memory access reported to observers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    /// Number of cycles executed before the access
+    pub cycle: usize,
+    /// `ip` at the start of the cycle
+    pub ip: usize,
+    /// Accessed address
+    pub address: usize,
+    /// The value that has been read or written
+    pub value: i32,
+    /// Kind of access
+    pub kind: AccessKind,
+}
+
+/// Receives the memory accesses of an `Emulator`.
+pub trait MemoryObserver {
+    /// Called for every access. See the module documentation for the order of calls.
+    fn access(&mut self, access: &MemoryAccess);
+}
+
+/// Calls every observer with an access.
+pub(crate) fn notify(
+    observers: &[Rc<RefCell<dyn MemoryObserver>>],
+    cycle: usize,
+    ip: usize,
+    address: usize,
+    value: i32,
+    kind: AccessKind,
+) {
+    let access = MemoryAccess {
+        cycle,
+        ip,
+        address,
+        value,
+        kind,
+    };
+    for observer in observers {
+        observer.borrow_mut().access(&access);
+    }
+}
+
+/// Wraps a memory and reports all accesses to a list of observers.
+pub(crate) struct ObservedMemory<'a> {
+    memory: &'a mut dyn Memory,
+    observers: &'a [Rc<RefCell<dyn MemoryObserver>>],
+    cycle: usize,
+    ip: usize,
+}
+
+impl<'a> ObservedMemory<'a> {
+    pub fn new(
+        memory: &'a mut dyn Memory,
+        observers: &'a [Rc<RefCell<dyn MemoryObserver>>],
+        cycle: usize,
+        ip: usize,
+    ) -> Self {
+        ObservedMemory {
+            memory,
+            observers,
+            cycle,
+            ip,
+        }
+    }
+
+    /// Reports the fetches of the instruction at `ip`.
+    pub fn fetched(&self, instr: &Instruction) {
+        let words = [instr.op_a, instr.op_b, instr.target, instr.jmp];
+        for (offset, word) in words.iter().enumerate() {
+            self.notify(self.ip + offset, *word, AccessKind::Fetch);
+        }
+    }
+
+    fn notify(&self, address: usize, value: i32, kind: AccessKind) {
+        notify(self.observers, self.cycle, self.ip, address, value, kind);
+    }
+}
+
+impl<'a> Memory for ObservedMemory<'a> {
+    fn get(&self, addr: usize) -> i32 {
+        let value = self.memory.get(addr);
+        self.notify(addr, value, AccessKind::Read);
+        value
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        self.memory.set(addr, value);
+        self.notify(addr, value, AccessKind::Write);
+    }
+
+    fn size(&self) -> usize {
+        self.memory.size()
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        self.memory.peek(addr)
+    }
+
+    fn try_get(&self, addr: usize) -> memory::Result<i32> {
+        let value = self.memory.try_get(addr)?;
+        self.notify(addr, value, AccessKind::Read);
+        Ok(value)
+    }
+
+    fn try_set(&mut self, addr: usize, value: i32) -> memory::Result<()> {
+        self.memory.try_set(addr, value)?;
+        self.notify(addr, value, AccessKind::Write);
+        Ok(())
+    }
+}
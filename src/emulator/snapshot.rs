@@ -0,0 +1,292 @@
+//! Emulator snapshots.
+//!
+//! A `Snapshot` captures the state of an `Emulator`: `ip`, the cycle counter, the contents and enabled state of every mounted memory and the state of every attached device (see `Device::save_state()`). Breakpoints, watchpoints, tracers and the journal are not part of a snapshot.
+//!
+//! A snapshot can only be restored into an emulator with the same memory layout, i.e. the same mounts in the same order with the same names and mount points. This usually means that the same devices have to be attached before restoring. Mounted memories are replaced by memories holding the saved contents, so the size of the main memory and the BIOS doesn’t need to match. This includes file-backed memories (`FileMemory`), which no longer write to their files after a restore.
+//!
+//! # File format
+//!
+//! All numbers are stored in network-endian byte order.
+//!
+//! - The magic bytes `OSCISNAP` and the format version (u32, currently `VERSION`)
+//! - `ip` and `cycles` (u64 each)
+//! - The number of mounts (u32), followed by each mount in the order of `MappedMemory::mounts()`:
+//!   - The length of the name (u32) and the name (UTF-8)
+//!   - Mount point and size (u64 each)
+//!   - Whether the mount is enabled (u8)
+//!   - The content kind (u8): 0 for no content, 1 for memory contents, 2 for a device state, 3 for sparse memory contents
+//!   - For kinds 0 to 2: The number of words (u64), followed by the words (i32 each)
+//!   - For kind 3: The number of ranges (u64), followed by each range’s start address (u64), its number of words (u64) and the words (i32 each)
+//!
+//! Version 1 files are read as well. They don’t contain sparse memory contents.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::emulator::Emulator;
+//! # use osciemu::memory::{address, Memory, SliceMemory};
+//! let mut emu = Emulator::from_bios_only(Box::new(SliceMemory::new(4)));
+//! emu.memory.set(address::REGISTERS_START_ADDRESS, 42);
+//! let mut file = Vec::new();
+//! emu.save_snapshot(&mut file).unwrap();
+//!
+//! let mut emu = Emulator::from_bios_only(Box::new(SliceMemory::new(0)));
+//! emu.load_snapshot(&mut file.as_slice()).unwrap();
+//! assert_eq!(emu.get_register(0), 42);
+//! assert_eq!(emu.memory.get(address::BIOS_START_ADDRESS + 3), 0);
+//! ```
+extern crate byteorder;
+
+use self::byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+use std::{error, fmt, result};
+
+/// Magic bytes at the start of every snapshot file.
+pub const MAGIC: &[u8; 8] = b"OSCISNAP";
+/// Current version of the file format.
+pub const VERSION: u32 = 2;
+
+/// Error type for saving and restoring snapshots.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Reading or writing the file failed.
+    Io(io::Error),
+    /// The file is not a valid snapshot.
+    Format(String),
+    /// The snapshot doesn’t match the emulator it is restored into.
+    Mismatch(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Io(ref err) => write!(f, "{}", err),
+            SnapshotError::Format(ref msg) => write!(f, "Invalid snapshot: {}", msg),
+            SnapshotError::Mismatch(ref msg) => write!(f, "Snapshot doesn’t match: {}", msg),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            SnapshotError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+pub type Result<T> = result::Result<T, SnapshotError>;
+
+/// Saved content of a mount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    /// The mount has no content worth saving (e.g. `NullMemory`).
+    None,
+    /// The words of a memory.
+    Memory(Vec<i32>),
+    /// The state returned by `Device::save_state()`.
+    Device(Vec<i32>),
+    /// The allocated ranges of a memory (see `Memory::allocated()`) as start address and words. All other words are 0.
+    Sparse(Vec<(usize, Vec<i32>)>),
+}
+
+/// Saved state of a single mount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountSnapshot {
+    /// Name of the mount
+    pub name: String,
+    /// The mount point
+    pub start_address: usize,
+    /// Size of the mounted memory
+    pub size: usize,
+    /// Whether the mount is enabled
+    pub enabled: bool,
+    /// The content
+    pub content: Content,
+}
+
+/// Saved state of an emulator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Instruction pointer
+    pub ip: usize,
+    /// Number of cycles executed so far
+    pub cycles: usize,
+    /// All mounts in the order they have been mounted
+    pub mounts: Vec<MountSnapshot>,
+}
+
+impl Snapshot {
+    /// Writes the snapshot in the snapshot file format.
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u32::<NetworkEndian>(VERSION)?;
+        w.write_u64::<NetworkEndian>(self.ip as u64)?;
+        w.write_u64::<NetworkEndian>(self.cycles as u64)?;
+        w.write_u32::<NetworkEndian>(self.mounts.len() as u32)?;
+        for mount in &self.mounts {
+            w.write_u32::<NetworkEndian>(mount.name.len() as u32)?;
+            w.write_all(mount.name.as_bytes())?;
+            w.write_u64::<NetworkEndian>(mount.start_address as u64)?;
+            w.write_u64::<NetworkEndian>(mount.size as u64)?;
+            w.write_u8(mount.enabled as u8)?;
+            let (kind, words): (u8, &[i32]) = match mount.content {
+                Content::None => (0, &[]),
+                Content::Memory(ref words) => (1, words),
+                Content::Device(ref words) => (2, words),
+                Content::Sparse(ref ranges) => {
+                    w.write_u8(3)?;
+                    w.write_u64::<NetworkEndian>(ranges.len() as u64)?;
+                    for &(start, ref words) in ranges {
+                        w.write_u64::<NetworkEndian>(start as u64)?;
+                        write_words(w, words)?;
+                    }
+                    continue;
+                }
+            };
+            w.write_u8(kind)?;
+            write_words(w, words)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a snapshot in the snapshot file format.
+    pub fn read<R: Read>(r: &mut R) -> Result<Snapshot> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::Format(String::from("Not a snapshot file")));
+        }
+        let version = r.read_u32::<NetworkEndian>()?;
+        if version != 1 && version != VERSION {
+            return Err(SnapshotError::Format(format!(
+                "Unsupported version {}",
+                version
+            )));
+        }
+        let ip = r.read_u64::<NetworkEndian>()? as usize;
+        let cycles = r.read_u64::<NetworkEndian>()? as usize;
+        let num_mounts = r.read_u32::<NetworkEndian>()?;
+        let mut mounts = Vec::new();
+        for _ in 0..num_mounts {
+            let name_len = r.read_u32::<NetworkEndian>()? as usize;
+            let mut name = vec![0u8; name_len];
+            r.read_exact(&mut name)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| SnapshotError::Format(String::from("Invalid mount name")))?;
+            let start_address = r.read_u64::<NetworkEndian>()? as usize;
+            let size = r.read_u64::<NetworkEndian>()? as usize;
+            let enabled = r.read_u8()? != 0;
+            let kind = r.read_u8()?;
+            let content = match kind {
+                0 => {
+                    read_words(r)?;
+                    Content::None
+                }
+                1 => Content::Memory(read_words(r)?),
+                2 => Content::Device(read_words(r)?),
+                3 if version > 1 => {
+                    let num_ranges = r.read_u64::<NetworkEndian>()?;
+                    let mut ranges = Vec::new();
+                    for _ in 0..num_ranges {
+                        let start = r.read_u64::<NetworkEndian>()? as usize;
+                        let words = read_words(r)?;
+                        if start.saturating_add(words.len()) > size {
+                            return Err(SnapshotError::Format(format!(
+                                "Contents of '{}' exceed its size",
+                                name
+                            )));
+                        }
+                        ranges.push((start, words));
+                    }
+                    Content::Sparse(ranges)
+                }
+                _ => {
+                    return Err(SnapshotError::Format(format!(
+                        "Unknown content kind {}",
+                        kind
+                    )))
+                }
+            };
+            mounts.push(MountSnapshot {
+                name,
+                start_address,
+                size,
+                enabled,
+                content,
+            });
+        }
+        Ok(Snapshot { ip, cycles, mounts })
+    }
+}
+
+fn write_words<W: Write>(w: &mut W, words: &[i32]) -> Result<()> {
+    w.write_u64::<NetworkEndian>(words.len() as u64)?;
+    for word in words {
+        w.write_i32::<NetworkEndian>(*word)?;
+    }
+    Ok(())
+}
+
+fn read_words<R: Read>(r: &mut R) -> Result<Vec<i32>> {
+    let num_words = r.read_u64::<NetworkEndian>()? as usize;
+    let mut words = Vec::new();
+    for _ in 0..num_words {
+        words.push(r.read_i32::<NetworkEndian>()?);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Content, MountSnapshot, Snapshot, SnapshotError};
+
+    #[test]
+    fn write_and_read() {
+        let snapshot = Snapshot {
+            ip: 0x40000004,
+            cycles: 12,
+            mounts: vec![
+                MountSnapshot {
+                    name: String::from("image"),
+                    start_address: 0,
+                    size: 2,
+                    enabled: true,
+                    content: Content::Memory(vec![1, -1]),
+                },
+                MountSnapshot {
+                    name: String::from("timer"),
+                    start_address: 0x7FFFFFF3,
+                    size: 3,
+                    enabled: false,
+                    content: Content::Device(vec![0, 1, 2, 3]),
+                },
+                MountSnapshot {
+                    name: String::from("ram"),
+                    start_address: 0,
+                    size: 0x7FFFFFF3,
+                    enabled: true,
+                    content: Content::Sparse(vec![(0x400, vec![1, 2]), (0x1000, vec![3])]),
+                },
+            ],
+        };
+        let mut file = Vec::new();
+        snapshot.write(&mut file).unwrap();
+        assert_eq!(Snapshot::read(&mut file.as_slice()).unwrap(), snapshot);
+
+        // Bump the version
+        file[11] += 1;
+        match Snapshot::read(&mut file.as_slice()) {
+            Err(SnapshotError::Format(msg)) => assert_eq!(msg, "Unsupported version 3"),
+            result => panic!("Unexpected {:?}", result),
+        }
+        assert!(Snapshot::read(&mut &b"OSCISNAX"[..]).is_err());
+    }
+}
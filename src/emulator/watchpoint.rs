@@ -0,0 +1,196 @@
+//! Memory watchpoints.
+//!
+//! A watchpoint observes a range of addresses and stops `Emulator::run()` when an instruction accesses it. Watchpoints only see the accesses made while executing an instruction, i.e. operand reads (including the pointers of indirect operands) and the write of the result. Instruction fetches, interrupt dispatch and device ticks are not observed.
+//!
+//! The access that triggers a watchpoint has already happened when the emulator stops.
+use memory::{self, Memory};
+use std::cell::RefCell;
+use std::ops::Range;
+
+/// Kind of access a watchpoint reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Any read of a watched address.
+    Read,
+    /// Any write to a watched address.
+    Write,
+    /// A write that changes the value of a watched address.
+    ///
+    /// The old value is read using `Memory::peek()` before the write. Writes to addresses that can’t be peeked (like `uart_data`) are never reported as changes.
+    Change,
+}
+
+/// Identifies a watchpoint added with `Emulator::add_watchpoint()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WatchpointId(pub usize);
+
+/// A watched address range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// Identifier of the watchpoint
+    pub id: WatchpointId,
+    /// Watched addresses
+    pub range: Range<usize>,
+    /// Kind of access to watch for
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, kind: WatchKind, addr: usize) -> bool {
+        self.kind == kind && self.range.contains(&addr)
+    }
+}
+
+/// A triggered watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// The watchpoint that has been triggered
+    pub id: WatchpointId,
+    /// Kind of the triggered watchpoint
+    pub kind: WatchKind,
+    /// Accessed address
+    pub address: usize,
+    /// The value that has been read or written
+    pub value: i32,
+    /// The value before the write. Only set for `WatchKind::Change`.
+    pub old_value: Option<i32>,
+    /// Address of the accessing instruction
+    pub ip: usize,
+}
+
+/// Wraps a memory and checks all accesses against a list of watchpoints.
+pub(crate) struct WatchedMemory<'a> {
+    memory: &'a mut dyn Memory,
+    watchpoints: &'a [Watchpoint],
+    ip: usize,
+    hits: RefCell<Vec<WatchHit>>,
+}
+
+impl<'a> WatchedMemory<'a> {
+    pub fn new(memory: &'a mut dyn Memory, watchpoints: &'a [Watchpoint], ip: usize) -> Self {
+        WatchedMemory {
+            memory,
+            watchpoints,
+            ip,
+            hits: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Returns the first triggered watchpoint.
+    pub fn first_hit(&self) -> Option<WatchHit> {
+        self.hits.borrow().first().cloned()
+    }
+
+    fn record(&self, kind: WatchKind, addr: usize, value: i32, old_value: Option<i32>) {
+        let ip = self.ip;
+        self.hits.borrow_mut().extend(
+            self.watchpoints
+                .iter()
+                .filter(|wp| wp.matches(kind, addr))
+                .map(|wp| WatchHit {
+                    id: wp.id,
+                    kind,
+                    address: addr,
+                    value,
+                    old_value,
+                    ip,
+                }),
+        );
+    }
+
+    fn old_value(&self, addr: usize) -> Option<i32> {
+        if self
+            .watchpoints
+            .iter()
+            .any(|wp| wp.matches(WatchKind::Change, addr))
+        {
+            self.memory.peek(addr)
+        } else {
+            None
+        }
+    }
+
+    fn record_write(&self, addr: usize, value: i32, old_value: Option<i32>) {
+        self.record(WatchKind::Write, addr, value, None);
+        if let Some(old_value) = old_value {
+            if old_value != value {
+                self.record(WatchKind::Change, addr, value, Some(old_value));
+            }
+        }
+    }
+}
+
+impl<'a> Memory for WatchedMemory<'a> {
+    fn get(&self, addr: usize) -> i32 {
+        let value = self.memory.get(addr);
+        self.record(WatchKind::Read, addr, value, None);
+        value
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        let old_value = self.old_value(addr);
+        self.memory.set(addr, value);
+        self.record_write(addr, value, old_value);
+    }
+
+    fn size(&self) -> usize {
+        self.memory.size()
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        self.memory.peek(addr)
+    }
+
+    fn try_get(&self, addr: usize) -> memory::Result<i32> {
+        let value = self.memory.try_get(addr)?;
+        self.record(WatchKind::Read, addr, value, None);
+        Ok(value)
+    }
+
+    fn try_set(&mut self, addr: usize, value: i32) -> memory::Result<()> {
+        let old_value = self.old_value(addr);
+        self.memory.try_set(addr, value)?;
+        self.record_write(addr, value, old_value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WatchKind, WatchedMemory, Watchpoint, WatchpointId};
+    use memory::{Memory, SliceMemory};
+
+    #[test]
+    fn records_hits() {
+        let mut m = SliceMemory::from_slice(Box::new([1, 2, 3, 4]));
+        let watchpoints = [
+            Watchpoint {
+                id: WatchpointId(0),
+                range: 0..2,
+                kind: WatchKind::Read,
+            },
+            Watchpoint {
+                id: WatchpointId(1),
+                range: 2..4,
+                kind: WatchKind::Change,
+            },
+        ];
+        let mut wm = WatchedMemory::new(&mut m, &watchpoints, 8);
+        wm.get(2);
+        wm.set(2, 3);
+        wm.set(0, 0);
+        assert_eq!(wm.first_hit(), None);
+
+        wm.set(3, 5);
+        let hit = wm.first_hit().unwrap();
+        assert_eq!(hit.id, WatchpointId(1));
+        assert_eq!(hit.address, 3);
+        assert_eq!(hit.value, 5);
+        assert_eq!(hit.old_value, Some(4));
+        assert_eq!(hit.ip, 8);
+
+        let wm = WatchedMemory::new(&mut m, &watchpoints, 8);
+        assert_eq!(wm.try_get(1), Ok(2));
+        assert_eq!(wm.first_hit().unwrap().kind, WatchKind::Read);
+    }
+}
@@ -0,0 +1,721 @@
+//! GDB remote serial protocol stub.
+//!
+//! `GdbStub` lets a GDB-compatible frontend control an `Emulator` over any pair of streams, e.g. a TCP connection or stdin and stdout.
+//!
+//! GDB addresses bytes while osci addresses 32-bit words. The stub presents every word as 4 bytes in network-endian byte order, so the osci address `A` is the GDB address `4 * A`. This also applies to `ip` and to breakpoint and watchpoint addresses. All other registers are reported as they are stored.
+//!
+//! The register file is described to GDB through `target.xml`:
+//!
+//! | Number | Name    | Size    |
+//! |--------|---------|---------|
+//! | 0-3    | r0-r3   | 32 bits |
+//! | 4      | sp      | 32 bits |
+//! | 5      | flags   | 32 bits |
+//! | 6      | ip (pc) | 64 bits |
+//!
+//! Memory is accessed using `Memory::peek()`, so reading memory doesn’t cause side effects. Words that can’t be peeked (like `uart_data`) fail to read. The stub supports single-stepping, continuing, software breakpoints (`Z0`/`Z1`) and watchpoints (`Z2`-`Z4`). A running `continue` can’t be interrupted from the frontend.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::emulator::Emulator;
+//! # use osciemu::gdbstub::GdbStub;
+//! # use osciemu::memory::SliceMemory;
+//! let mut emu = Emulator::from_bios_only(Box::new(SliceMemory::new(4)));
+//! let mut output = Vec::new();
+//! GdbStub::new(&mut emu, &b"$p6#a6+$k#6b"[..], &mut output)
+//!     .serve()
+//!     .unwrap();
+//! assert_eq!(output, b"+$0000000100000000#01+");
+//! ```
+use emulator::watchpoint::{WatchKind, WatchpointId};
+use emulator::{Emulator, RunLimits, StepResult, StopReason};
+use memory::{address, Memory};
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+
+/// Number of registers in the register file.
+pub const NUM_GDB_REGISTERS: usize = 7;
+
+const REGISTER_SP: usize = 4;
+const REGISTER_FLAGS: usize = 5;
+const REGISTER_IP: usize = 6;
+
+/// Maximum size of a packet as advertised to the client.
+const PACKET_SIZE: usize = 0x1000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.osci.cpu">
+    <reg name="r0" bitsize="32" type="int32" regnum="0"/>
+    <reg name="r1" bitsize="32" type="int32"/>
+    <reg name="r2" bitsize="32" type="int32"/>
+    <reg name="r3" bitsize="32" type="int32"/>
+    <reg name="sp" bitsize="32" type="int32"/>
+    <reg name="flags" bitsize="32" type="int32"/>
+    <reg name="ip" bitsize="64" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// What to do after handling a packet.
+enum Action {
+    Reply(String),
+    ReplyAndClose(String),
+    Close,
+}
+
+/// Serves the GDB remote serial protocol for an `Emulator`.
+pub struct GdbStub<'a, R: Read, W: Write> {
+    emulator: &'a mut Emulator,
+    input: io::Bytes<BufReader<R>>,
+    output: W,
+    limits: RunLimits,
+    ack: bool,
+    last_packet: Vec<u8>,
+    watchpoints: HashMap<(char, usize, usize), Vec<WatchpointId>>,
+}
+
+impl<'a, R: Read, W: Write> GdbStub<'a, R, W> {
+    /// Creates a stub that reads packets from `input` and writes replies to `output`.
+    pub fn new(emulator: &'a mut Emulator, input: R, output: W) -> Self {
+        GdbStub {
+            emulator,
+            input: BufReader::new(input).bytes(),
+            output,
+            limits: RunLimits::default(),
+            ack: true,
+            last_packet: Vec::new(),
+            watchpoints: HashMap::new(),
+        }
+    }
+
+    /// Sets the limits used when continuing.
+    pub fn set_limits(&mut self, limits: RunLimits) {
+        self.limits = limits;
+    }
+
+    /// Handles packets until the frontend detaches, kills the program or closes the connection.
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet) {
+                Action::Reply(reply) => self.send(&reply)?,
+                Action::ReplyAndClose(reply) => {
+                    self.send(&reply)?;
+                    break;
+                }
+                Action::Close => break,
+            }
+        }
+        self.output.flush()
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        self.input.next().map_or(Ok(None), |byte| byte.map(Some))
+    }
+
+    /// Reads the next packet, answering acknowledgements and dropping interrupt requests.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.next_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(b'-') => {
+                    let packet = self.last_packet.clone();
+                    self.output.write_all(&packet)?;
+                    self.output.flush()?;
+                    continue;
+                }
+                // Acknowledgements and interrupt requests (0x03)
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            let mut escaped = false;
+            let mut sum = 0u8;
+            loop {
+                let byte = self.next_byte()?;
+                if let Some(byte) = byte {
+                    sum = sum.wrapping_add(byte);
+                }
+                match byte {
+                    None => return Ok(None),
+                    Some(b'#') if !escaped => {
+                        sum = sum.wrapping_sub(b'#');
+                        break;
+                    }
+                    Some(b'}') if !escaped => escaped = true,
+                    Some(byte) if escaped => {
+                        data.push(byte ^ 0x20);
+                        escaped = false;
+                    }
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            for digit in checksum.iter_mut() {
+                *digit = match self.next_byte()? {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+            }
+            let expected = String::from_utf8_lossy(&checksum);
+            let valid = u8::from_str_radix(&expected, 16)
+                .map(|checksum| checksum == sum)
+                .unwrap_or(false);
+            if self.ack {
+                self.output.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let data = escape(reply.as_bytes());
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&data);
+        packet.extend_from_slice(format!("#{:02x}", checksum_of(&data)).as_bytes());
+        self.output.write_all(&packet)?;
+        self.output.flush()?;
+        self.last_packet = packet;
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> Action {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match command {
+            "?" => self.stop_status(),
+            "g" => (0..NUM_GDB_REGISTERS)
+                .map(|reg| self.read_register(reg))
+                .collect(),
+            "G" => self.write_registers(args),
+            "p" => parse_hex(args)
+                .filter(|reg| *reg < NUM_GDB_REGISTERS)
+                .map_or_else(error, |reg| self.read_register(reg)),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.emulator.ip = addr / 4;
+                }
+                match self.emulator.step() {
+                    StepResult::Fault(_) => String::from("S0b"),
+                    StepResult::Watchpoint(hit) => watch_reply(hit.kind, hit.address),
+                    _ if self.emulator.is_halted() => String::from("W00"),
+                    _ => String::from("S05"),
+                }
+            }
+            "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.emulator.ip = addr / 4;
+                }
+                let outcome = self.emulator.run(self.limits);
+                stop_reply(outcome.reason)
+            }
+            "Z" => self.insert_point(args),
+            "z" => self.remove_point(args),
+            "H" | "T" => String::from("OK"),
+            "D" => return Action::ReplyAndClose(String::from("OK")),
+            "k" => return Action::Close,
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+                PACKET_SIZE
+            );
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let mut parts = args.split(',').map(parse_hex);
+            return match (parts.next(), parts.next()) {
+                (Some(Some(offset)), Some(Some(length))) => {
+                    let offset = offset.min(TARGET_XML.len());
+                    let end = offset.saturating_add(length).min(TARGET_XML.len());
+                    let marker = if end == TARGET_XML.len() { "l" } else { "m" };
+                    format!("{}{}", marker, &TARGET_XML[offset..end])
+                }
+                _ => error(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.ack = false;
+                String::from("OK")
+            }
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    fn stop_status(&self) -> String {
+        if self.emulator.is_halted() {
+            String::from("W00")
+        } else {
+            String::from("S05")
+        }
+    }
+
+    fn register_address(reg: usize) -> usize {
+        match reg {
+            REGISTER_SP => address::STACK_POINTER_ADDRESS,
+            REGISTER_FLAGS => address::FLAGS_START_ADDRESS,
+            reg => address::REGISTERS_START_ADDRESS + reg,
+        }
+    }
+
+    fn read_register(&self, reg: usize) -> String {
+        if reg == REGISTER_IP {
+            return format!("{:016x}", self.emulator.ip as u64 * 4);
+        }
+        let value = self
+            .emulator
+            .memory
+            .peek(Self::register_address(reg))
+            .unwrap_or(0);
+        format!("{:08x}", value as u32)
+    }
+
+    fn set_register(&mut self, reg: usize, hex: &str) -> bool {
+        let value = match u64::from_str_radix(hex, 16) {
+            Ok(value) => value,
+            Err(_) => return false,
+        };
+        if reg == REGISTER_IP {
+            self.emulator.ip = (value / 4) as usize;
+            return true;
+        }
+        self.emulator
+            .memory
+            .try_set(Self::register_address(reg), value as u32 as i32)
+            .is_ok()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        if args.len() != 8 * (NUM_GDB_REGISTERS - 1) + 16 || !args.is_ascii() {
+            return error();
+        }
+        for reg in 0..NUM_GDB_REGISTERS {
+            let start = 8 * reg;
+            let end = if reg == REGISTER_IP {
+                start + 16
+            } else {
+                start + 8
+            };
+            if !self.set_register(reg, &args[start..end]) {
+                return error();
+            }
+        }
+        String::from("OK")
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, '=');
+        match (parts.next().and_then(parse_hex), parts.next()) {
+            (Some(reg), Some(value)) if reg < NUM_GDB_REGISTERS => {
+                if self.set_register(reg, value) {
+                    String::from("OK")
+                } else {
+                    error()
+                }
+            }
+            _ => error(),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, length) = match parse_address_length(args) {
+            Some(range) => range,
+            None => return error(),
+        };
+        // Every byte takes two hex digits in the reply.
+        let length = length.min(PACKET_SIZE / 2);
+        let mut reply = String::new();
+        for byte_addr in addr..addr.saturating_add(length) {
+            match self.emulator.memory.peek(byte_addr / 4) {
+                Some(word) => {
+                    let shift = 8 * (3 - byte_addr % 4);
+                    reply.push_str(&format!("{:02x}", (word as u32 >> shift) as u8));
+                }
+                None if reply.is_empty() => return error(),
+                // Partial reads are allowed.
+                None => break,
+            }
+        }
+        reply
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, ':');
+        let (addr, length) = match parts.next().and_then(parse_address_length) {
+            Some(range) => range,
+            None => return error(),
+        };
+        let data = match parts.next().and_then(parse_bytes) {
+            Some(ref data) if data.len() == length => data.clone(),
+            _ => return error(),
+        };
+        for (i, byte) in data.into_iter().enumerate() {
+            let byte_addr = addr + i;
+            let word = match self.emulator.memory.peek(byte_addr / 4) {
+                Some(word) => word as u32,
+                None => return error(),
+            };
+            let shift = 8 * (3 - byte_addr % 4);
+            let word = (word & !(0xFF << shift)) | (u32::from(byte) << shift);
+            if self
+                .emulator
+                .memory
+                .try_set(byte_addr / 4, word as i32)
+                .is_err()
+            {
+                return error();
+            }
+        }
+        String::from("OK")
+    }
+
+    fn insert_point(&mut self, args: &str) -> String {
+        let (kind, addr, length) = match parse_point(args) {
+            Some(point) => point,
+            None => return error(),
+        };
+        let end = match addr.checked_add(length.max(1)) {
+            Some(end) => end,
+            None => return error(),
+        };
+        let range = addr / 4..end.div_ceil(4);
+        let kinds: &[WatchKind] = match kind {
+            '0' | '1' => {
+                if addr % 4 != 0 {
+                    return error();
+                }
+                self.emulator.add_breakpoint(addr / 4);
+                return String::from("OK");
+            }
+            '2' => &[WatchKind::Write],
+            '3' => &[WatchKind::Read],
+            '4' => &[WatchKind::Read, WatchKind::Write],
+            _ => return String::new(),
+        };
+        let ids = kinds
+            .iter()
+            .map(|kind| self.emulator.add_watchpoint(range.clone(), *kind))
+            .collect::<Vec<_>>();
+        self.watchpoints
+            .entry((kind, addr, length))
+            .or_default()
+            .extend(ids);
+        String::from("OK")
+    }
+
+    fn remove_point(&mut self, args: &str) -> String {
+        let (kind, addr, length) = match parse_point(args) {
+            Some(point) => point,
+            None => return error(),
+        };
+        match kind {
+            '0' | '1' => {
+                self.emulator.remove_breakpoint(addr / 4);
+            }
+            '2' | '3' | '4' => {
+                for id in self
+                    .watchpoints
+                    .remove(&(kind, addr, length))
+                    .unwrap_or_default()
+                {
+                    self.emulator.remove_watchpoint(id);
+                }
+            }
+            _ => return String::new(),
+        }
+        String::from("OK")
+    }
+}
+
+fn error() -> String {
+    String::from("E01")
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Halted => String::from("W00"),
+        StopReason::Breakpoint(_) => String::from("T05swbreak:;"),
+        StopReason::Watchpoint(hit) => watch_reply(hit.kind, hit.address),
+        StopReason::Fault(_) => String::from("S0b"),
+        StopReason::CycleLimit | StopReason::Spin(_) => String::from("S05"),
+    }
+}
+
+fn watch_reply(kind: WatchKind, addr: usize) -> String {
+    let name = match kind {
+        WatchKind::Read => "rwatch",
+        WatchKind::Write | WatchKind::Change => "watch",
+    };
+    format!("T05{}:{:x};", name, addr * 4)
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Escapes the characters that can’t appear in a packet.
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for byte in data {
+        match *byte {
+            b'$' | b'#' | b'}' | b'*' => {
+                escaped.push(b'}');
+                escaped.push(byte ^ 0x20);
+            }
+            byte => escaped.push(byte),
+        }
+    }
+    escaped
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parses `ADDR,LENGTH`.
+fn parse_address_length(s: &str) -> Option<(usize, usize)> {
+    let mut parts = s.splitn(2, ',');
+    match (
+        parts.next().and_then(parse_hex),
+        parts.next().and_then(parse_hex),
+    ) {
+        (Some(addr), Some(length)) => Some((addr, length)),
+        _ => None,
+    }
+}
+
+/// Parses the arguments of `Z` and `z` packets: `TYPE,ADDR,KIND`.
+fn parse_point(s: &str) -> Option<(char, usize, usize)> {
+    let mut parts = s.splitn(2, ',');
+    let kind = parts.next()?.chars().next()?;
+    let (addr, length) = parse_address_length(parts.next()?)?;
+    Some((kind, addr, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GdbStub;
+    use emulator::Emulator;
+    use memory::{address, Memory, SliceMemory};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Frames a packet as a client would send it.
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}+", data, super::checksum_of(data.as_bytes()))
+    }
+
+    /// Sends the packets to a stub and returns its replies without framing.
+    fn script(emu: &mut Emulator, packets: &[&str]) -> Vec<String> {
+        let input: String = packets.iter().map(|p| packet(p)).collect();
+        let mut output = Vec::new();
+        GdbStub::new(emu, input.as_bytes(), &mut output)
+            .serve()
+            .unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .split('$')
+            .skip(1)
+            .map(|reply| String::from(&reply[..reply.find('#').unwrap()]))
+            .collect()
+    }
+
+    fn emulator() -> Emulator {
+        let bios = SliceMemory::from_slice(Box::new([
+            // 0x00: Decrement r0 and exit the loop once it is not positive
+            address::REGISTERS_START_ADDRESS as i32,
+            address::BIOS_START_ADDRESS as i32 + 12,
+            address::REGISTERS_START_ADDRESS as i32,
+            address::BIOS_START_ADDRESS as i32 + 8,
+            // 0x04: Jump to 0x00
+            address::BIOS_START_ADDRESS as i32 + 13,
+            address::BIOS_START_ADDRESS as i32 + 13,
+            address::REGISTERS_START_ADDRESS as i32 + 1,
+            address::BIOS_START_ADDRESS as i32,
+            // 0x08: Halt
+            address::BIOS_START_ADDRESS as i32 + 12,
+            address::BIOS_START_ADDRESS as i32 + 13,
+            address::FLAGS_START_ADDRESS as i32,
+            0,
+            // 0x0C: Constants
+            1,
+            0,
+            0,
+            0,
+        ]));
+        Emulator::new(Box::new(bios), Box::new(SliceMemory::new(4)))
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut emu = emulator();
+        emu.memory.set(address::REGISTERS_START_ADDRESS, 2);
+        let replies = script(
+            &mut emu,
+            &[
+                "qSupported:multiprocess+",
+                "?",
+                "g",
+                "P1=0000ffff",
+                "p1",
+                "p7",
+                "m100000030,6",
+                "M4,4:12345678",
+                "m4,2",
+                "M2,1:ff",
+                "D",
+            ],
+        );
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert_eq!(replies[1], "S05");
+        assert_eq!(
+            replies[2],
+            format!("00000002{}0000000100000000", "0".repeat(40))
+        );
+        assert_eq!(replies[3], "OK");
+        assert_eq!(replies[4], "0000ffff");
+        assert_eq!(replies[5], "E01");
+        assert_eq!(replies[6], "000000010000");
+        assert_eq!(replies[7], "OK");
+        assert_eq!(replies[8], "1234");
+        assert_eq!(replies[9], "OK");
+        assert_eq!(replies[10], "OK");
+        assert_eq!(emu.get_register(1), 0xFFFF);
+        assert_eq!(emu.memory.get(1), 0x12345678);
+        assert_eq!(emu.memory.get(0), 0x0000FF00);
+    }
+
+    #[test]
+    fn step_continue_and_breakpoints() {
+        let mut emu = emulator();
+        emu.memory.set(address::REGISTERS_START_ADDRESS, 2);
+        let replies = script(
+            &mut emu,
+            &[
+                "s",
+                "p6",
+                "Z0,100000000,4",
+                "c",
+                "z0,100000000,4",
+                "c",
+                "c",
+                "k",
+            ],
+        );
+        assert_eq!(
+            replies,
+            vec![
+                "S05",
+                "0000000100000010",
+                "OK",
+                "T05swbreak:;",
+                "OK",
+                "W00",
+                "W00",
+            ]
+        );
+        assert!(emu.is_halted());
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut emu = emulator();
+        emu.memory.set(address::REGISTERS_START_ADDRESS, 2);
+        let r1 = format!("{:x}", (address::REGISTERS_START_ADDRESS + 1) * 4);
+        let replies = script(
+            &mut emu,
+            &[&format!("Z2,{},4", r1), "c", &format!("z2,{},4", r1), "c"],
+        );
+        assert_eq!(replies[1], format!("T05watch:{};", r1));
+        assert_eq!(replies[3], "W00");
+    }
+
+    #[test]
+    fn limits() {
+        let mut emu = emulator();
+        let replies = script(&mut emu, &["m0,ffffffff", "Z2,fffffffffffffffc,8", "D"]);
+        assert_eq!(replies[0].len(), super::PACKET_SIZE);
+        assert_eq!(replies[1], "E01");
+    }
+
+    #[test]
+    fn framing() {
+        let mut emu = emulator();
+        let mut output = Vec::new();
+        // A packet with a bad checksum is answered with a NAK and a NAK is answered by resending the last packet.
+        GdbStub::new(
+            &mut emu,
+            &b"$?#00$?#3f-+$QStartNoAckMode#b0$?#3f"[..],
+            &mut output,
+        )
+        .serve()
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "-+$S05#b8$S05#b8+$OK#9a$S05#b8"
+        );
+
+        let replies = script(&mut emu, &["qXfer:features:read:target.xml:0,10"]);
+        assert_eq!(replies[0], "m<?xml version=\"1");
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut replies = Vec::new();
+            for data in &["p0", "c", "D"] {
+                stream.write_all(packet(data).as_bytes()).unwrap();
+                let mut reply = Vec::new();
+                let mut byte = [0u8];
+                // Read the acknowledgement, the packet and its checksum
+                while reply.len() < 3 || reply[reply.len() - 3] != b'#' {
+                    stream.read_exact(&mut byte).unwrap();
+                    reply.push(byte[0]);
+                }
+                replies.push(String::from_utf8(reply).unwrap());
+            }
+            replies
+        });
+
+        let mut emu = emulator();
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(&mut emu, stream.try_clone().unwrap(), stream)
+            .serve()
+            .unwrap();
+        assert_eq!(
+            client.join().unwrap(),
+            vec!["+$00000000#80", "+$W00#b7", "+$OK#9a"]
+        );
+    }
+}
@@ -15,13 +15,119 @@
 //!   GOTO jmp;
 //! ```
 //!
+//! If any of the memory accesses is invalid, the instruction faults (see `Instruction::try_execute()`).
+//!
 //! [SUBLEQ]: https://esolangs.org/wiki/Subleq
 
-use memory::Memory;
-use std::fmt;
+use memory::{Memory, MemoryError};
+use std::{error, fmt, result};
+
+/// Size of an instruction in words.
+pub const INSTRUCTION_SIZE: usize = 4;
 
 // TODO: Consider a flag to switch between absolute and relative addressing.
 
+/// Kind of an execution fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// The instruction itself could not be read.
+    BadFetch,
+    /// An operand (or the value it points to) could not be read.
+    BadOperand,
+    /// The result could not be written.
+    BadWrite,
+    /// The result was written to read-only memory.
+    WriteToRom,
+    /// The instruction set the `IR` flag, but the return address could not be popped from the stack.
+    ///
+    /// Unlike all other faults, this one is raised after the instruction has been executed.
+    BadReturn,
+}
+
+/// A failed memory access during execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    /// What went wrong
+    pub kind: FaultKind,
+    /// The address of the failed access
+    pub address: usize,
+    /// Address of the faulting instruction
+    pub ip: usize,
+}
+
+impl Fault {
+    fn from_read(kind: FaultKind, err: MemoryError, ip: usize) -> Fault {
+        Fault {
+            kind,
+            address: err.address(),
+            ip,
+        }
+    }
+
+    fn from_write(err: MemoryError, ip: usize) -> Fault {
+        let kind = match err {
+            MemoryError::ReadOnly(_) => FaultKind::WriteToRom,
+            _ => FaultKind::BadWrite,
+        };
+        Fault {
+            kind,
+            address: err.address(),
+            ip,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self.kind {
+            FaultKind::BadFetch => "Bad instruction fetch",
+            FaultKind::BadOperand => "Bad operand read",
+            FaultKind::BadWrite => "Bad write",
+            FaultKind::WriteToRom => "Write to read-only memory",
+            FaultKind::BadReturn => "Bad interrupt return",
+        };
+        write!(
+            f,
+            "{} at 0x{:08X} (ip 0x{:08X})",
+            description, self.address, self.ip
+        )
+    }
+}
+
+impl error::Error for Fault {}
+
+pub type Result<T> = result::Result<T, Fault>;
+
+/// Details of a successfully executed instruction as returned by `Instruction::try_execute()`.
+///
+/// The addresses are the operands after resolving indirect operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Execution {
+    /// Address of operand A
+    pub op_a: usize,
+    /// Address of operand B
+    pub op_b: usize,
+    /// Address the result has been written to
+    pub target: usize,
+    /// Address to jump to when the result is non-positive
+    pub jmp: usize,
+    /// Value of operand A
+    pub a: i32,
+    /// Value of operand B
+    pub b: i32,
+    /// The written value `a - b`
+    pub result: i32,
+    /// Whether the jump has been taken
+    pub jumped: bool,
+}
+
+/// Converts an operand value to an address.
+///
+/// Negative values are mapped outside of the address space so that accessing them fails.
+fn to_address(value: i32) -> usize {
+    value as u32 as usize
+}
+
 /// Data structure for a single instruction.
 ///
 /// # Examples
@@ -37,6 +143,7 @@ use std::fmt;
 /// Instruction::execute_at(&mut ip, &mut m);
 /// assert_eq!(ip, 128);
 /// ```
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Instruction {
     /// Address of operand A
     pub op_a: i32,
@@ -50,7 +157,7 @@ pub struct Instruction {
 
 impl Instruction {
     /// Deserializes an instruction from memory at the given address.
-    pub fn from_memory(addr: usize, mem: &Memory) -> Instruction {
+    pub fn from_memory(addr: usize, mem: &dyn Memory) -> Instruction {
         Instruction {
             op_a: mem.get(addr),
             op_b: mem.get(addr + 1),
@@ -59,9 +166,23 @@ impl Instruction {
         }
     }
 
+    /// Deserializes an instruction from memory at the given address or fails if any of its words can’t be read.
+    pub fn try_from_memory(addr: usize, mem: &dyn Memory) -> Result<Instruction> {
+        let fetch = |offset: usize| {
+            mem.try_get(addr + offset)
+                .map_err(|err| Fault::from_read(FaultKind::BadFetch, err, addr))
+        };
+        Ok(Instruction {
+            op_a: fetch(0)?,
+            op_b: fetch(1)?,
+            target: fetch(2)?,
+            jmp: fetch(3)?,
+        })
+    }
+
     /// Serializes the instruction to memory at the given adress.
-    pub fn serialize(&self, addr: usize, mem: &mut Memory) {
-        mem.set(addr + 0, self.op_a);
+    pub fn serialize(&self, addr: usize, mem: &mut dyn Memory) {
+        mem.set(addr, self.op_a);
         mem.set(addr + 1, self.op_b);
         mem.set(addr + 2, self.target);
         mem.set(addr + 3, self.jmp);
@@ -69,7 +190,7 @@ impl Instruction {
 
     /// Executes the instruction using `mem` for reads and writes and
     /// adjusting `ip` appropriately.
-    pub fn execute(&self, ip: &mut usize, mem: &mut Memory) {
+    pub fn execute(&self, ip: &mut usize, mem: &mut dyn Memory) {
         let mut op_a = self.op_a;
         let mut op_b = self.op_b;
         let mut target = self.target;
@@ -95,9 +216,60 @@ impl Instruction {
         *ip = if r <= 0 { jmp as usize } else { *ip + 4 }
     }
 
+    /// Executes the instruction like `execute()`, but reports invalid memory accesses as a `Fault` instead of panicking.
+    ///
+    /// All reads happen before the result is written. If the instruction faults, `ip` is left unchanged. On success, the resolved operands and values are returned.
+    pub fn try_execute<M: Memory + ?Sized>(&self, ip: &mut usize, mem: &mut M) -> Result<Execution> {
+        let read = |mem: &M, addr: usize| {
+            mem.try_get(addr)
+                .map_err(|err| Fault::from_read(FaultKind::BadOperand, err, *ip))
+        };
+        let resolve = |mem: &M, op: i32| {
+            if op < 0 {
+                read(mem, to_address(op.wrapping_neg()))
+            } else {
+                Ok(op)
+            }
+        };
+
+        let op_a = resolve(mem, self.op_a)?;
+        let op_b = resolve(mem, self.op_b)?;
+        let target = resolve(mem, self.target)?;
+        let jmp = resolve(mem, self.jmp)?;
+
+        let a = read(mem, to_address(op_a))?;
+        let b = read(mem, to_address(op_b))?;
+        let r = a.wrapping_sub(b);
+        mem.try_set(to_address(target), r)
+            .map_err(|err| Fault::from_write(err, *ip))?;
+        let jumped = r <= 0;
+        *ip = if jumped { to_address(jmp) } else { *ip + 4 };
+        Ok(Execution {
+            op_a: to_address(op_a),
+            op_b: to_address(op_b),
+            target: to_address(target),
+            jmp: to_address(jmp),
+            a,
+            b,
+            result: r,
+            jumped,
+        })
+    }
+
+    /// Returns the address the instruction will write to, resolving an indirect target, or `None` if it can’t be read.
+    pub(crate) fn target_address(&self, mem: &dyn Memory) -> Option<usize> {
+        if self.target < 0 {
+            mem.try_get(to_address(self.target.wrapping_neg()))
+                .ok()
+                .map(to_address)
+        } else {
+            Some(to_address(self.target))
+        }
+    }
+
     /// Executes the instruction in memory at the given address, adjusting the
     // `ip` appropriately.
-    pub fn execute_at(ip: &mut usize, mem: &mut Memory) {
+    pub fn execute_at(ip: &mut usize, mem: &mut dyn Memory) {
         let instr = Instruction::from_memory(*ip, mem);
         instr.execute(ip, mem);
     }
@@ -128,7 +300,7 @@ mod tests {
             jmp: 128,
         };
         i.execute(&mut ip, &mut m);
-        assert_eq!(m.get(2) as i32, -1);
+        assert_eq!(m.get(2), -1);
         assert_eq!(ip, 128);
 
         let mut m = SliceMemory::from_slice(Box::new([1, 2, 0, 0]));
@@ -156,6 +328,70 @@ mod tests {
         assert_eq!(ip, 8);
     }
 
+    #[test]
+    fn try_execute() {
+        let mut ip = 0;
+        let mut m = SliceMemory::from_slice(Box::new([1, 2, 0, 0, -1]));
+        let i = super::Instruction {
+            op_a: 0,
+            op_b: -4,
+            target: 2,
+            jmp: 128,
+        };
+        assert_eq!(
+            i.try_execute(&mut ip, &mut m),
+            Err(super::Fault {
+                kind: super::FaultKind::BadOperand,
+                address: 0xFFFFFFFF,
+                ip: 0,
+            })
+        );
+        assert_eq!(ip, 0);
+
+        let i = super::Instruction {
+            op_a: 0,
+            op_b: 1,
+            target: 5,
+            jmp: 128,
+        };
+        assert_eq!(
+            i.try_execute(&mut ip, &mut m),
+            Err(super::Fault {
+                kind: super::FaultKind::BadWrite,
+                address: 5,
+                ip: 0,
+            })
+        );
+        assert_eq!(ip, 0);
+
+        let i = super::Instruction {
+            op_a: -4,
+            op_b: 1,
+            target: 2,
+            jmp: 128,
+        };
+        m.set(4, 0);
+        assert_eq!(
+            i.try_execute(&mut ip, &mut m),
+            Ok(super::Execution {
+                op_a: 0,
+                op_b: 1,
+                target: 2,
+                jmp: 128,
+                a: 1,
+                b: 2,
+                result: -1,
+                jumped: true,
+            })
+        );
+        assert_eq!(ip, 128);
+
+        assert_eq!(
+            super::Instruction::try_from_memory(4, &m).unwrap_err().kind,
+            super::FaultKind::BadFetch
+        );
+    }
+
     #[test]
     fn execute_at() {
         let mut ip = 0;
@@ -4,10 +4,22 @@
 //!
 //! - For more details on the instruction set, see the `instruction` module.
 //! - For more details on the architecture and memory layout, see the `memory` module.
+//! - For more details on the assembly syntax, see the `assembler` module.
+//! - For labels and source lines of assembled images, see the `symbols` module.
+//! - For recording executed instructions, see the `trace` module.
+//! - For profiling guest programs, see the `profile` module.
+//! - For debugging with GDB, see the `gdbstub` module.
 //!
 //! [SUBLEQ]: https://esolangs.org/wiki/Subleq
 pub mod memory;
 pub mod instruction;
 pub mod emulator;
+pub mod device;
 pub mod loader;
+pub mod assembler;
+pub mod disassembler;
+pub mod symbols;
+pub mod trace;
+pub mod profile;
+pub mod gdbstub;
 pub mod utils;
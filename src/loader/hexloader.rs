@@ -16,7 +16,7 @@
 //!     -5 # ... and they can be negative
 //! ");
 //! let mem = hexloader::load(&mut code).unwrap();
-//! assert_eq!(mem.get(0), 0xDEADBEEF);
+//! assert_eq!(mem.get(0), 0xDEADBEEF_u32 as i32);
 //! assert_eq!(mem.get(1), 0x1);
 //! assert_eq!(mem.get(2), 0x10);
 //! assert_eq!(mem.get(3), 0x100);
@@ -26,7 +26,7 @@ use memory::{Memory, SliceMemory};
 use std::io::{BufRead, BufReader, Read};
 use loader::{LoadError, Result};
 
-pub fn load<U: Read>(f: &mut U) -> Result<Box<Memory>> {
+pub fn load<U: Read>(f: &mut U) -> Result<Box<dyn Memory>> {
     let mut vec = Vec::<i32>::new();
     let buf = BufReader::new(f);
     for line in buf.lines() {
@@ -35,13 +35,13 @@ pub fn load<U: Read>(f: &mut U) -> Result<Box<Memory>> {
             continue;
         }
         for chunk in line.split_whitespace() {
-            if chunk.len() == 0 {
+            if chunk.is_empty() {
                 continue;
             }
             if chunk.starts_with("#") {
                 break;
             }
-            if !chunk.chars().all(|c| c.is_digit(16) || c == '-') {
+            if !chunk.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
                 return Err(LoadError::from_message(format!(
                     "Word contains non-hexnumeric characters: {}",
                     chunk
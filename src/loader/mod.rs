@@ -29,28 +29,30 @@ impl LoadError {
     }
 }
 
-impl fmt::Display for LoadError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Loading failed")
+impl Default for LoadError {
+    fn default() -> LoadError {
+        LoadError::new()
     }
 }
 
-impl error::Error for LoadError {
-    fn description(&self) -> &str {
-        match self {
-            &LoadError::None => &"Loading failed",
-            &LoadError::Message(ref str) => str,
-            &LoadError::IoErr(ref err) => err.description(),
-            &LoadError::ParseIntErr(ref err) => err.description(),
-            &LoadError::FormatErr(ref err) => err.description(),
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::None => write!(f, "Loading failed"),
+            LoadError::Message(ref str) => write!(f, "{}", str),
+            LoadError::IoErr(ref err) => write!(f, "{}", err),
+            LoadError::ParseIntErr(ref err) => write!(f, "{}", err),
+            LoadError::FormatErr(ref err) => write!(f, "{}", err),
         }
     }
+}
 
-    fn cause(&self) -> Option<&error::Error> {
-        match self {
-            &LoadError::IoErr(ref err) => Some(err),
-            &LoadError::ParseIntErr(ref err) => Some(err),
-            &LoadError::FormatErr(ref err) => Some(err),
+impl error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            LoadError::IoErr(ref err) => Some(err),
+            LoadError::ParseIntErr(ref err) => Some(err),
+            LoadError::FormatErr(ref err) => Some(err),
             _ => None,
         }
     }
@@ -1,47 +1,160 @@
 //! Loader for binary files.
 //!
-//! This loader turns a binary streams into a `SliceMemory`. The file is read in network-endian byte order.
+//! This loader turns a binary streams into a `SliceMemory`. By default, the file is read in network-endian byte order and trailing bytes that don’t make up a full word are ignored. `Options` select a different byte order, the handling of a trailing partial word and a header to skip.
+//!
+//! # Examples
+//!
+//! ```
+//! use osciemu::loader::rawloader::{self, Endianness, Options, PartialWord};
+//!
+//! let mut bytes: &[u8] = &[0xAA, 0x01, 0x00, 0x00, 0x00, 0x02];
+//! let options = Options {
+//!     endianness: Endianness::Little,
+//!     partial_word: PartialWord::Pad,
+//!     header: 1,
+//! };
+//! let mem = rawloader::load_with_options(&mut bytes, &options).unwrap();
+//! assert_eq!(mem.size(), 2);
+//! assert_eq!(mem.get(0), 1);
+//! assert_eq!(mem.get(1), 2);
+//! ```
 extern crate byteorder;
 
 use memory::{Memory, SliceMemory};
-use std::io::{ErrorKind, Read, Seek, SeekFrom};
-use self::byteorder::{NetworkEndian, ReadBytesExt};
+use std::io::{self, Read, Seek, SeekFrom};
+use self::byteorder::{ByteOrder, LittleEndian, NetworkEndian, ReadBytesExt};
 use loader::{LoadError, Result};
 
-/// Loads a seekable stream into a memory.
+/// Byte order of the words in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// Most significant byte first (network byte order)
+    Big,
+    /// Least significant byte first
+    Little,
+}
+
+/// Handling of trailing bytes that don’t make up a full word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialWord {
+    /// Ignore the trailing bytes.
+    Drop,
+    /// Fail with a `LoadError`.
+    Reject,
+    /// Load the trailing bytes as a word whose missing bytes are 0.
+    Pad,
+}
+
+/// Options of the raw loader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Byte order of the words
+    pub endianness: Endianness,
+    /// Handling of a trailing partial word
+    pub partial_word: PartialWord,
+    /// Number of bytes to skip at the start of the file
+    pub header: u64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            endianness: Endianness::Big,
+            partial_word: PartialWord::Drop,
+            header: 0,
+        }
+    }
+}
+
+/// Loads a seekable stream into a memory using the default `Options`.
 ///
 /// This method only allocates the resulting slice once, as seeking allows to determine the size ahead of time. This method should be preferred over `load()`.
-pub fn load_with_seek<U: Read + Seek>(f: &mut U) -> Result<Box<Memory>> {
-    let size: usize = (f.seek(SeekFrom::End(0))? as usize) / 4;
-    f.seek(SeekFrom::Start(0))?;
-    let mut vec = Vec::<i32>::with_capacity(size);
-    vec.resize(size, 0);
-    let mut slice: Box<[i32]> = vec.into_boxed_slice();
-    f.read_i32_into::<NetworkEndian>(slice.as_mut())?;
+pub fn load_with_seek<U: Read + Seek>(f: &mut U) -> Result<Box<dyn Memory>> {
+    load_with_seek_and_options(f, &Options::default())
+}
+
+/// Loads a seekable stream into a memory like `load_with_seek()` using the given `Options`.
+pub fn load_with_seek_and_options<U: Read + Seek>(
+    f: &mut U,
+    options: &Options,
+) -> Result<Box<dyn Memory>> {
+    let len = f
+        .seek(SeekFrom::End(0))?
+        .checked_sub(options.header)
+        .ok_or_else(|| short_header(options))?;
+    f.seek(SeekFrom::Start(options.header))?;
+    let size = (len / 4) as usize;
+    let mut rest = vec![0u8; (len % 4) as usize];
+    let padded = partial_word(&rest, options)?.is_some();
+    let mut slice: Box<[i32]> = vec![0; size + padded as usize].into_boxed_slice();
+    match options.endianness {
+        Endianness::Big => f.read_i32_into::<NetworkEndian>(&mut slice[..size])?,
+        Endianness::Little => f.read_i32_into::<LittleEndian>(&mut slice[..size])?,
+    }
+    if padded {
+        f.read_exact(&mut rest)?;
+        slice[size] = partial_word(&rest, options)?.unwrap();
+    }
     Ok(Box::new(SliceMemory::from_slice(slice)))
 }
 
-/// Loads a stream into a memory.
-pub fn load<U: Read>(f: &mut U) -> Result<Box<Memory>> {
-    let mut vec = Vec::<i32>::new();
-    loop {
-        // TODO: Is there a more elegant way for this?
-        match f.read_i32::<NetworkEndian>() {
-            Ok(word) => vec.push(word),
-            Err(err) => {
-                if err.kind() == ErrorKind::UnexpectedEof {
-                    break;
-                }
-                return Err(LoadError::IoErr(err));
-            }
+/// Loads a stream into a memory using the default `Options`.
+pub fn load<U: Read>(f: &mut U) -> Result<Box<dyn Memory>> {
+    load_with_options(f, &Options::default())
+}
+
+/// Loads a stream into a memory like `load()` using the given `Options`.
+pub fn load_with_options<U: Read>(f: &mut U, options: &Options) -> Result<Box<dyn Memory>> {
+    if io::copy(&mut f.by_ref().take(options.header), &mut io::sink())? < options.header {
+        return Err(short_header(options));
+    }
+    let mut bytes = Vec::new();
+    f.read_to_end(&mut bytes)?;
+    let chunks = bytes.chunks_exact(4);
+    let last = partial_word(chunks.remainder(), options)?;
+    let words: Vec<i32> = chunks
+        .map(|chunk| read_word(chunk, options.endianness))
+        .chain(last)
+        .collect();
+    Ok(Box::new(SliceMemory::from_slice(words.into_boxed_slice())))
+}
+
+fn read_word(bytes: &[u8], endianness: Endianness) -> i32 {
+    match endianness {
+        Endianness::Big => NetworkEndian::read_i32(bytes),
+        Endianness::Little => LittleEndian::read_i32(bytes),
+    }
+}
+
+/// Returns the word to append for the trailing bytes `rest`, if any.
+fn partial_word(rest: &[u8], options: &Options) -> Result<Option<i32>> {
+    if rest.is_empty() {
+        return Ok(None);
+    }
+    match options.partial_word {
+        PartialWord::Drop => Ok(None),
+        PartialWord::Reject => Err(LoadError::from_message(format!(
+            "File ends with a partial word of {} bytes",
+            rest.len()
+        ))),
+        PartialWord::Pad => {
+            let mut word = [0u8; 4];
+            word[..rest.len()].copy_from_slice(rest);
+            Ok(Some(read_word(&word, options.endianness)))
         }
     }
-    let slice: Box<[i32]> = vec.into_boxed_slice();
-    Ok(Box::new(SliceMemory::from_slice(slice)))
+}
+
+fn short_header(options: &Options) -> LoadError {
+    LoadError::from_message(format!(
+        "File is shorter than the header of {} bytes",
+        options.header
+    ))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{Endianness, Options, PartialWord};
     use std::io::Cursor;
 
     #[test]
@@ -49,7 +162,7 @@ mod tests {
         let mut bytes: &[u8] = &[0x00, 0x01, 0x02, 0x03, 0xF0, 0xF1, 0xF2, 0xF3];
         let mem = super::load(&mut bytes).unwrap();
         assert_eq!(mem.get(0), 0x00010203);
-        assert_eq!(mem.get(1), 0xF0F1F2F3 as u32 as i32);
+        assert_eq!(mem.get(1), 0xF0F1F2F3_u32 as i32);
     }
 
     #[test]
@@ -58,6 +171,46 @@ mod tests {
             Cursor::new(&[0x00, 0x01, 0x02, 0x03, 0xF0, 0xF1, 0xF2, 0xF3]);
         let mem = super::load_with_seek(&mut bytes).unwrap();
         assert_eq!(mem.get(0), 0x00010203);
-        assert_eq!(mem.get(1), 0xF0F1F2F3 as u32 as i32);
+        assert_eq!(mem.get(1), 0xF0F1F2F3_u32 as i32);
+    }
+
+    #[test]
+    fn options() {
+        let bytes: &[u8] = &[0xEE, 0xEE, 0x00, 0x01, 0x02, 0x03, 0xF0, 0xF1];
+        let load = |options: &Options| {
+            let seeked = super::load_with_seek_and_options(&mut Cursor::new(bytes), options);
+            let streamed = super::load_with_options(&mut &bytes[..], options);
+            match (seeked, streamed) {
+                (Ok(seeked), Ok(streamed)) => {
+                    let words: Vec<i32> = (0..seeked.size()).map(|i| seeked.get(i)).collect();
+                    let other: Vec<i32> = (0..streamed.size()).map(|i| streamed.get(i)).collect();
+                    assert_eq!(words, other);
+                    Some(words)
+                }
+                (Err(_), Err(_)) => None,
+                _ => panic!("Loaders disagree"),
+            }
+        };
+
+        let mut options = Options::default();
+        assert_eq!(
+            load(&options),
+            Some(vec![0xEEEE0001_u32 as i32, 0x0203F0F1])
+        );
+        options.header = 2;
+        assert_eq!(load(&options), Some(vec![0x00010203]));
+        options.partial_word = PartialWord::Pad;
+        assert_eq!(
+            load(&options),
+            Some(vec![0x00010203, 0xF0F10000_u32 as i32])
+        );
+        options.endianness = Endianness::Little;
+        assert_eq!(load(&options), Some(vec![0x03020100, 0xF1F0]));
+        options.partial_word = PartialWord::Reject;
+        assert_eq!(load(&options), None);
+        options.header = 4;
+        assert_eq!(load(&options), Some(vec![0xF1F00302_u32 as i32]));
+        options.header = 9;
+        assert_eq!(load(&options), None);
     }
 }
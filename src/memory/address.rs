@@ -20,8 +20,26 @@ pub const IVT_START_ADDRESS: usize = FLAGS_START_ADDRESS - NUM_IVT_ENTRIES;
 pub const REGISTERS_START_ADDRESS: usize = IVT_START_ADDRESS - NUM_REGISTERS;
 /// Address of the stack pointer register.
 pub const STACK_POINTER_ADDRESS: usize = REGISTERS_START_ADDRESS - 1;
+/// Number of words of the UART memory region.
+pub const UART_SIZE: usize = 2;
+/// Lowest address of the UART memory region.
+pub const UART_START_ADDRESS: usize = STACK_POINTER_ADDRESS - UART_SIZE;
+/// Address of the UART data word.
+pub const UART_DATA_ADDRESS: usize = UART_START_ADDRESS;
+/// Address of the UART status word.
+pub const UART_STATUS_ADDRESS: usize = UART_START_ADDRESS + 1;
+/// Number of words of the timer memory region.
+pub const TIMER_SIZE: usize = 3;
+/// Lowest address of the timer memory region.
+pub const TIMER_START_ADDRESS: usize = UART_START_ADDRESS - TIMER_SIZE;
+/// Address of the timer counter word.
+pub const TIMER_COUNTER_ADDRESS: usize = TIMER_START_ADDRESS;
+/// Address of the timer reload word.
+pub const TIMER_RELOAD_ADDRESS: usize = TIMER_START_ADDRESS + 1;
+/// Address of the timer control word.
+pub const TIMER_CONTROL_ADDRESS: usize = TIMER_START_ADDRESS + 2;
 /// Lowest address of all the control memory regions.
-pub const CONTROLS_ADDRESS: usize = STACK_POINTER_ADDRESS;
+pub const CONTROLS_ADDRESS: usize = TIMER_START_ADDRESS;
 
 /// Flag index of the `H` bit.
 ///
@@ -31,3 +49,19 @@ pub const FLAG_HALTED: usize = 0;
 ///
 /// For use with `Emulator.is_flag_set()`.
 pub const FLAG_BIOS_DONE: usize = 1;
+/// Flag index of the `IE` bit.
+///
+/// For use with `Emulator.is_flag_set()`.
+pub const FLAG_INTERRUPT_ENABLE: usize = 2;
+/// Flag index of the `IR` bit.
+///
+/// For use with `Emulator.is_flag_set()`.
+pub const FLAG_INTERRUPT_RETURN: usize = 3;
+/// Flag index of the pending bit of interrupt line 0.
+///
+/// The pending bit of line `n` has the flag index `FLAG_INTERRUPT_PENDING + n`.
+pub const FLAG_INTERRUPT_PENDING: usize = 8;
+/// Number of interrupt lines.
+pub const NUM_INTERRUPT_LINES: usize = 8;
+/// Interrupt line of the timer.
+pub const TIMER_INTERRUPT_LINE: usize = 0;
@@ -0,0 +1,152 @@
+//! Copy-on-write overlay over a shared memory.
+use memory::Memory;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// A memory that reads from a shared base memory and keeps its own copy of every page it writes.
+///
+/// The base is never written. The first write to a page copies the page from the base. Cloning a `CowMemory` shares the base and all copied pages with the clone, so it only takes time proportional to the number of written pages. A page shared by clones is copied again when one of them writes to it.
+///
+/// # Examples
+///
+/// ```
+/// use osciemu::memory::{CowMemory, Memory, SliceMemory};
+/// use std::rc::Rc;
+///
+/// let base = Rc::new(SliceMemory::from_slice(Box::new([1, 2, 3, 4])));
+/// let mut a = CowMemory::new(base.clone());
+/// a.set(0, 10);
+/// let mut b = a.clone();
+/// b.set(1, 20);
+/// assert_eq!((a.get(0), a.get(1)), (10, 2));
+/// assert_eq!((b.get(0), b.get(1)), (10, 20));
+/// assert_eq!(base.get(0), 1);
+/// ```
+#[derive(Clone)]
+pub struct CowMemory {
+    base: Rc<dyn Memory>,
+    pages: BTreeMap<usize, Rc<Vec<i32>>>,
+}
+
+impl CowMemory {
+    /// Number of words per page.
+    pub const PAGE_SIZE: usize = 1024;
+
+    /// Creates an overlay over `base` without any written pages.
+    pub fn new(base: Rc<dyn Memory>) -> CowMemory {
+        CowMemory {
+            base,
+            pages: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the shared base memory.
+    pub fn base(&self) -> &Rc<dyn Memory> {
+        &self.base
+    }
+
+    /// Returns the number of pages that have been written.
+    pub fn dirty_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn page_range(&self, page_idx: usize) -> Range<usize> {
+        let start = page_idx * CowMemory::PAGE_SIZE;
+        start..(start + CowMemory::PAGE_SIZE).min(self.base.size())
+    }
+}
+
+impl Memory for CowMemory {
+    fn get(&self, addr: usize) -> i32 {
+        assert!(addr < self.size(), "Out of bounds");
+        match self.pages.get(&(addr / CowMemory::PAGE_SIZE)) {
+            Some(page) => page[addr % CowMemory::PAGE_SIZE],
+            None => self.base.get(addr),
+        }
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        assert!(addr < self.size(), "Out of bounds");
+        let page_idx = addr / CowMemory::PAGE_SIZE;
+        if !self.pages.contains_key(&page_idx) {
+            let page = self
+                .page_range(page_idx)
+                .map(|addr| self.base.get(addr))
+                .collect();
+            self.pages.insert(page_idx, Rc::new(page));
+        }
+        let page = self.pages.get_mut(&page_idx).unwrap();
+        Rc::make_mut(page)[addr % CowMemory::PAGE_SIZE] = value;
+    }
+
+    fn size(&self) -> usize {
+        self.base.size()
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        match self.pages.get(&(addr / CowMemory::PAGE_SIZE)) {
+            Some(page) => page.get(addr % CowMemory::PAGE_SIZE).cloned(),
+            None => self.base.peek(addr),
+        }
+    }
+
+    fn allocated(&self) -> Vec<Range<usize>> {
+        let mut ranges = self.base.allocated();
+        ranges.extend(self.pages.keys().map(|&page_idx| self.page_range(page_idx)));
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    fn fork(&self) -> Option<Box<dyn Memory>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CowMemory;
+    use memory::{Memory, MemoryError, SliceMemory, SparseMemory};
+    use std::rc::Rc;
+
+    #[test]
+    fn copy_on_write() {
+        let size = 2 * CowMemory::PAGE_SIZE + 5;
+        let base: Vec<i32> = (0..size as i32).collect();
+        let mut a = CowMemory::new(Rc::new(SliceMemory::from_slice(base.into_boxed_slice())));
+        a.set(size - 1, -1);
+        assert_eq!(a.dirty_pages(), 1);
+        assert_eq!(a.get(size - 2), size as i32 - 2);
+
+        let mut b = a.clone();
+        b.set(size - 2, -2);
+        b.set(3, -3);
+        assert_eq!(b.dirty_pages(), 2);
+        assert_eq!((b.get(size - 1), b.get(size - 2), b.get(3)), (-1, -2, -3));
+        assert_eq!(
+            (a.get(size - 1), a.get(size - 2), a.get(3)),
+            (-1, size as i32 - 2, 3)
+        );
+        assert_eq!(a.base().get(size - 1), size as i32 - 1);
+        assert_eq!(a.try_set(size, 0), Err(MemoryError::OutOfBounds(size)));
+    }
+
+    #[test]
+    fn allocated() {
+        let page = CowMemory::PAGE_SIZE;
+        let mut base = SparseMemory::new(8 * page);
+        base.set(2 * SparseMemory::PAGE_SIZE, 1);
+        let mut m = CowMemory::new(Rc::new(base));
+        assert_eq!(m.allocated(), vec![2 * page..3 * page]);
+        m.set(page + 1, 1);
+        m.set(5 * page, 1);
+        assert_eq!(m.allocated(), vec![page..3 * page, 5 * page..6 * page]);
+    }
+}
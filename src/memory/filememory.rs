@@ -0,0 +1,270 @@
+//! Memory backed by a host file.
+//!
+//! The file is not mapped into the address space of the process with `mmap`, which the standard library doesn’t offer. Instead, `FileMemory` keeps a bounded cache of pages read with ordinary file I/O. Like a mapping, this allows mounting files that are too large to be loaded completely, and the host memory used stays the same no matter how large the file is.
+extern crate byteorder;
+
+use self::byteorder::{ByteOrder, NetworkEndian};
+use memory::{Memory, MemoryError, Result, SliceMemory};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// A memory whose words are stored in a file.
+///
+/// The file uses the same layout as the `rawloader`: every word takes four bytes in network-endian byte order. Trailing bytes that don’t make up a full word are ignored.
+///
+/// Words are read in pages of `PAGE_SIZE` words when they are first accessed, so files of any size can be mounted without reading them completely. Writes are buffered and written back by `flush()`, when the number of buffered pages exceeds `CACHED_PAGES` and when the memory is dropped. Errors while writing back on drop are ignored, so call `flush()` to find out whether the contents have been saved.
+///
+/// `fork()` copies the contents into a `SliceMemory`, which takes time proportional to the size of the file. Only the original memory writes to the file.
+///
+/// # Panics
+///
+/// `get()` and `set()` panic if reading or writing the file fails. `try_get()` and `try_set()`, which the emulator uses for all accesses of the guest, fail with `MemoryError::Io` instead, and `peek()` returns `None`.
+///
+/// # Examples
+///
+/// ```
+/// # use std::fs;
+/// use osciemu::memory::{FileMemory, Memory};
+///
+/// let path = std::env::temp_dir().join("osciemu-filememory-doctest.bin");
+/// # let _ = fs::remove_file(&path);
+/// let mut m = FileMemory::create(&path, 16).unwrap();
+/// m.set(1, 0x01020304);
+/// m.flush().unwrap();
+/// assert_eq!(fs::read(&path).unwrap()[4..8], [1, 2, 3, 4]);
+///
+/// let m = FileMemory::open(&path).unwrap();
+/// assert_eq!(m.get(1), 0x01020304);
+/// # fs::remove_file(&path).unwrap();
+/// ```
+pub struct FileMemory {
+    path: PathBuf,
+    file: RefCell<File>,
+    pages: RefCell<BTreeMap<usize, Page>>,
+    size: usize,
+}
+
+struct Page {
+    words: Box<[i32]>,
+    dirty: bool,
+}
+
+impl FileMemory {
+    /// Number of words per page.
+    pub const PAGE_SIZE: usize = 1024;
+    /// Maximum number of pages kept in memory.
+    pub const CACHED_PAGES: usize = 256;
+
+    /// Opens an existing file. The size of the memory is the size of the file in words.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileMemory> {
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        FileMemory::from_file(path.as_ref(), file)
+    }
+
+    /// Opens a file with at least `size` words, creating or extending it as necessary.
+    ///
+    /// New words are 0. Existing files are never truncated, so the memory can be larger than `size`.
+    pub fn create<P: AsRef<Path>>(path: P, size: usize) -> io::Result<FileMemory> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let len = (size * 4) as u64;
+        if file.metadata()?.len() < len {
+            file.set_len(len)?;
+        }
+        FileMemory::from_file(path.as_ref(), file)
+    }
+
+    fn from_file(path: &Path, file: File) -> io::Result<FileMemory> {
+        let size = (file.metadata()?.len() / 4) as usize;
+        Ok(FileMemory {
+            path: path.to_path_buf(),
+            file: RefCell::new(file),
+            pages: RefCell::new(BTreeMap::new()),
+            size,
+        })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Calls `f` with the cached page containing `addr`, reading it first if necessary.
+    fn with_page<T, F: FnOnce(&mut Page) -> T>(&self, addr: usize, f: F) -> Result<T> {
+        if addr >= self.size {
+            return Err(MemoryError::OutOfBounds(addr));
+        }
+        let page_idx = addr / FileMemory::PAGE_SIZE;
+        let mut pages = self.pages.borrow_mut();
+        if !pages.contains_key(&page_idx) {
+            if pages.len() >= FileMemory::CACHED_PAGES {
+                self.write_back(&mut pages)
+                    .map_err(|_| MemoryError::Io(addr))?;
+                pages.clear();
+            }
+            let page = self
+                .read_page(page_idx)
+                .map_err(|_| MemoryError::Io(addr))?;
+            pages.insert(page_idx, page);
+        }
+        Ok(f(pages.get_mut(&page_idx).unwrap()))
+    }
+
+    fn read_page(&self, page_idx: usize) -> io::Result<Page> {
+        let start = page_idx * FileMemory::PAGE_SIZE;
+        let len = FileMemory::PAGE_SIZE.min(self.size - start);
+        let mut bytes = vec![0u8; len * 4];
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start((start * 4) as u64))?;
+        file.read_exact(&mut bytes)?;
+        let mut words = vec![0; len].into_boxed_slice();
+        NetworkEndian::read_i32_into(&bytes, &mut words);
+        Ok(Page {
+            words,
+            dirty: false,
+        })
+    }
+
+    fn write_back(&self, pages: &mut BTreeMap<usize, Page>) -> io::Result<()> {
+        let mut file = self.file.borrow_mut();
+        for (page_idx, page) in pages.iter_mut().filter(|(_, page)| page.dirty) {
+            let mut bytes = vec![0u8; page.words.len() * 4];
+            NetworkEndian::write_i32_into(&page.words, &mut bytes);
+            file.seek(SeekFrom::Start(
+                (page_idx * FileMemory::PAGE_SIZE * 4) as u64,
+            ))?;
+            file.write_all(&bytes)?;
+            page.dirty = false;
+        }
+        file.flush()
+    }
+}
+
+impl Memory for FileMemory {
+    fn get(&self, addr: usize) -> i32 {
+        match self.try_get(addr) {
+            Ok(value) => value,
+            Err(MemoryError::OutOfBounds(_)) => panic!("Out of bounds"),
+            Err(_) => panic!("Could not read {}", self.path.display()),
+        }
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        match self.try_set(addr, value) {
+            Ok(()) => {}
+            Err(MemoryError::OutOfBounds(_)) => panic!("Out of bounds"),
+            Err(_) => panic!("Could not write {}", self.path.display()),
+        }
+    }
+
+    fn try_get(&self, addr: usize) -> Result<i32> {
+        self.with_page(addr, |page| page.words[addr % FileMemory::PAGE_SIZE])
+    }
+
+    fn try_set(&mut self, addr: usize, value: i32) -> Result<()> {
+        self.with_page(addr, |page| {
+            page.words[addr % FileMemory::PAGE_SIZE] = value;
+            page.dirty = true;
+        })
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        self.try_get(addr).ok()
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_back(&mut self.pages.borrow_mut())
+    }
+
+    fn fork(&self) -> Option<Box<dyn Memory>> {
+        let pages = self.pages.borrow();
+        let mut words = Vec::with_capacity(self.size);
+        for page_idx in 0..self.size.div_ceil(FileMemory::PAGE_SIZE) {
+            match pages.get(&page_idx) {
+                Some(page) => words.extend_from_slice(&page.words),
+                None => words.extend_from_slice(&self.read_page(page_idx).ok()?.words),
+            }
+        }
+        Some(Box::new(SliceMemory::from_slice(words.into_boxed_slice())))
+    }
+}
+
+impl Drop for FileMemory {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileMemory;
+    use memory::{MappedMemory, Memory, MemoryError};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn persists() {
+        let path = env::temp_dir().join(format!("osciemu-filememory-{}.bin", line!()));
+        let _ = fs::remove_file(&path);
+        fs::write(&path, [0, 0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFE, 9]).unwrap();
+        {
+            let mut m = FileMemory::open(&path).unwrap();
+            assert_eq!(m.size(), 2);
+            assert_eq!(m.get(0), 1);
+            assert_eq!(m.get(1), -2);
+            m.set(0, 0x11223344);
+        }
+        assert_eq!(
+            fs::read(&path).unwrap(),
+            [0x11, 0x22, 0x33, 0x44, 0xFF, 0xFF, 0xFF, 0xFE, 9]
+        );
+
+        // Touch more pages than are cached
+        let size = (FileMemory::CACHED_PAGES + 2) * FileMemory::PAGE_SIZE;
+        let mut m = FileMemory::create(&path, size).unwrap();
+        assert_eq!(m.get(0), 0x11223344);
+        for addr in (0..size).step_by(FileMemory::PAGE_SIZE) {
+            m.set(addr + 1, addr as i32);
+        }
+        for addr in (0..size).step_by(FileMemory::PAGE_SIZE) {
+            assert_eq!(m.get(addr + 1), addr as i32);
+        }
+        m.flush().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), (size * 4) as u64);
+        let m = FileMemory::open(&path).unwrap();
+        let last = size - FileMemory::PAGE_SIZE;
+        assert_eq!(m.get(last + 1), last as i32);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn io_errors() {
+        let path = env::temp_dir().join(format!("osciemu-filememory-{}.bin", line!()));
+        let _ = fs::remove_file(&path);
+        let m = FileMemory::create(&path, 2 * FileMemory::PAGE_SIZE).unwrap();
+        let mut mm = MappedMemory::new();
+        mm.mount(0x100, Box::new(m)).unwrap();
+        // Reading the pages fails once the file has been truncated behind the memory’s back.
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        assert_eq!(mm.try_get(0x101), Err(MemoryError::Io(0x101)));
+        assert_eq!(mm.try_set(0x102, 1), Err(MemoryError::Io(0x102)));
+        assert_eq!(mm.peek(0x101), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
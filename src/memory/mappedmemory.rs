@@ -1,7 +1,10 @@
 //! Maps multiple `Memory`s into a single address space.
-use memory::Memory;
+use memory::{address, Memory, MemoryError, Result};
+use std::io;
+use std::mem;
 use std::vec::Vec;
-use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::vec;
 
 /// The `MappedMemory` allows to unify multiple `Memory`s in one address space.
 ///
@@ -29,8 +32,8 @@ use std::sync::atomic::{AtomicIsize, Ordering};
 /// use osciemu::memory::{Memory, SliceMemory, MappedMemory};
 ///
 /// let mut mm = MappedMemory::new();
-/// let m1 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1]))));
-/// let m2 = mm.mount(2, Box::new(SliceMemory::from_slice(Box::new([2, 2]))));
+/// let m1 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1])))).unwrap();
+/// let m2 = mm.mount(2, Box::new(SliceMemory::from_slice(Box::new([2, 2])))).unwrap();
 /// // Now mm =~ [1, _, 2, 2]
 /// assert_eq!(mm.get(0), 1);
 /// assert_eq!(mm.get(3), 2);
@@ -45,8 +48,8 @@ use std::sync::atomic::{AtomicIsize, Ordering};
 /// # use osciemu::memory::mappedmemory::MemoryToken;
 ///
 /// let mut mm = MappedMemory::new();
-/// let m1 = mm.mount(0, Box::new(NullMemory::new()));
-/// let m2 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1, 2, 3, 4]))));
+/// let m1 = mm.mount(0, Box::new(NullMemory::new())).unwrap();
+/// let m2 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1, 2, 3, 4])))).unwrap();
 /// mm.set(0, 99);
 /// assert_eq!(mm.get(0), 99);
 /// assert_eq!(mm.borrow(&m2).get(0), 99);
@@ -60,8 +63,8 @@ use std::sync::atomic::{AtomicIsize, Ordering};
 /// # use osciemu::memory::{Memory, NullMemory, SliceMemory, MappedMemory};
 /// # use osciemu::memory::mappedmemory::MemoryToken;
 /// # let mut mm = MappedMemory::new();
-/// # let m1 = mm.mount(0, Box::new(NullMemory::new()));
-/// # let m2 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1, 2, 3, 4]))));
+/// # let m1 = mm.mount(0, Box::new(NullMemory::new())).unwrap();
+/// # let m2 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1, 2, 3, 4])))).unwrap();
 /// # mm.set(0, 99);
 /// // ...
 /// mm.disable_mount(&m2);
@@ -69,14 +72,14 @@ use std::sync::atomic::{AtomicIsize, Ordering};
 /// assert_eq!(mm.borrow(&m2).get(0), 99);
 /// ```
 ///
-/// To move a memory out of `MappedMemory`’s ownership, a memory can be unmounted. Note that the returned type will be `Box<Memory>` and the underlying type is lost.
+/// To move a memory out of `MappedMemory`’s ownership, a memory can be unmounted. Note that the returned type will be `Box<dyn Memory>` and the underlying type is lost.
 ///
 /// ```
 /// # use osciemu::memory::{Memory, NullMemory, SliceMemory, MappedMemory};
 /// # use osciemu::memory::mappedmemory::MemoryToken;
 /// # let mut mm = MappedMemory::new();
-/// # let m1 = mm.mount(0, Box::new(NullMemory::new()));
-/// # let m2 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1, 2, 3, 4]))));
+/// # let m1 = mm.mount(0, Box::new(NullMemory::new())).unwrap();
+/// # let m2 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1, 2, 3, 4])))).unwrap();
 /// # mm.set(0, 99);
 /// // ...
 /// let m2 = mm.unmount(m2);
@@ -84,69 +87,272 @@ use std::sync::atomic::{AtomicIsize, Ordering};
 /// assert_eq!(m2.get(0), 99);
 /// ```
 ///
+/// # Address decoding
+///
+/// Lookups don’t walk the mounts. Whenever a memory is mounted, unmounted, replaced, enabled or disabled, the `MappedMemory` resolves the shadowing once and splits the address space into segments that are each served by a single memory. A page table maps every page of `PAGE_SIZE` words to the first segment reaching into it, so the cost of a lookup only depends on the number of segments within a single page.
+///
+/// # Change tracking
+///
+/// Caches of memory contents (like the instruction cache of the emulator) can use `generation()` and `track_writes()` to find out what has changed. The generation changes whenever the layout changes or a memory is borrowed mutably. With tracking enabled, the addresses of all writes through the `MappedMemory` are logged until they are drained with `drain_written()`. Memories that change their contents on their own (e.g. device registers) are not covered.
+///
 /// # Panics
-/// `MappedMemory` panics when an unmapped address is read or written.
+/// `MappedMemory` panics when an unmapped address is read or written using `get()` or `set()`. `try_get()` and `try_set()` fail with `MemoryError::Unmapped` instead.
 pub struct MappedMemory {
     memories: Vec<Entry>,
+    segments: Vec<Segment>,
+    pages: Vec<u32>,
+    generation: usize,
+    track_writes: bool,
+    written: Vec<usize>,
 }
 
 static ID_COUNTER: AtomicIsize = AtomicIsize::new(0);
+static GENERATION_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of address bits of the offset within a page.
+const PAGE_BITS: usize = 16;
+
+/// Number of words per page of the address decoder.
+pub const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+/// Maximum number of logged writes. When the log is full, the generation is changed instead.
+const WRITE_LOG_LIMIT: usize = 4096;
 
 struct Entry {
     id: isize,
+    name: String,
     start_address: usize,
     size: usize,
     enabled: bool,
-    memory: Box<Memory>,
+    memory: Box<dyn Memory>,
+}
+
+/// Addresses `start` to `end` (exclusive) are served by `memories[entry]`.
+struct Segment {
+    start: usize,
+    end: usize,
+    entry: usize,
 }
 
 impl Entry {
+    fn end_address(&self) -> usize {
+        self.start_address.saturating_add(self.size)
+    }
+
     fn contains(&self, addr: usize) -> bool {
-        self.start_address <= addr && self.start_address + self.size > addr
+        self.start_address <= addr && addr < self.end_address()
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 /// Represents a `Memory` in the ownership of `MappedMemory`.
 pub struct MemoryToken {
     id: isize,
 }
 
+/// Description of a mounted memory as returned by `MappedMemory::mounts()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MountInfo {
+    /// Token of the mounted memory
+    pub token: MemoryToken,
+    /// Name given in `mount_named()`. Empty for memories mounted with `mount()`.
+    pub name: String,
+    /// The mount point
+    pub start_address: usize,
+    /// Size of the mounted memory
+    pub size: usize,
+    /// Whether the memory is enabled
+    pub enabled: bool,
+}
+
 impl MappedMemory {
     // Create a new `MappedMemory`.
     pub fn new() -> MappedMemory {
         MappedMemory {
             memories: Vec::new(),
+            segments: Vec::new(),
+            pages: Vec::new(),
+            generation: next_generation(),
+            track_writes: false,
+            written: Vec::new(),
+        }
+    }
+
+    /// Returns the current generation. See “Change tracking”.
+    ///
+    /// Generations are unique across all `MappedMemory`s.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Enables or disables logging of written addresses. See “Change tracking”.
+    ///
+    /// This changes the generation and clears the log.
+    pub fn track_writes(&mut self, enabled: bool) {
+        self.track_writes = enabled;
+        self.changed();
+    }
+
+    /// Checks if written addresses are logged.
+    pub fn is_tracking_writes(&self) -> bool {
+        self.track_writes
+    }
+
+    /// Removes and returns the logged addresses in the order they have been written.
+    pub fn drain_written(&mut self) -> vec::Drain<'_, usize> {
+        self.written.drain(..)
+    }
+
+    /// Returns the token of the memory that is responsible for `addr`.
+    pub fn token_at(&self, addr: usize) -> Option<MemoryToken> {
+        self.enabled_entry_at_addr(addr)
+            .map(|entry| MemoryToken { id: entry.id })
+    }
+
+    fn layout_changed(&mut self) {
+        self.rebuild();
+        self.changed();
+    }
+
+    /// Rebuilds the segments and the page table from the enabled mounts.
+    fn rebuild(&mut self) {
+        let mut bounds: Vec<usize> = self.memories
+            .iter()
+            .filter(|entry| entry.enabled && entry.size > 0)
+            .flat_map(|entry| vec![entry.start_address, entry.end_address()])
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        self.segments.clear();
+        for bound in bounds.windows(2) {
+            let (start, end) = (bound[0], bound[1]);
+            let entry = match self.memories
+                .iter()
+                .rposition(|entry| entry.enabled && entry.contains(start))
+            {
+                Some(entry) => entry,
+                None => continue,
+            };
+            match self.segments.last_mut() {
+                Some(last) if last.entry == entry && last.end == start => last.end = end,
+                _ => self.segments.push(Segment { start, end, entry }),
+            }
+        }
+
+        let num_pages = self.segments
+            .last()
+            .map_or(0, |segment| ((segment.end - 1) >> PAGE_BITS) + 1);
+        self.pages.clear();
+        let mut idx = 0;
+        for page in 0..num_pages {
+            while self.segments[idx].end <= page << PAGE_BITS {
+                idx += 1;
+            }
+            self.pages.push(idx as u32);
+        }
+    }
+
+    fn changed(&mut self) {
+        self.generation = next_generation();
+        self.written.clear();
+    }
+
+    fn log_write(&mut self, addr: usize) {
+        if !self.track_writes {
+            return;
+        }
+        if self.written.len() >= WRITE_LOG_LIMIT {
+            self.changed();
+        } else {
+            self.written.push(addr);
         }
     }
 
     /// Mounts a `Memory` at the given address.
     ///
     /// More recent mounts will take precedence over earlier mounts, effectively “shadowing” the earlier mounts.
-    pub fn mount(&mut self, start_address: usize, memory: Box<Memory>) -> MemoryToken {
+    ///
+    /// Fails with `MemoryError::OutOfBounds` if the memory would reach beyond `MAX_ADDRESS`.
+    pub fn mount(&mut self, start_address: usize, memory: Box<dyn Memory>) -> Result<MemoryToken> {
+        self.mount_named(start_address, "", memory)
+    }
+
+    /// Mounts a `Memory` at the given address like `mount()` and gives it a name.
+    ///
+    /// The name is only used to describe the memory layout (see `mounts()`).
+    pub fn mount_named(
+        &mut self,
+        start_address: usize,
+        name: &str,
+        memory: Box<dyn Memory>,
+    ) -> Result<MemoryToken> {
         let size = memory.size();
+        if start_address.saturating_add(size) > address::MAX_ADDRESS + 1 {
+            return Err(MemoryError::OutOfBounds(start_address.max(address::MAX_ADDRESS + 1)));
+        }
         let id = ID_COUNTER.fetch_add(1, Ordering::Relaxed);
         let new_entry = Entry {
             id,
+            name: String::from(name),
             start_address,
             size,
             memory,
             enabled: true,
         };
         self.memories.push(new_entry);
-        MemoryToken { id }
+        self.layout_changed();
+        Ok(MemoryToken { id })
     }
 
     /// Unmounts a memory.
-    pub fn unmount(&mut self, token: MemoryToken) -> Box<Memory> {
+    pub fn unmount(&mut self, token: MemoryToken) -> Box<dyn Memory> {
         let idx = self.memories
             .iter()
             .enumerate()
-            .find(|&(_idx, ref entry)| entry.id == token.id)
-            .map(|(idx, ref _entry)| idx)
+            .find(|&(_idx, entry)| entry.id == token.id)
+            .map(|(idx, _entry)| idx)
             .unwrap();
 
-        self.memories.remove(idx).memory
+        let entry = self.memories.remove(idx);
+        self.layout_changed();
+        entry.memory
+    }
+
+    /// Replaces a mounted memory, keeping its mount point, name and enabled state.
+    ///
+    /// The token stays valid. Returns the previously mounted memory.
+    pub fn replace(&mut self, token: &MemoryToken, memory: Box<dyn Memory>) -> Box<dyn Memory> {
+        let old_memory = {
+            let entry = self.entry_for_token_mut(token);
+            entry.size = memory.size();
+            mem::replace(&mut entry.memory, memory)
+        };
+        self.layout_changed();
+        old_memory
+    }
+
+    /// Creates a copy with the same mounts, using `fork_memory` to copy each mounted memory.
+    ///
+    /// The copy accepts the same `MemoryToken`s. Returns `None` as soon as `fork_memory` does.
+    pub fn fork_with<F>(&self, mut fork_memory: F) -> Option<MappedMemory>
+    where
+        F: FnMut(&MemoryToken, &dyn Memory) -> Option<Box<dyn Memory>>,
+    {
+        let mut forked = MappedMemory::new();
+        for entry in &self.memories {
+            let memory = fork_memory(&MemoryToken { id: entry.id }, &*entry.memory)?;
+            forked.memories.push(Entry {
+                id: entry.id,
+                name: entry.name.clone(),
+                start_address: entry.start_address,
+                size: entry.size,
+                enabled: entry.enabled,
+                memory,
+            });
+        }
+        forked.layout_changed();
+        Some(forked)
     }
 
     // Checks if a memory is enabled.
@@ -157,54 +363,90 @@ impl MappedMemory {
     /// Disables a memory. This is the same as unmounting without moving
     /// ownership out of `MappedMemory`.
     pub fn disable_mount(&mut self, token: &MemoryToken) {
-        let entry = self.entry_for_token_mut(token);
-        entry.enabled = false;
+        self.entry_for_token_mut(token).enabled = false;
+        self.layout_changed();
     }
 
     // Enables a memory. The mount point remains unchanged.
     pub fn enable_mount(&mut self, token: &MemoryToken) {
-        let entry = self.entry_for_token_mut(token);
-        entry.enabled = true;
+        self.entry_for_token_mut(token).enabled = true;
+        self.layout_changed();
     }
 
     /// Borrows a memory.
-    pub fn borrow(&self, token: &MemoryToken) -> &Box<Memory> {
-        &self.entry_for_token(token).memory
+    pub fn borrow(&self, token: &MemoryToken) -> &dyn Memory {
+        self.entry_for_token(token).memory.as_ref()
     }
 
     /// Mutably borrows a memory.
-    pub fn borrow_mut(&mut self, token: &MemoryToken) -> &mut Box<Memory> {
+    ///
+    /// This changes the generation (see “Change tracking”).
+    pub fn borrow_mut(&mut self, token: &MemoryToken) -> &mut Box<dyn Memory> {
+        self.changed();
         &mut self.entry_for_token_mut(token).memory
     }
 
+    /// Describes all mounted memories in the order they have been mounted.
+    ///
+    /// Later mounts shadow earlier ones.
+    pub fn mounts(&self) -> Vec<MountInfo> {
+        self.memories
+            .iter()
+            .map(|entry| MountInfo {
+                token: MemoryToken { id: entry.id },
+                name: entry.name.clone(),
+                start_address: entry.start_address,
+                size: entry.size,
+                enabled: entry.enabled,
+            })
+            .collect()
+    }
+
     fn entry_for_token(&self, token: &MemoryToken) -> &Entry {
         self.memories
             .iter()
-            .find(|ref entry| entry.id == token.id)
+            .find(|entry| entry.id == token.id)
             .unwrap()
     }
 
     fn entry_for_token_mut(&mut self, token: &MemoryToken) -> &mut Entry {
         self.memories
             .iter_mut()
-            .find(|ref mut entry| entry.id == token.id)
+            .find(|entry| entry.id == token.id)
             .unwrap()
     }
 
     fn enabled_entry_at_addr(&self, addr: usize) -> Option<&Entry> {
-        self.memories
-            .iter()
-            .rev()
-            .filter(|entry| entry.enabled)
-            .find(|entry| entry.contains(addr))
+        self.entry_idx_at_addr(addr).map(|idx| &self.memories[idx])
     }
 
     fn enabled_entry_at_addr_mut(&mut self, addr: usize) -> Option<&mut Entry> {
-        self.memories
-            .iter_mut()
-            .rev()
-            .filter(|entry| entry.enabled)
-            .find(|entry| entry.contains(addr))
+        self.entry_idx_at_addr(addr)
+            .map(move |idx| &mut self.memories[idx])
+    }
+
+    fn entry_idx_at_addr(&self, addr: usize) -> Option<usize> {
+        let mut idx = *self.pages.get(addr >> PAGE_BITS)? as usize;
+        while let Some(segment) = self.segments.get(idx) {
+            if addr < segment.start {
+                return None;
+            }
+            if addr < segment.end {
+                return Some(segment.entry);
+            }
+            idx += 1;
+        }
+        None
+    }
+}
+
+fn next_generation() -> usize {
+    GENERATION_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+impl Default for MappedMemory {
+    fn default() -> MappedMemory {
+        MappedMemory::new()
     }
 }
 
@@ -218,7 +460,8 @@ impl Memory for MappedMemory {
     fn set(&mut self, addr: usize, value: i32) {
         self.enabled_entry_at_addr_mut(addr)
             .map(|entry| entry.memory.set(addr - entry.start_address, value))
-            .expect("Out of bounds")
+            .expect("Out of bounds");
+        self.log_write(addr);
     }
 
     fn size(&self) -> usize {
@@ -229,19 +472,54 @@ impl Memory for MappedMemory {
             .max()
             .unwrap_or(0)
     }
+
+    fn try_get(&self, addr: usize) -> Result<i32> {
+        let entry = self.enabled_entry_at_addr(addr)
+            .ok_or(MemoryError::Unmapped(addr))?;
+        entry
+            .memory
+            .try_get(addr - entry.start_address)
+            .map_err(|err| err.at(addr))
+    }
+
+    fn try_set(&mut self, addr: usize, value: i32) -> Result<()> {
+        let entry = self.enabled_entry_at_addr_mut(addr)
+            .ok_or(MemoryError::Unmapped(addr))?;
+        let start_address = entry.start_address;
+        entry
+            .memory
+            .try_set(addr - start_address, value)
+            .map_err(|err| err.at(addr))?;
+        self.log_write(addr);
+        Ok(())
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        self.enabled_entry_at_addr(addr)
+            .and_then(|entry| entry.memory.peek(addr - entry.start_address))
+    }
+
+    /// Flushes all mounted memories, including disabled ones.
+    fn flush(&mut self) -> io::Result<()> {
+        for entry in &mut self.memories {
+            entry.memory.flush()?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use memory::{Memory, NullMemory, SliceMemory};
+    use super::PAGE_SIZE;
+    use memory::{address, Memory, MemoryError, NullMemory, ReadOnlyMemory, SliceMemory};
 
     #[test]
     #[allow(unused_variables)]
     fn overlapping_mounts() {
         let mut mm = super::MappedMemory::new();
-        let m1 = mm.mount(0, Box::new(NullMemory::new()));
-        let m2 = mm.mount(1, Box::new(SliceMemory::from_slice(Box::new([2, 2]))));
-        let m3 = mm.mount(2, Box::new(SliceMemory::from_slice(Box::new([3]))));
+        let m1 = mm.mount(0, Box::new(NullMemory::new())).unwrap();
+        let m2 = mm.mount(1, Box::new(SliceMemory::from_slice(Box::new([2, 2])))).unwrap();
+        let m3 = mm.mount(2, Box::new(SliceMemory::from_slice(Box::new([3])))).unwrap();
         assert_eq!(mm.get(0), 0);
         assert_eq!(mm.get(1), 2);
         assert_eq!(mm.get(2), 3);
@@ -252,8 +530,8 @@ mod tests {
     #[allow(unused_variables)]
     fn get_and_set() {
         let mut mm = super::MappedMemory::new();
-        let m1 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1]))));
-        let m2 = mm.mount(2, Box::new(SliceMemory::from_slice(Box::new([2, 2]))));
+        let m1 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1])))).unwrap();
+        let m2 = mm.mount(2, Box::new(SliceMemory::from_slice(Box::new([2, 2])))).unwrap();
 
         assert_eq!(mm.get(0), 1);
         assert_eq!(mm.get(2), 2);
@@ -275,10 +553,10 @@ mod tests {
         let m2 = Box::new(SliceMemory::from_slice(Box::new([2, 2])));
         assert_eq!(mm.size(), 0);
 
-        mm.mount(0, m1);
+        mm.mount(0, m1).unwrap();
         assert_eq!(mm.size(), 1);
 
-        mm.mount(2, m2);
+        mm.mount(2, m2).unwrap();
         assert_eq!(mm.size(), 4);
     }
 
@@ -290,11 +568,11 @@ mod tests {
         let m2 = Box::new(SliceMemory::from_slice(Box::new([2, 2])));
         let m3 = Box::new(SliceMemory::from_slice(Box::new([3, 3, 3])));
 
-        mm.mount(0, m1);
-        mm.mount(2, m2);
+        mm.mount(0, m1).unwrap();
+        mm.mount(2, m2).unwrap();
         assert_eq!(mm.size(), 5);
 
-        mm.mount(6, m3);
+        mm.mount(6, m3).unwrap();
         assert_eq!(mm.size(), 9);
     }
 
@@ -305,15 +583,15 @@ mod tests {
         let m1 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([1, 1, 1, 1, 1]))),
-        );
+        ).unwrap();
         let m2 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([2, 2, 2, 2, 2]))),
-        );
+        ).unwrap();
         let m3 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([3, 3, 3, 3, 3]))),
-        );
+        ).unwrap();
 
         for i in 0..5 {
             assert_eq!(mm.get(i), 3);
@@ -335,11 +613,11 @@ mod tests {
         let m1 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([1, 1, 1, 1, 1]))),
-        );
+        ).unwrap();
         let m2 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([2, 2, 2, 2, 2]))),
-        );
+        ).unwrap();
 
         for i in 0..5 {
             assert_eq!(mm.get(i), 2);
@@ -361,11 +639,11 @@ mod tests {
         let m1 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([1, 1, 1, 1, 1]))),
-        );
+        ).unwrap();
         let m2 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([2, 2, 2, 2, 2]))),
-        );
+        ).unwrap();
 
         for i in 0..5 {
             assert_eq!(mm.get(i), 2);
@@ -384,11 +662,11 @@ mod tests {
         let m1 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([1, 1, 1, 1, 1]))),
-        );
+        ).unwrap();
         let m2 = mm.mount(
             0,
             Box::new(SliceMemory::from_slice(Box::new([2, 2, 2, 2, 2]))),
-        );
+        ).unwrap();
 
         assert!(mm.is_enabled_mount(&m2));
         mm.disable_mount(&m2);
@@ -397,14 +675,167 @@ mod tests {
         assert!(mm.is_enabled_mount(&m2));
     }
 
+    #[test]
+    fn mounts() {
+        let mut mm = super::MappedMemory::new();
+        let m1 = mm.mount(0, Box::new(NullMemory::new())).unwrap();
+        let m2 = mm.mount_named(4, "slice", Box::new(SliceMemory::new(2))).unwrap();
+        mm.disable_mount(&m2);
+
+        let mounts = mm.mounts();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].token, m1);
+        assert_eq!(mounts[0].name, "");
+        assert_eq!(mounts[1].token, m2);
+        assert_eq!(mounts[1].name, "slice");
+        assert_eq!(mounts[1].start_address, 4);
+        assert_eq!(mounts[1].size, 2);
+        assert!(!mounts[1].enabled);
+    }
+
+    #[test]
+    fn replace() {
+        let mut mm = super::MappedMemory::new();
+        let m1 = mm.mount_named(4, "slice", Box::new(SliceMemory::new(2))).unwrap();
+        mm.disable_mount(&m1);
+        let old = mm.replace(&m1, Box::new(SliceMemory::from_slice(Box::new([1, 2, 3]))));
+        assert_eq!(old.size(), 2);
+
+        let mounts = mm.mounts();
+        assert_eq!(mounts[0].name, "slice");
+        assert_eq!(mounts[0].size, 3);
+        assert!(!mounts[0].enabled);
+        mm.enable_mount(&m1);
+        assert_eq!(mm.get(6), 3);
+    }
+
+    #[test]
+    #[allow(unused_variables)]
+    fn try_get_and_try_set() {
+        let mut mm = super::MappedMemory::new();
+        let m1 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1])))).unwrap();
+        let m2 = mm.mount(
+            2,
+            Box::new(ReadOnlyMemory::new(Box::new(SliceMemory::from_slice(
+                Box::new([2, 2]),
+            )))),
+        ).unwrap();
+
+        assert_eq!(mm.try_get(0), Ok(1));
+        assert_eq!(mm.try_get(1), Err(MemoryError::Unmapped(1)));
+        assert_eq!(mm.try_get(3), Ok(2));
+        assert_eq!(mm.try_set(0, 5), Ok(()));
+        assert_eq!(mm.get(0), 5);
+        assert_eq!(mm.try_set(1, 5), Err(MemoryError::Unmapped(1)));
+        assert_eq!(mm.try_set(3, 5), Err(MemoryError::ReadOnly(3)));
+        mm.disable_mount(&m1);
+        assert_eq!(mm.try_get(0), Err(MemoryError::Unmapped(0)));
+    }
+
+    #[test]
+    fn decoder_matches_mount_order() {
+        // Compares the decoder with a walk over the mounts for random layouts.
+        let mut seed: u32 = 1;
+        let mut random = |max: usize| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 8) as usize % max
+        };
+        let mut mm = super::MappedMemory::new();
+        let mut tokens = Vec::new();
+        for _ in 0..200 {
+            match random(4) {
+                0 | 1 => {
+                    let start = random(3 * PAGE_SIZE);
+                    let size = random(PAGE_SIZE + 2);
+                    tokens.push(mm.mount(start, Box::new(SliceMemory::new(size))).unwrap());
+                }
+                2 if !tokens.is_empty() => {
+                    let token = &tokens[random(tokens.len())];
+                    if random(2) == 0 {
+                        mm.disable_mount(token);
+                    } else {
+                        mm.enable_mount(token);
+                    }
+                }
+                _ if !tokens.is_empty() => {
+                    let idx = random(tokens.len());
+                    mm.unmount(tokens.remove(idx));
+                }
+                _ => {}
+            }
+
+            let mounts = mm.mounts();
+            let mut probes: Vec<usize> = (0..20).map(|_| random(4 * PAGE_SIZE)).collect();
+            for mount in &mounts {
+                let end = mount.start_address + mount.size;
+                probes.extend_from_slice(&[mount.start_address, end, end.saturating_sub(1)]);
+            }
+            for addr in probes {
+                let expected = mounts
+                    .iter()
+                    .rev()
+                    .filter(|mount| mount.enabled)
+                    .find(|mount| {
+                        mount.start_address <= addr && addr < mount.start_address + mount.size
+                    })
+                    .map(|mount| mount.token.clone());
+                assert_eq!(mm.token_at(addr), expected, "address {:X}", addr);
+            }
+        }
+    }
+
+    #[test]
+    fn change_tracking() {
+        let mut mm = super::MappedMemory::new();
+        let m1 = mm.mount(0, Box::new(SliceMemory::new(8))).unwrap();
+        let generation = mm.generation();
+        mm.set(1, 1);
+        assert_eq!(mm.drain_written().count(), 0);
+
+        mm.track_writes(true);
+        assert_ne!(mm.generation(), generation);
+        let generation = mm.generation();
+        mm.set(3, 1);
+        assert_eq!(mm.try_set(2, 1), Ok(()));
+        assert!(mm.try_set(9, 1).is_err());
+        assert_eq!(mm.drain_written().collect::<Vec<_>>(), vec![3, 2]);
+        assert_eq!(mm.drain_written().count(), 0);
+        assert_eq!(mm.generation(), generation);
+
+        mm.set(3, 1);
+        mm.disable_mount(&m1);
+        assert_ne!(mm.generation(), generation);
+        assert_eq!(mm.drain_written().count(), 0);
+        assert_eq!(mm.token_at(3), None);
+        mm.enable_mount(&m1);
+        assert_eq!(mm.token_at(3), Some(m1));
+    }
+
+    #[test]
+    fn mount_beyond_max_address() {
+        let mut mm = super::MappedMemory::new();
+        let top = address::MAX_ADDRESS - 1;
+        let m1 = mm.mount(top, Box::new(SliceMemory::new(2))).unwrap();
+        assert_eq!(mm.token_at(address::MAX_ADDRESS), Some(m1));
+        assert_eq!(
+            mm.mount(top, Box::new(SliceMemory::new(3))),
+            Err(MemoryError::OutOfBounds(address::MAX_ADDRESS + 1))
+        );
+        assert_eq!(
+            mm.mount_named(usize::MAX, "far", Box::new(SliceMemory::new(1))),
+            Err(MemoryError::OutOfBounds(usize::MAX))
+        );
+        assert_eq!(mm.mounts().len(), 1);
+    }
+
     #[test]
     #[allow(unused_variables)]
     #[should_panic]
     fn read_disabled() {
         let mut mm = super::MappedMemory::new();
-        let m1 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1]))));
-        let m2 = mm.mount(1, Box::new(SliceMemory::from_slice(Box::new([2]))));
-        let m3 = mm.mount(2, Box::new(SliceMemory::from_slice(Box::new([3]))));
+        let m1 = mm.mount(0, Box::new(SliceMemory::from_slice(Box::new([1])))).unwrap();
+        let m2 = mm.mount(1, Box::new(SliceMemory::from_slice(Box::new([2])))).unwrap();
+        let m3 = mm.mount(2, Box::new(SliceMemory::from_slice(Box::new([3])))).unwrap();
         mm.disable_mount(&m2);
         mm.get(1);
     }
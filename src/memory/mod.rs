@@ -16,6 +16,8 @@
 //!   |                 Word 0                |
 //!   |                 Word 1                |
 //!   |                   ...                 |
+//!   +---------------------------------------+ CONTROLS_ADDRESS
+//!   |              Peripherals              |
 //!   +---------------------------------------+ STACK_POINTER_ADDRESS
 //!   |              Stack Pointer            |
 //!   +---------------------------------------+ REGISTERS_START_ADDRESS
@@ -34,6 +36,12 @@
 //! ```
 //! The concrete values for these constants can be found in the `address` module.
 //!
+//! ### Peripherals
+//! Peripherals are memory-mapped below the stack pointer. Their addresses can be found in the `address` module. If a peripheral is not attached, its words behave like plain memory.
+//!
+//! - UART: `UART_DATA_ADDRESS` and `UART_STATUS_ADDRESS`. See the `device::uart` module.
+//! - Timer: `TIMER_COUNTER_ADDRESS`, `TIMER_RELOAD_ADDRESS` and `TIMER_CONTROL_ADDRESS`. Raises interrupts on `TIMER_INTERRUPT_LINE`. See the `device::timer` module.
+//!
 //! ### Registers
 //!
 //! - Register 0: General purpose
@@ -43,7 +51,7 @@
 //!
 //! ### IVTs
 //!
-//! - IVT Entry 0: Unused
+//! - IVT Entry 0: Address of the interrupt handler. All interrupt lines share this entry.
 //!
 //! ### Flags
 //! #### Word 0:
@@ -51,9 +59,9 @@
 //! ```text
 //!   MSB                                   LSB
 //!   +---------------------------------------+
-//!   |    |    |    |    |    |    | bD | H  | Byte 0
+//!   |    |    |    |    | IR | IE | bD | H  | Byte 0
 //!   +---------------------------------------+
-//!   |                 Unused                |
+//!   | P7 | P6 | P5 | P4 | P3 | P2 | P1 | P0 | Byte 1
 //!   +---------------------------------------+
 //!   |                 Unused                |
 //!   +---------------------------------------+
@@ -65,18 +73,94 @@
 //!
 //! - `biosDone` (`bD`): Unmaps the BIOS from the address space when set to 1
 //! - `halt` (`H`): Halts the CPU when set to 1
+//! - `interruptEnable` (`IE`): Allows pending interrupts to be dispatched when set to 1
+//! - `interruptReturn` (`IR`): Returns from an interrupt handler when set to 1
+//! - `pending` (`P0` to `P7`): Set to 1 when the corresponding interrupt line has been raised
+//!
+//! ### Interrupts
+//! Before fetching an instruction, the CPU checks if `IE` and any of the pending bits are set. If so, it dispatches the interrupt instead of executing an instruction:
+//!
+//! 1. The stack pointer is decremented by one.
+//! 2. The instruction pointer is stored at the address the stack pointer now points to.
+//! 3. `IE` is cleared.
+//! 4. The instruction pointer is set to the value of IVT entry 0.
+//!
+//! The pending bits are not cleared by the CPU. The handler is responsible for clearing the pending bits of the lines it has handled. To return from the handler, the `IR` bit is set. After the instruction that set the bit, the CPU pops the instruction pointer from the stack, sets `IE` and clears `IR`.
 
+mod cowmemory;
+mod filememory;
 mod nullmemory;
 mod slicememory;
 mod readonlymemory;
+mod sparsememory;
 pub mod mappedmemory;
 pub mod address;
+pub use self::cowmemory::CowMemory;
+pub use self::filememory::FileMemory;
 pub use self::nullmemory::NullMemory;
 pub use self::slicememory::SliceMemory;
 pub use self::mappedmemory::MappedMemory;
 pub use self::readonlymemory::ReadOnlyMemory;
+pub use self::sparsememory::SparseMemory;
+
+use std::ops::Range;
+use std::{error, fmt, io, iter, result};
+
+/// Error type for fallible memory accesses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryError {
+    /// The address is not mapped to any memory.
+    Unmapped(usize),
+    /// The address is beyond the end of the memory.
+    OutOfBounds(usize),
+    /// The memory at the address can’t be written.
+    ReadOnly(usize),
+    /// The storage backing the memory at the address failed, e.g. a host file couldn’t be read.
+    Io(usize),
+}
+
+impl MemoryError {
+    /// Returns the address of the failed access.
+    pub fn address(&self) -> usize {
+        match *self {
+            MemoryError::Unmapped(addr)
+            | MemoryError::OutOfBounds(addr)
+            | MemoryError::ReadOnly(addr)
+            | MemoryError::Io(addr) => addr,
+        }
+    }
+
+    /// Returns the same error for a different address.
+    ///
+    /// Useful to translate an address relative to a mount point into an absolute address.
+    pub fn at(&self, addr: usize) -> MemoryError {
+        match *self {
+            MemoryError::Unmapped(_) => MemoryError::Unmapped(addr),
+            MemoryError::OutOfBounds(_) => MemoryError::OutOfBounds(addr),
+            MemoryError::ReadOnly(_) => MemoryError::ReadOnly(addr),
+            MemoryError::Io(_) => MemoryError::Io(addr),
+        }
+    }
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryError::Unmapped(addr) => write!(f, "Address 0x{:08X} is unmapped", addr),
+            MemoryError::OutOfBounds(addr) => write!(f, "Address 0x{:08X} is out of bounds", addr),
+            MemoryError::ReadOnly(addr) => write!(f, "Address 0x{:08X} is read-only", addr),
+            MemoryError::Io(addr) => write!(f, "Address 0x{:08X} could not be accessed", addr),
+        }
+    }
+}
+
+impl error::Error for MemoryError {}
+
+pub type Result<T> = result::Result<T, MemoryError>;
 
 /// Access to individual memory cells.
+///
+/// `get()` and `set()` may panic when given an invalid address. `try_get()` and `try_set()` report these cases as a `MemoryError` instead. Their default implementations check `addr` against `size()`.
 pub trait Memory {
     /// Gets the value of the memory cell at `addr`.
     fn get(&self, addr: usize) -> i32;
@@ -84,4 +168,87 @@ pub trait Memory {
     fn set(&mut self, addr: usize, value: i32);
     /// Returns the size of this memory in bytes.
     fn size(&self) -> usize;
+
+    /// Gets the value of the memory cell at `addr` or fails if `addr` is invalid.
+    fn try_get(&self, addr: usize) -> Result<i32> {
+        if addr >= self.size() {
+            return Err(MemoryError::OutOfBounds(addr));
+        }
+        Ok(self.get(addr))
+    }
+
+    /// Sets the value of the memory cell at `addr` or fails if `addr` is invalid.
+    fn try_set(&mut self, addr: usize, value: i32) -> Result<()> {
+        if addr >= self.size() {
+            return Err(MemoryError::OutOfBounds(addr));
+        }
+        self.set(addr, value);
+        Ok(())
+    }
+
+    /// Reads the memory cell at `addr` without side effects.
+    ///
+    /// Returns `None` if `addr` is invalid or if the value can’t be read without side effects, e.g. a device register that consumes input when read. This is meant for debugging tools that must not disturb the emulated program.
+    fn peek(&self, addr: usize) -> Option<i32> {
+        self.try_get(addr).ok()
+    }
+
+    /// Returns the address ranges that are backed by storage, in ascending order.
+    ///
+    /// All other addresses read as 0. Memories that allocate storage lazily (like `SparseMemory`) override this so their contents can be saved without visiting every address.
+    fn allocated(&self) -> Vec<Range<usize>> {
+        iter::once(0..self.size()).collect()
+    }
+
+    /// Writes buffered changes to the storage backing the memory (e.g. `FileMemory`).
+    ///
+    /// Memories that don’t buffer changes do nothing.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns a copy of the memory that can be changed independently of this one.
+    ///
+    /// Forking must not change the behavior of this memory. Memories that can’t be copied return `None` (the default).
+    fn fork(&self) -> Option<Box<dyn Memory>> {
+        None
+    }
+}
+
+impl<M: Memory + ?Sized> Memory for Box<M> {
+    fn get(&self, addr: usize) -> i32 {
+        (**self).get(addr)
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        (**self).set(addr, value)
+    }
+
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
+    fn try_get(&self, addr: usize) -> Result<i32> {
+        (**self).try_get(addr)
+    }
+
+    fn try_set(&mut self, addr: usize, value: i32) -> Result<()> {
+        (**self).try_set(addr, value)
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        (**self).peek(addr)
+    }
+
+    fn allocated(&self) -> Vec<Range<usize>> {
+        (**self).allocated()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn fork(&self) -> Option<Box<dyn Memory>> {
+        (**self).fork()
+    }
 }
@@ -1,7 +1,6 @@
 //! Like `/dev/null`.
-use std;
-
 use memory::Memory;
+use std::ops::Range;
 
 /// A read-only memory full of zeros.
 ///
@@ -26,6 +25,12 @@ impl NullMemory {
     }
 }
 
+impl Default for NullMemory {
+    fn default() -> NullMemory {
+        NullMemory::new()
+    }
+}
+
 impl Memory for NullMemory {
     #[inline]
     fn get(&self, _: usize) -> i32 {
@@ -37,7 +42,15 @@ impl Memory for NullMemory {
 
     #[inline]
     fn size(&self) -> usize {
-        std::i32::MAX as usize
+        i32::MAX as usize
+    }
+
+    fn allocated(&self) -> Vec<Range<usize>> {
+        Vec::new()
+    }
+
+    fn fork(&self) -> Option<Box<dyn Memory>> {
+        Some(Box::new(NullMemory))
     }
 }
 
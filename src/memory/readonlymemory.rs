@@ -1,11 +1,15 @@
 //! Make a memory read-only.
-use memory::Memory;
+use memory::{Memory, MemoryError, Result};
+use std::io;
+use std::ops::Range;
 
 /// Wraps another `Memory` and discards all writes.
-pub struct ReadOnlyMemory(Box<Memory>);
+///
+/// `try_set()` fails with `MemoryError::ReadOnly`.
+pub struct ReadOnlyMemory(Box<dyn Memory>);
 
 impl ReadOnlyMemory {
-    pub fn new(m: Box<Memory>) -> ReadOnlyMemory {
+    pub fn new(m: Box<dyn Memory>) -> ReadOnlyMemory {
         ReadOnlyMemory(m)
     }
 }
@@ -22,11 +26,39 @@ impl Memory for ReadOnlyMemory {
     fn size(&self) -> usize {
         self.0.size()
     }
+
+    fn try_get(&self, addr: usize) -> Result<i32> {
+        self.0.try_get(addr)
+    }
+
+    fn peek(&self, addr: usize) -> Option<i32> {
+        self.0.peek(addr)
+    }
+
+    fn allocated(&self) -> Vec<Range<usize>> {
+        self.0.allocated()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn fork(&self) -> Option<Box<dyn Memory>> {
+        let inner = self.0.fork()?;
+        Some(Box::new(ReadOnlyMemory(inner)))
+    }
+
+    fn try_set(&mut self, addr: usize, _: i32) -> Result<()> {
+        if addr >= self.size() {
+            return Err(MemoryError::OutOfBounds(addr));
+        }
+        Err(MemoryError::ReadOnly(addr))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use memory::{Memory, SliceMemory};
+    use memory::{Memory, MemoryError, SliceMemory};
 
     #[test]
     fn read() {
@@ -46,6 +78,15 @@ mod tests {
         assert_eq!(m.get(3), 3);
     }
 
+    #[test]
+    fn try_set() {
+        let sm = SliceMemory::from_slice(Box::new([0, 1, 2, 3]));
+        let mut m = super::ReadOnlyMemory::new(Box::new(sm));
+        assert_eq!(m.try_set(1, 9), Err(MemoryError::ReadOnly(1)));
+        assert_eq!(m.try_set(4, 9), Err(MemoryError::OutOfBounds(4)));
+        assert_eq!(m.get(1), 1);
+    }
+
     #[test]
     fn size() {
         let sm = SliceMemory::from_slice(Box::new([0, 1, 2, 3]));
@@ -22,10 +22,10 @@ use std::vec::Vec;
 /// ```
 /// # use osciemu::memory::{Memory, SliceMemory};
 ///
-/// let v : Vec<i32> = vec![0, 0xFFFFFFFF];
+/// let v : Vec<i32> = vec![0, -1];
 /// let m = SliceMemory(v.into_boxed_slice());
 /// assert_eq!(m.get(0), 0);
-/// assert_eq!(m.get(1), 0xFFFFFFFF);
+/// assert_eq!(m.get(1), -1);
 /// ```
 pub struct SliceMemory(pub Box<[i32]>);
 
@@ -57,6 +57,11 @@ impl Memory for SliceMemory {
     fn size(&self) -> usize {
         self.0.len()
     }
+
+    /// Copies the contents, which takes time proportional to the size of the memory.
+    fn fork(&self) -> Option<Box<dyn Memory>> {
+        Some(Box::new(SliceMemory(self.0.clone())))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,178 @@
+//! Memory that allocates pages on demand.
+use memory::Memory;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// A memory that only allocates storage for pages that have been written.
+///
+/// The `SparseMemory` has a fixed size but starts out without any storage. Reads from pages that haven’t been written yield 0. The first write of a non-zero value to a page allocates the page. Pages are looked up in a two-level table, so accesses take constant time regardless of how many pages have been allocated.
+///
+/// Forking shares the allocated pages between both memories. A shared page is copied when one of them writes to it.
+///
+/// # Examples
+///
+/// ```
+/// use osciemu::memory::{Memory, SparseMemory};
+///
+/// let mut m = SparseMemory::new(1 << 30);
+/// assert_eq!(m.get(0x1234_5678), 0);
+/// m.set(0x1234_5678, 42);
+/// assert_eq!(m.get(0x1234_5678), 42);
+/// assert_eq!(m.allocated_pages(), 1);
+/// ```
+#[derive(Clone)]
+pub struct SparseMemory {
+    tables: Vec<Option<Table>>,
+    size: usize,
+    allocated_pages: usize,
+}
+
+type Page = Rc<Vec<i32>>;
+type Table = Box<[Option<Page>]>;
+
+const PAGE_BITS: usize = 10;
+const TABLE_BITS: usize = 10;
+
+impl SparseMemory {
+    /// Number of words per page.
+    pub const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+    /// Creates a `SparseMemory` with `size` words, all of them 0.
+    pub fn new(size: usize) -> SparseMemory {
+        let num_pages = size.div_ceil(SparseMemory::PAGE_SIZE);
+        let num_tables = num_pages.div_ceil(1 << TABLE_BITS);
+        SparseMemory {
+            tables: (0..num_tables).map(|_| None).collect(),
+            size,
+            allocated_pages: 0,
+        }
+    }
+
+    /// Returns the number of pages that have been allocated.
+    pub fn allocated_pages(&self) -> usize {
+        self.allocated_pages
+    }
+
+    fn page(&self, addr: usize) -> Option<&[i32]> {
+        let table = self.tables[addr >> (PAGE_BITS + TABLE_BITS)].as_ref()?;
+        table[(addr >> PAGE_BITS) & ((1 << TABLE_BITS) - 1)]
+            .as_ref()
+            .map(|page| &page[..])
+    }
+}
+
+impl Memory for SparseMemory {
+    fn get(&self, addr: usize) -> i32 {
+        assert!(addr < self.size, "Out of bounds");
+        self.page(addr)
+            .map_or(0, |page| page[addr & (SparseMemory::PAGE_SIZE - 1)])
+    }
+
+    fn set(&mut self, addr: usize, value: i32) {
+        assert!(addr < self.size, "Out of bounds");
+        let table = &mut self.tables[addr >> (PAGE_BITS + TABLE_BITS)];
+        if table.is_none() {
+            if value == 0 {
+                return;
+            }
+            *table = Some((0..1 << TABLE_BITS).map(|_| None).collect());
+        }
+        let page = &mut table.as_mut().unwrap()[(addr >> PAGE_BITS) & ((1 << TABLE_BITS) - 1)];
+        if page.is_none() {
+            if value == 0 {
+                return;
+            }
+            *page = Some(Rc::new(vec![0; SparseMemory::PAGE_SIZE]));
+            self.allocated_pages += 1;
+        }
+        Rc::make_mut(page.as_mut().unwrap())[addr & (SparseMemory::PAGE_SIZE - 1)] = value;
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn allocated(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (table_idx, table) in self.tables.iter().enumerate() {
+            let table = match *table {
+                Some(ref table) => table,
+                None => continue,
+            };
+            for (page_idx, page) in table.iter().enumerate() {
+                if page.is_none() {
+                    continue;
+                }
+                let start = ((table_idx << TABLE_BITS) + page_idx) << PAGE_BITS;
+                let end = (start + SparseMemory::PAGE_SIZE).min(self.size);
+                match ranges.last_mut() {
+                    Some(last) if last.end == start => last.end = end,
+                    _ => ranges.push(start..end),
+                }
+            }
+        }
+        ranges
+    }
+
+    fn fork(&self) -> Option<Box<dyn Memory>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SparseMemory;
+    use memory::{Memory, MemoryError};
+
+    #[test]
+    fn get_and_set() {
+        let mut m = SparseMemory::new(3 * SparseMemory::PAGE_SIZE + 5);
+        assert_eq!(m.get(3 * SparseMemory::PAGE_SIZE + 4), 0);
+        m.set(1, 0);
+        assert_eq!(m.allocated_pages(), 0);
+        m.set(1, 1);
+        m.set(SparseMemory::PAGE_SIZE + 2, 2);
+        m.set(3 * SparseMemory::PAGE_SIZE + 4, 3);
+        m.set(SparseMemory::PAGE_SIZE + 3, 0);
+        assert_eq!(m.get(1), 1);
+        assert_eq!(m.get(SparseMemory::PAGE_SIZE + 2), 2);
+        assert_eq!(m.get(3 * SparseMemory::PAGE_SIZE + 4), 3);
+        assert_eq!(m.allocated_pages(), 3);
+        assert_eq!(
+            m.try_set(3 * SparseMemory::PAGE_SIZE + 5, 1),
+            Err(MemoryError::OutOfBounds(3 * SparseMemory::PAGE_SIZE + 5))
+        );
+    }
+
+    #[test]
+    fn allocated() {
+        let page = SparseMemory::PAGE_SIZE;
+        let mut m = SparseMemory::new(2000 * page + 5);
+        assert_eq!(m.allocated(), vec![]);
+        m.set(page, 1);
+        m.set(2 * page + 7, 1);
+        m.set(1500 * page, 1);
+        m.set(2000 * page + 1, 1);
+        assert_eq!(
+            m.allocated(),
+            vec![
+                page..3 * page,
+                1500 * page..1501 * page,
+                2000 * page..2000 * page + 5
+            ]
+        );
+    }
+
+    #[test]
+    fn fork() {
+        let page = SparseMemory::PAGE_SIZE;
+        let mut m = SparseMemory::new(4 * page);
+        m.set(1, 1);
+        let mut forked = m.fork().unwrap();
+        forked.set(1, 2);
+        forked.set(page, 3);
+        m.set(2, 4);
+        assert_eq!((m.get(1), m.get(2), m.get(page)), (1, 4, 0));
+        assert_eq!((forked.get(1), forked.get(2), forked.get(page)), (2, 0, 3));
+    }
+}
@@ -0,0 +1,277 @@
+//! Execution profiles.
+//!
+//! `Profiler` is a `TraceSink` that counts per address how often an instruction has been fetched, how often its jump has been taken and how often the address has been read or written by an instruction. Reads include the pointers of indirect operands. Like all trace sinks, it doesn’t see cycles spent dispatching interrupts, so the stack accesses of interrupts are not counted.
+//!
+//! A profile can be written as a hot-spot report (`write_report()`) or in the [callgrind format] (`write_callgrind()`) to be viewed with tools like KCachegrind. Both use symbol names for addresses if symbols have been set with `set_symbols()`.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::emulator::{Emulator, RunLimits};
+//! # use osciemu::memory::Memory;
+//! # use osciemu::profile::Profiler;
+//! let mut bios_code = std::io::Cursor::new("
+//!     ## Count register 0 down from 3, then halt
+//!     7FFFFFF9 40000011 7FFFFFF9 40000008
+//!     0 0 0 40000000
+//!     40000011 0 7FFFFFFE 0
+//!     0 0 0 0
+//!     3 1
+//! ");
+//! let bios = osciemu::loader::hexloader::load(&mut bios_code).unwrap();
+//! let mut emu = Emulator::from_bios_only(bios);
+//! emu.memory.set(0x7FFFFFF9, 3);
+//! let profiler = emu.attach_tracer(Profiler::new());
+//! emu.run(RunLimits::default());
+//! let profile = profiler.borrow();
+//! assert_eq!(profile.get(0x40000000).fetches, 3);
+//! assert_eq!(profile.get(0x40000000).taken, 1);
+//! assert_eq!(profile.get(0x7FFFFFF9).writes, 3);
+//! ```
+//!
+//! [callgrind format]: https://valgrind.org/docs/manual/cl-format.html
+use instruction::Instruction;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use symbols::Symbols;
+use trace::{TraceRecord, TraceSink};
+
+/// Counters of a single address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AddressProfile {
+    /// Number of executed instructions at this address
+    pub fetches: u64,
+    /// Number of times the jump of the instruction at this address has been taken
+    pub taken: u64,
+    /// Number of reads of this address
+    pub reads: u64,
+    /// Number of writes to this address
+    pub writes: u64,
+}
+
+/// A `TraceSink` that counts instruction fetches, taken jumps, reads and writes per address.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    counters: BTreeMap<usize, AddressProfile>,
+    symbols: Symbols,
+    cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    /// Sets the symbols used for addresses in the output.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Returns the counters of `addr`.
+    pub fn get(&self, addr: usize) -> AddressProfile {
+        self.counters.get(&addr).cloned().unwrap_or_default()
+    }
+
+    /// Returns the counters of all addresses that have been accessed.
+    pub fn counters(&self) -> &BTreeMap<usize, AddressProfile> {
+        &self.counters
+    }
+
+    /// Returns the number of recorded instructions.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Formats an address using the symbols (see `Symbols::format()`).
+    pub fn location(&self, addr: usize) -> String {
+        self.symbols.format(addr)
+    }
+
+    /// Writes the `limit` most executed instructions and the `limit` most accessed addresses.
+    pub fn write_report<W: Write>(&self, w: &mut W, limit: usize) -> io::Result<()> {
+        let mut instructions: Vec<(&usize, &AddressProfile)> = self
+            .counters
+            .iter()
+            .filter(|(_, counters)| counters.fetches > 0)
+            .collect();
+        instructions.sort_by(|a, b| b.1.fetches.cmp(&a.1.fetches).then(a.0.cmp(b.0)));
+        writeln!(w, "Instructions ({} executed):", self.cycles)?;
+        writeln!(w, "{:>10} {:>6} {:>10}  address", "fetches", "%", "taken")?;
+        for (addr, counters) in instructions.into_iter().take(limit) {
+            writeln!(
+                w,
+                "{:>10} {:>5.1}% {:>10}  0x{:08X} {}",
+                counters.fetches,
+                100.0 * counters.fetches as f64 / self.cycles as f64,
+                counters.taken,
+                addr,
+                self.location(*addr)
+            )?;
+        }
+
+        let mut accesses: Vec<(&usize, &AddressProfile)> = self
+            .counters
+            .iter()
+            .filter(|(_, counters)| counters.reads + counters.writes > 0)
+            .collect();
+        accesses.sort_by(|a, b| {
+            (b.1.reads + b.1.writes)
+                .cmp(&(a.1.reads + a.1.writes))
+                .then(a.0.cmp(b.0))
+        });
+        writeln!(w)?;
+        writeln!(w, "Memory accesses:")?;
+        writeln!(w, "{:>10} {:>10}  address", "reads", "writes")?;
+        for (addr, counters) in accesses.into_iter().take(limit) {
+            writeln!(
+                w,
+                "{:>10} {:>10}  0x{:08X} {}",
+                counters.reads,
+                counters.writes,
+                addr,
+                self.location(*addr)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the instruction counters in the callgrind format.
+    ///
+    /// Every symbol becomes a function. The events are the number of executed instructions (`Ir`) and taken jumps (`Jt`) per instruction address.
+    pub fn write_callgrind<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "# callgrind format")?;
+        writeln!(w, "version: 1")?;
+        writeln!(w, "creator: osciemu")?;
+        writeln!(w, "positions: instr")?;
+        writeln!(w, "events: Ir Jt")?;
+        let taken: u64 = self.counters.values().map(|counters| counters.taken).sum();
+        writeln!(w, "summary: {} {}", self.cycles, taken)?;
+        let mut function = None;
+        for (addr, counters) in self.counters.iter().filter(|(_, c)| c.fetches > 0) {
+            let name = self
+                .symbols
+                .lookup(*addr)
+                .map_or("(unknown)", |(name, _)| name);
+            if function != Some(name) {
+                writeln!(w)?;
+                writeln!(w, "fn={}", name)?;
+                function = Some(name);
+            }
+            writeln!(w, "0x{:X} {} {}", addr, counters.fetches, counters.taken)?;
+        }
+        Ok(())
+    }
+
+    fn counters_mut(&mut self, addr: usize) -> &mut AddressProfile {
+        self.counters.entry(addr).or_default()
+    }
+}
+
+impl TraceSink for Profiler {
+    fn record(&mut self, record: &TraceRecord) {
+        let Instruction {
+            op_a,
+            op_b,
+            target,
+            jmp,
+        } = record.instruction;
+        self.cycles += 1;
+        {
+            let counters = self.counters_mut(record.ip);
+            counters.fetches += 1;
+            if record.execution.jumped {
+                counters.taken += 1;
+            }
+        }
+        for op in &[op_a, op_b, target, jmp] {
+            if *op < 0 {
+                self.counters_mut(op.unsigned_abs() as usize).reads += 1;
+            }
+        }
+        self.counters_mut(record.execution.op_a).reads += 1;
+        self.counters_mut(record.execution.op_b).reads += 1;
+        self.counters_mut(record.execution.target).writes += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+    use emulator::{Emulator, RunLimits};
+    use loader::hexloader;
+    use memory::Memory;
+    use std::io::Cursor;
+    use symbols::Symbols;
+
+    fn profile() -> Profiler {
+        // Counts register 0 down from 2 with an indirect operand, then halts.
+        let mut bios_code = Cursor::new(
+            "
+            7FFFFFF9 -40000012 7FFFFFF9 40000008
+            0 0 0 40000000
+            40000011 0 7FFFFFFE 0
+            0 0 0 0
+            2 1 40000011
+        ",
+        );
+        let bios = hexloader::load(&mut bios_code).unwrap();
+        let mut emu = Emulator::from_bios_only(bios);
+        emu.memory.set(0x7FFFFFF9, 2);
+        let profiler = emu.attach_tracer(Profiler::new());
+        emu.run(RunLimits::default());
+        let mut profiler = profiler.borrow().clone();
+        let mut symbols = Symbols::new();
+        symbols.add_label(0x40000000, "loop");
+        symbols.add_label(0x40000008, "done");
+        profiler.set_symbols(symbols);
+        profiler
+    }
+
+    #[test]
+    fn counters() {
+        let profiler = profile();
+        assert_eq!(profiler.cycles(), 4);
+        let looped = profiler.get(0x40000000);
+        assert_eq!((looped.fetches, looped.taken), (2, 1));
+        let back = profiler.get(0x40000004);
+        assert_eq!((back.fetches, back.taken), (1, 1));
+        assert_eq!(profiler.get(0x7FFFFFF9).reads, 2);
+        // The pointer and the value it points to
+        assert_eq!(profiler.get(0x40000012).reads, 2);
+        assert_eq!(profiler.get(0x40000011).reads, 3);
+        assert_eq!(profiler.get(0x7FFFFFF9).writes, 2);
+        assert_eq!(profiler.get(0x7FFFFFFE).writes, 1);
+        assert_eq!(profiler.get(0x12345), Default::default());
+    }
+
+    #[test]
+    fn report() {
+        let profiler = profile();
+        assert_eq!(profiler.location(0x40000000), "loop");
+        assert_eq!(profiler.location(0x40000012), "done+10");
+        assert_eq!(profiler.location(0x3FFFFFFF), "0x3FFFFFFF");
+
+        let mut report = Vec::new();
+        profiler.write_report(&mut report, 2).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "Instructions (4 executed):");
+        assert_eq!(lines[2], "         2  50.0%          1  0x40000000 loop");
+        assert_eq!(lines[3], "         1  25.0%          1  0x40000004 loop+4");
+        assert_eq!(lines[5], "Memory accesses:");
+        assert_eq!(lines[7], "         3          1  0x00000000 0x00000000");
+        assert_eq!(lines[8], "         2          2  0x7FFFFFF9 r0");
+        assert_eq!(lines.len(), 9);
+    }
+
+    #[test]
+    fn callgrind() {
+        let mut output = Vec::new();
+        profile().write_callgrind(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("# callgrind format\n"));
+        assert!(output.contains("events: Ir Jt\nsummary: 4 2\n"));
+        assert!(output.contains("\nfn=loop\n0x40000000 2 1\n0x40000004 1 1\n"));
+        assert!(output.ends_with("\nfn=done\n0x40000008 1 0\n"));
+    }
+}
@@ -0,0 +1,286 @@
+//! Debug symbols.
+//!
+//! `Symbols` maps labels and source lines to word addresses. It is created by the assembler (see `Symbols::from_program()`) and can be stored in a symbol file next to an assembled image, e.g. `foo.sym` next to `foo.hex`. `utils::load_image()` picks up that file automatically.
+//!
+//! The addresses are the ones the source claims with `.addr`, so the image needs to be mounted there for the symbols to be meaningful.
+//!
+//! # File format
+//!
+//! A symbol file is a text file with one entry per line. Numbers are hexadecimal unless noted otherwise, everything after a `#` is ignored.
+//!
+//! - `source PATH`: The source file the image has been assembled from
+//! - `label ADDR NAME`: A label
+//! - `line ADDR SIZE LINE KIND`: The words `ADDR` to `ADDR + SIZE` have been emitted by the (decimal) source line `LINE`. `KIND` is `code` for instructions and `data` for data directives.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::assembler;
+//! # use osciemu::symbols::Symbols;
+//! let program = assembler::assemble("
+//!     .addr 0x40000000
+//!     loop: 0 0 0 loop
+//!     data: .dw 1 2 3
+//! ").unwrap();
+//! let symbols = Symbols::from_program(&program, Some("loop.asm"));
+//! assert_eq!(symbols.format(0x40000006), "data+2");
+//! assert_eq!(symbols.format(0x7FFFFFF9), "r0");
+//! assert_eq!(symbols.line_at(0x40000001).unwrap().line, 3);
+//!
+//! let mut file = Vec::new();
+//! symbols.write(&mut file).unwrap();
+//! assert_eq!(Symbols::read(&mut file.as_slice()).unwrap(), symbols);
+//! ```
+use assembler::Program;
+use disassembler;
+use loader::{LoadError, Result};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Words emitted by a source line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolLine {
+    /// Address of the first word
+    pub address: usize,
+    /// Number of words
+    pub size: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// Whether the line is an instruction rather than a data directive
+    pub instruction: bool,
+}
+
+/// Labels and line table of an image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    source: Option<String>,
+    labels: BTreeMap<usize, String>,
+    lines: Vec<SymbolLine>,
+}
+
+/// Returns the path of the symbol file for an image.
+pub fn sidecar_path(image: &Path) -> PathBuf {
+    image.with_extension("sym")
+}
+
+impl Symbols {
+    /// Creates an empty symbol table.
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// Collects the labels (see `Program::labels()`) and lines of an assembled program.
+    pub fn from_program(program: &Program, source: Option<&str>) -> Symbols {
+        Symbols {
+            source: source.map(String::from),
+            labels: program.labels(),
+            lines: program
+                .lines
+                .iter()
+                .map(|line| SymbolLine {
+                    address: line.address,
+                    size: line.size,
+                    line: line.line,
+                    instruction: line.instruction,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the path of the source file, if known.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_ref().map(|s| &s[..])
+    }
+
+    /// Returns all labels by their address.
+    pub fn labels(&self) -> &BTreeMap<usize, String> {
+        &self.labels
+    }
+
+    /// Returns the line table in source order.
+    pub fn lines(&self) -> &[SymbolLine] {
+        &self.lines
+    }
+
+    /// Adds a label. An existing label at the same address is replaced.
+    pub fn add_label(&mut self, addr: usize, name: &str) {
+        self.labels.insert(addr, String::from(name));
+    }
+
+    /// Adds the labels and lines of `other`. The source is kept.
+    pub fn extend(&mut self, other: &Symbols) {
+        for (addr, name) in &other.labels {
+            self.labels.entry(*addr).or_insert_with(|| name.clone());
+        }
+        self.lines.extend_from_slice(&other.lines);
+    }
+
+    /// Checks if there are no labels and no lines.
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+
+    /// Returns the address of a label.
+    pub fn address_of(&self, name: &str) -> Option<usize> {
+        self.labels
+            .iter()
+            .find(|(_, label)| *label == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Returns the line that emitted the word at `addr`.
+    pub fn line_at(&self, addr: usize) -> Option<&SymbolLine> {
+        self.lines
+            .iter()
+            .find(|line| line.address <= addr && addr < line.address + line.size)
+    }
+
+    /// Returns the closest label at or below `addr` and the offset of `addr` from it.
+    ///
+    /// If there is a line table, only addresses emitted by a line are considered to belong to a label.
+    pub fn lookup(&self, addr: usize) -> Option<(&str, usize)> {
+        let (start, name) = self.labels.range(..=addr).next_back()?;
+        if *start != addr && !self.lines.is_empty() && self.line_at(addr).is_none() {
+            return None;
+        }
+        Some((name, addr - start))
+    }
+
+    /// Formats an address as `label` or `label+offset`.
+    ///
+    /// Addresses that are not labeled themselves use the name of a control register or peripheral (see `disassembler::address_name()`) if they have one. Otherwise they are formatted in hex.
+    pub fn format(&self, addr: usize) -> String {
+        match self.lookup(addr) {
+            Some((name, 0)) => String::from(name),
+            lookup => match (disassembler::address_name(addr), lookup) {
+                (Some(name), _) => name,
+                (None, Some((name, offset))) => format!("{}+{}", name, offset),
+                (None, None) => format!("0x{:08X}", addr),
+            },
+        }
+    }
+
+    /// Formats an operand like `format()`. Indirect operands are prefixed with `*`.
+    pub fn format_operand(&self, op: i32) -> String {
+        if op < 0 {
+            format!("*{}", self.format(op.wrapping_neg() as u32 as usize))
+        } else {
+            self.format(op as usize)
+        }
+    }
+
+    /// Writes the symbols in the symbol file format.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "# osci symbols")?;
+        if let Some(ref source) = self.source {
+            writeln!(w, "source {}", source)?;
+        }
+        for (addr, name) in &self.labels {
+            writeln!(w, "label {:08X} {}", addr, name)?;
+        }
+        for line in &self.lines {
+            writeln!(
+                w,
+                "line {:08X} {:X} {} {}",
+                line.address,
+                line.size,
+                line.line,
+                if line.instruction { "code" } else { "data" }
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads symbols in the symbol file format.
+    pub fn read<R: Read>(r: &mut R) -> Result<Symbols> {
+        let mut symbols = Symbols::new();
+        for (idx, line) in BufReader::new(r).lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let error =
+                || LoadError::from_message(format!("line {}: Invalid symbol entry", idx + 1));
+            match fields.first() {
+                None => {}
+                Some(&"source") => {
+                    let path = line.trim().strip_prefix("source").unwrap().trim();
+                    symbols.source = Some(String::from(path));
+                }
+                Some(&"label") if fields.len() == 3 => {
+                    let addr = usize::from_str_radix(fields[1], 16).map_err(|_| error())?;
+                    symbols.add_label(addr, fields[2]);
+                }
+                Some(&"line") if fields.len() == 5 => {
+                    let instruction = match fields[4] {
+                        "code" => true,
+                        "data" => false,
+                        _ => return Err(error()),
+                    };
+                    symbols.lines.push(SymbolLine {
+                        address: usize::from_str_radix(fields[1], 16).map_err(|_| error())?,
+                        size: usize::from_str_radix(fields[2], 16).map_err(|_| error())?,
+                        line: fields[3].parse().map_err(|_| error())?,
+                        instruction,
+                    });
+                }
+                Some(_) => return Err(error()),
+            }
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sidecar_path, Symbols};
+    use std::path::Path;
+
+    #[test]
+    fn format() {
+        let mut symbols = Symbols::new();
+        symbols.add_label(0x40000000, "start");
+        symbols.add_label(0x7FFFFFF9, "counter");
+        assert_eq!(symbols.format(0x40000000), "start");
+        assert_eq!(symbols.format(0x4000000A), "start+10");
+        assert_eq!(symbols.format(0x3FFFFFFF), "0x3FFFFFFF");
+        assert_eq!(symbols.format(0x7FFFFFF9), "counter");
+        assert_eq!(symbols.format(0x7FFFFFFA), "r1");
+        assert_eq!(symbols.format_operand(-0x40000001), "*start+1");
+        assert_eq!(symbols.address_of("counter"), Some(0x7FFFFFF9));
+        assert_eq!(symbols.address_of("foo"), None);
+    }
+
+    #[test]
+    fn read() {
+        let symbols = Symbols::read(
+            &mut "
+            # comment
+            source dir/my file.asm
+            label 40000000 start # comment
+            line 40000000 4 3 code
+            line 40000004 8 5 data
+            "
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(symbols.source(), Some("dir/my file.asm"));
+        assert_eq!(symbols.lines().len(), 2);
+        assert_eq!(symbols.line_at(0x4000000B).map(|l| l.line), Some(5));
+        // Beyond the line table
+        assert_eq!(symbols.lookup(0x4000000C), None);
+        assert_eq!(symbols.format(0x4000000B), "start+11");
+
+        assert!(Symbols::read(&mut "label 40000000".as_bytes()).is_err());
+        assert!(Symbols::read(&mut "line 0 4 3 text".as_bytes()).is_err());
+        assert!(Symbols::read(&mut "foo".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn sidecar() {
+        assert_eq!(
+            sidecar_path(Path::new("examples/foo.bios.hex")),
+            Path::new("examples/foo.bios.sym")
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Execution traces.
+//!
+//! A `TraceSink` attached with `Emulator::attach_tracer()` receives a `TraceRecord` for every executed instruction. Cycles spent dispatching interrupts and faulting cycles are not recorded.
+//!
+//! `Tracer` is a sink that writes the records in one of the `TraceFormat`s, optionally restricted to the instructions in certain address ranges.
+//!
+//! # Examples
+//!
+//! ```
+//! # use osciemu::emulator::Emulator;
+//! # use osciemu::trace::TraceRecord;
+//! let mut bios_code = std::io::Cursor::new("
+//!     ## Calculate 0x10 - 0x3 and store it in register 0
+//!     40000004 40000005 7FFFFFF9 0
+//!     10 3
+//! ");
+//! let bios = osciemu::loader::hexloader::load(&mut bios_code).unwrap();
+//! let mut emu = Emulator::from_bios_only(bios);
+//! let records = emu.attach_tracer(Vec::<TraceRecord>::new());
+//! emu.step();
+//! let record = records.borrow()[0];
+//! assert_eq!(record.execution.result, 0x10 - 0x3);
+//! assert!(!record.execution.jumped);
+//! ```
+use instruction::{Execution, Instruction};
+use memory::MappedMemory;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::str::FromStr;
+use symbols::Symbols;
+
+/// An executed instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Number of cycles executed before this instruction
+    pub cycle: usize,
+    /// Address of the instruction
+    pub ip: usize,
+    /// The instruction as it was read from memory
+    pub instruction: Instruction,
+    /// Resolved operands and values
+    pub execution: Execution,
+}
+
+/// Receives the records of executed instructions.
+pub trait TraceSink {
+    /// Called after every successfully executed instruction.
+    fn record(&mut self, record: &TraceRecord);
+}
+
+impl TraceSink for Vec<TraceRecord> {
+    fn record(&mut self, record: &TraceRecord) {
+        self.push(*record);
+    }
+}
+
+/// Output format of a `Tracer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One human-readable line per instruction.
+    Text,
+    /// Comma-separated values with a header line.
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TraceFormat, String> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "csv" => Ok(TraceFormat::Csv),
+            "json" | "jsonl" => Ok(TraceFormat::JsonLines),
+            _ => Err(format!("Unknown trace format '{}'", s)),
+        }
+    }
+}
+
+/// Selects the instructions to trace by their address.
+///
+/// An empty filter matches all instructions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    ranges: Vec<Range<usize>>,
+}
+
+impl TraceFilter {
+    /// Creates a filter that matches all instructions.
+    pub fn new() -> TraceFilter {
+        TraceFilter { ranges: Vec::new() }
+    }
+
+    /// Adds an address range to trace.
+    pub fn add_range(&mut self, range: Range<usize>) {
+        self.ranges.push(range);
+    }
+
+    /// Adds the region of all mounts with the given name (see `MappedMemory::mount_named()`).
+    ///
+    /// Returns `false` if there is no such mount.
+    pub fn add_mount(&mut self, memory: &MappedMemory, name: &str) -> bool {
+        let mut found = false;
+        for mount in memory.mounts().into_iter().filter(|mount| mount.name == name) {
+            self.add_range(mount.start_address..mount.start_address + mount.size);
+            found = true;
+        }
+        found
+    }
+
+    /// Checks if the instruction at `addr` should be traced.
+    pub fn matches(&self, addr: usize) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&addr))
+    }
+}
+
+/// A `TraceSink` that writes records to a `Write`.
+///
+/// As tracing happens during `Emulator::step()`, write errors can’t be reported immediately. The first error stops the tracer and can be retrieved using `error()`.
+pub struct Tracer {
+    output: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    symbols: Symbols,
+    header_written: bool,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>, format: TraceFormat, filter: TraceFilter) -> Tracer {
+        Tracer {
+            output,
+            format,
+            filter,
+            symbols: Symbols::new(),
+            header_written: false,
+            error: None,
+        }
+    }
+
+    /// Sets the symbols used for addresses in the `TraceFormat::Text` format.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Returns the first write error, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Flushes the output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.output, "{}", Text(record, &self.symbols)),
+            TraceFormat::Csv => {
+                if !self.header_written {
+                    writeln!(
+                        self.output,
+                        "cycle,ip,op_a,op_b,target,jmp,addr_a,addr_b,addr_target,addr_jmp,a,b,result,jumped"
+                    )?;
+                    self.header_written = true;
+                }
+                let i = &record.instruction;
+                let e = &record.execution;
+                writeln!(
+                    self.output,
+                    "{},0x{:08X},0x{:08X},0x{:08X},0x{:08X},0x{:08X},0x{:08X},0x{:08X},0x{:08X},0x{:08X},{},{},{},{}",
+                    record.cycle,
+                    record.ip,
+                    i.op_a,
+                    i.op_b,
+                    i.target,
+                    i.jmp,
+                    e.op_a,
+                    e.op_b,
+                    e.target,
+                    e.jmp,
+                    e.a,
+                    e.b,
+                    e.result,
+                    e.jumped
+                )
+            }
+            TraceFormat::JsonLines => {
+                let i = &record.instruction;
+                let e = &record.execution;
+                writeln!(
+                    self.output,
+                    "{{\"cycle\":{},\"ip\":{},\"instruction\":[{},{},{},{}],\"op_a\":{},\"op_b\":{},\"target\":{},\"jmp\":{},\"a\":{},\"b\":{},\"result\":{},\"jumped\":{}}}",
+                    record.cycle,
+                    record.ip,
+                    i.op_a,
+                    i.op_b,
+                    i.target,
+                    i.jmp,
+                    e.op_a,
+                    e.op_b,
+                    e.target,
+                    e.jmp,
+                    e.a,
+                    e.b,
+                    e.result,
+                    e.jumped
+                )
+            }
+        }
+    }
+}
+
+impl TraceSink for Tracer {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() || !self.filter.matches(record.ip) {
+            return;
+        }
+        if let Err(err) = self.write(record) {
+            self.error = Some(err);
+        }
+    }
+}
+
+/// Formats a record in the `TraceFormat::Text` format.
+struct Text<'a>(&'a TraceRecord, &'a Symbols);
+
+impl<'a> fmt::Display for Text<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let i = &self.0.instruction;
+        let e = &self.0.execution;
+        let location = match self.1.lookup(self.0.ip) {
+            Some(_) => format!("0x{:08X} <{}>", self.0.ip, self.1.format(self.0.ip)),
+            None => format!("0x{:08X}", self.0.ip),
+        };
+        write!(
+            f,
+            "{:8} {}: {} {} {} {} | {}=0x{:08X} - {}=0x{:08X} -> {}=0x{:08X} | ",
+            self.0.cycle,
+            location,
+            self.1.format_operand(i.op_a),
+            self.1.format_operand(i.op_b),
+            self.1.format_operand(i.target),
+            self.1.format_operand(i.jmp),
+            self.1.format(e.op_a),
+            e.a,
+            self.1.format(e.op_b),
+            e.b,
+            self.1.format(e.target),
+            e.result
+        )?;
+        if e.jumped {
+            write!(f, "jump {}", self.1.format(e.jmp))
+        } else {
+            write!(f, "next")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceFilter, TraceFormat, TraceRecord, TraceSink, Tracer};
+    use instruction::{Execution, Instruction};
+    use memory::{MappedMemory, SliceMemory};
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use symbols::Symbols;
+
+    #[derive(Clone)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(ip: usize) -> TraceRecord {
+        TraceRecord {
+            cycle: 3,
+            ip,
+            instruction: Instruction {
+                op_a: -16,
+                op_b: 17,
+                target: 0x7FFFFFF9,
+                jmp: 0,
+            },
+            execution: Execution {
+                op_a: 20,
+                op_b: 17,
+                target: 0x7FFFFFF9,
+                jmp: 0,
+                a: 1,
+                b: 2,
+                result: -1,
+                jumped: true,
+            },
+        }
+    }
+
+    fn trace(format: TraceFormat, filter: TraceFilter) -> String {
+        let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), format, filter);
+        tracer.record(&record(4));
+        tracer.record(&record(8));
+        let output = buffer.0.borrow().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn text() {
+        let output = trace(TraceFormat::Text, TraceFilter::new());
+        assert_eq!(
+            output.lines().next().unwrap(),
+            "       3 0x00000004: *0x00000010 0x00000011 r0 0x00000000 | 0x00000014=0x00000001 - 0x00000011=0x00000002 -> r0=0xFFFFFFFF | jump 0x00000000"
+        );
+        assert_eq!(output.lines().count(), 2);
+    }
+
+    #[test]
+    fn text_with_symbols() {
+        let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+        let mut tracer = Tracer::new(
+            Box::new(buffer.clone()),
+            TraceFormat::Text,
+            TraceFilter::new(),
+        );
+        let mut symbols = Symbols::new();
+        symbols.add_label(0, "start");
+        symbols.add_label(0x10, "data");
+        tracer.set_symbols(symbols);
+        tracer.record(&record(4));
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        assert_eq!(
+            output,
+            "       3 0x00000004 <start+4>: *data data+1 r0 start | data+4=0x00000001 - data+1=0x00000002 -> r0=0xFFFFFFFF | jump start\n"
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let output = trace(TraceFormat::Csv, TraceFilter::new());
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("cycle,ip,"));
+        assert_eq!(
+            lines[1],
+            "3,0x00000004,0xFFFFFFF0,0x00000011,0x7FFFFFF9,0x00000000,0x00000014,0x00000011,0x7FFFFFF9,0x00000000,1,2,-1,true"
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let output = trace(TraceFormat::JsonLines, TraceFilter::new());
+        assert_eq!(
+            output.lines().next().unwrap(),
+            "{\"cycle\":3,\"ip\":4,\"instruction\":[-16,17,2147483641,0],\"op_a\":20,\"op_b\":17,\"target\":2147483641,\"jmp\":0,\"a\":1,\"b\":2,\"result\":-1,\"jumped\":true}"
+        );
+    }
+
+    #[test]
+    fn filter() {
+        let mut filter = TraceFilter::new();
+        filter.add_range(8..12);
+        let output = trace(TraceFormat::Text, filter);
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.contains("0x00000008:"));
+
+        let mut mm = MappedMemory::new();
+        mm.mount_named(4, "code", Box::new(SliceMemory::new(4))).unwrap();
+        let mut filter = TraceFilter::new();
+        assert!(!filter.add_mount(&mm, "data"));
+        assert!(filter.add_mount(&mm, "code"));
+        assert!(filter.matches(4));
+        assert!(!filter.matches(8));
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!("csv".parse(), Ok(TraceFormat::Csv));
+        assert_eq!("json".parse(), Ok(TraceFormat::JsonLines));
+        assert!("xml".parse::<TraceFormat>().is_err());
+    }
+}
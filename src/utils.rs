@@ -1,8 +1,19 @@
 //! Various utils to work with osci.
 use std::fs::File;
+use std::io::Read;
 use std::path::Path;
-use memory::Memory;
+use memory::{Memory, SliceMemory};
 use loader::{hexloader, rawloader, LoadError, Result};
+use assembler;
+use symbols::{self, Symbols};
+
+/// A loaded image and its debug symbols.
+pub struct Image {
+    /// The contents of the image
+    pub memory: Box<dyn Memory>,
+    /// Symbols of the image, if available
+    pub symbols: Option<Symbols>,
+}
 
 /// Turn a file into a memory.
 ///
@@ -10,19 +21,122 @@ use loader::{hexloader, rawloader, LoadError, Result};
 ///
 /// - “.raw”, “.bin”, “.img” or no extension: `rawloader`
 /// - “.hex”: `hexloader`
-pub fn load_file(filename: &Path) -> Result<Box<Memory>> {
+/// - “.asm”: `assembler`
+///
+/// Raw files are read with the default `rawloader::Options`. Use `load_file_with_options()` to change them and `load_image()` to get the symbols of the image as well.
+pub fn load_file(filename: &Path) -> Result<Box<dyn Memory>> {
+    load_file_with_options(filename, &rawloader::Options::default())
+}
+
+/// Turns a file into a memory like `load_file()`, reading raw files with the given options.
+pub fn load_file_with_options(
+    filename: &Path,
+    options: &rawloader::Options,
+) -> Result<Box<dyn Memory>> {
+    load_image_with_options(filename, options).map(|image| image.memory)
+}
+
+/// Turns a file into a memory like `load_file()` and loads its symbols.
+///
+/// Symbols of “.asm” files are taken from the assembler. For other formats, the symbol file next to the image is loaded if it exists (see `symbols::sidecar_path()`).
+pub fn load_image(filename: &Path) -> Result<Image> {
+    load_image_with_options(filename, &rawloader::Options::default())
+}
+
+/// Loads an image like `load_image()`, reading raw files with the given options.
+pub fn load_image_with_options(filename: &Path, options: &rawloader::Options) -> Result<Image> {
     let mut file = File::open(filename)?;
-    match Path::new(filename).extension().and_then(|ext| ext.to_str()) {
-        Some("img") | Some("bin") | Some("raw") | None => rawloader::load_with_seek(&mut file),
-        Some("hex") => hexloader::load(&mut file),
-        ext => Err(LoadError::from_message(format!(
-            "Unknown file extension: {:?}",
-            ext
-        ))),
+    let memory = match filename.extension().and_then(|ext| ext.to_str()) {
+        Some("img") | Some("bin") | Some("raw") | None => {
+            rawloader::load_with_seek_and_options(&mut file, options)?
+        }
+        Some("hex") => hexloader::load(&mut file)?,
+        Some("asm") => {
+            let mut source = String::new();
+            file.read_to_string(&mut source)?;
+            let program = assembler::assemble(&source)?;
+            let symbols = Symbols::from_program(&program, filename.to_str());
+            return Ok(Image {
+                memory: Box::new(SliceMemory::from_slice(program.words.into_boxed_slice())),
+                symbols: Some(symbols),
+            });
+        }
+        ext => {
+            return Err(LoadError::from_message(format!(
+                "Unknown file extension: {:?}",
+                ext
+            )))
+        }
+    };
+    Ok(Image {
+        memory,
+        symbols: read_sidecar(filename)?,
+    })
+}
+
+/// Loads only the symbols of an image as `load_image()` would.
+pub fn load_symbols(filename: &Path) -> Result<Option<Symbols>> {
+    if filename.extension().and_then(|ext| ext.to_str()) != Some("asm") {
+        return read_sidecar(filename);
     }
+    let mut source = String::new();
+    File::open(filename)?.read_to_string(&mut source)?;
+    let program = assembler::assemble(&source)?;
+    Ok(Some(Symbols::from_program(&program, filename.to_str())))
+}
+
+fn read_sidecar(image: &Path) -> Result<Option<Symbols>> {
+    let path = symbols::sidecar_path(image);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Symbols::read(&mut File::open(path)?).map(Some)
 }
 
 /// List of formats supported by `load_file`.
 ///
 /// The list contains file extensions that are recognized by `load_file`.
-pub static SUPPORTED_FORMATS: [&str; 4] = ["img", "bin", "raw", "hex"];
+pub static SUPPORTED_FORMATS: [&str; 5] = ["img", "bin", "raw", "hex", "asm"];
+
+#[cfg(test)]
+mod tests {
+    use loader::rawloader;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn load_image() {
+        let dir = env::temp_dir().join(format!("osci-utils-test-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("loop.hex");
+        fs::write(&image, "0 0 0 40000000").unwrap();
+        let loaded = super::load_image(&image).unwrap();
+        assert_eq!(loaded.memory.get(3), 0x40000000);
+        assert!(loaded.symbols.is_none());
+
+        fs::write(dir.join("loop.sym"), "label 40000000 loop").unwrap();
+        let loaded = super::load_image(&image).unwrap();
+        assert_eq!(loaded.symbols.unwrap().format(0x40000002), "loop+2");
+
+        let source = dir.join("loop.asm");
+        fs::write(&source, ".addr 4\nloop: 0 0 0 loop").unwrap();
+        let symbols = super::load_symbols(&source).unwrap().unwrap();
+        assert_eq!(symbols.address_of("loop"), Some(4));
+        assert_eq!(symbols.source(), source.to_str());
+
+        let raw = dir.join("loop.bin");
+        fs::write(&raw, [0xFF, 4, 3, 2, 1, 7]).unwrap();
+        let options = rawloader::Options {
+            endianness: rawloader::Endianness::Little,
+            partial_word: rawloader::PartialWord::Pad,
+            header: 1,
+        };
+        let memory = super::load_file_with_options(&raw, &options).unwrap();
+        assert_eq!((memory.get(0), memory.get(1)), (0x01020304, 7));
+        assert_eq!(super::load_file(&raw).unwrap().get(0), 0xFF040302_u32 as i32);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(super::load_symbols(Path::new("missing.hex")).unwrap().is_none());
+    }
+}
@@ -1,11 +1,10 @@
 extern crate osciemu;
 
 use std::{error, fmt, fs, io, num};
-use std::error::Error;
 use std::io::BufRead;
 use std::path::{Path, PathBuf};
 
-use osciemu::emulator::Emulator;
+use osciemu::emulator::{Emulator, RunLimits, StopReason};
 use osciemu::memory::{Memory, SliceMemory};
 use osciemu::utils;
 
@@ -35,34 +34,29 @@ fn run_example(path: &Path) {
                 .unwrap()
                 .contains(".expect.")
         })
-        .expect(&format!("No expect file found for {:?}", path));
+        .unwrap_or_else(|| panic!("No expect file found for {:?}", path));
 
-    let expect =
-        parse_expect(&expect_file).expect(&format!("Could not parse expect file for {:?}", path));
+    let expect = parse_expect(expect_file)
+        .unwrap_or_else(|_| panic!("Could not parse expect file for {:?}", path));
 
-    let bios = files
-        .iter()
-        .find(|path| path.as_os_str().to_str().unwrap().contains(".bios."))
+    let bios = find_image(&files, ".bios.")
         .map(|path| utils::load_file(path).unwrap())
         .unwrap();
 
-    let memory = files
-        .iter()
-        .find(|path| path.as_os_str().to_str().unwrap().contains(".memory."))
+    let memory = find_image(&files, ".memory.")
         .map(|path| utils::load_file(path).unwrap())
         .unwrap_or_else(|| Box::new(SliceMemory::new(0)));
 
     let mut emu = Emulator::new(bios, memory);
 
-    let max = 100;
-    for i in 1..max {
-        if emu.is_halted() {
-            break;
-        }
-        emu.step();
-        if i == max - 1 {
-            panic!("Test never halted");
-        }
+    let outcome = emu.run(RunLimits {
+        max_cycles: Some(100),
+        ..RunLimits::default()
+    });
+    match outcome.reason {
+        StopReason::Halted => {}
+        StopReason::CycleLimit => panic!("Test never halted"),
+        reason => panic!("{:?} stopped: {}", path, reason),
     }
 
     for (addr, value) in expect {
@@ -70,6 +64,16 @@ fn run_example(path: &Path) {
     }
 }
 
+/// Finds the file whose name contains `kind`.
+///
+/// Assembly sources are only used if there is no image, as examples may keep the source an image has been built from next to it.
+fn find_image<'a>(files: &'a [PathBuf], kind: &str) -> Option<&'a PathBuf> {
+    files
+        .iter()
+        .filter(|path| path.as_os_str().to_str().unwrap().contains(kind))
+        .min_by_key(|path| path.extension().is_some_and(|ext| ext == "asm"))
+}
+
 #[derive(Debug)]
 enum ExpectError {
     IoErr(io::Error),
@@ -78,19 +82,15 @@ enum ExpectError {
 
 impl fmt::Display for ExpectError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description())
-    }
-}
-
-impl error::Error for ExpectError {
-    fn description(&self) -> &str {
-        match self {
-            &ExpectError::IoErr(ref err) => err.description(),
-            &ExpectError::ParseIntErr(ref err) => err.description(),
+        match *self {
+            ExpectError::IoErr(ref err) => write!(f, "{}", err),
+            ExpectError::ParseIntErr(ref err) => write!(f, "{}", err),
         }
     }
 }
 
+impl error::Error for ExpectError {}
+
 impl From<io::Error> for ExpectError {
     fn from(err: io::Error) -> Self {
         ExpectError::IoErr(err)
@@ -111,7 +111,7 @@ fn parse_expect(file: &PathBuf) -> Result<Vec<(usize, i32)>, ExpectError> {
             .split("=")
             .map(|item| String::from(item.trim()))
             .collect();
-        let addr = usize::from_str_radix(items.get(0).unwrap(), 16)?;
+        let addr = usize::from_str_radix(items.first().unwrap(), 16)?;
         let value = i32::from_str_radix(items.get(1).unwrap(), 16)?;
         v.push((addr, value))
     }